@@ -0,0 +1,90 @@
+//! Clipboard access.
+use crate::{
+    bindings::Windows::Win32::{
+        DataExchange::{
+            CloseClipboard, EmptyClipboard, GetClipboardData, IsClipboardFormatAvailable,
+            OpenClipboard, SetClipboardData,
+        },
+        Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GLOBAL_ALLOC_FLAGS},
+        SystemServices::{CLIPBOARD_FORMATS, HANDLE},
+        WindowsAndMessaging::HWND,
+    },
+    error::Result,
+    platform::Platform,
+};
+use std::ptr;
+
+/// Closes the clipboard when dropped.
+struct ClipboardGuard;
+
+impl ClipboardGuard {
+    fn open() -> Result<ClipboardGuard> {
+        unsafe {
+            if !OpenClipboard(HWND(0)).as_bool() {
+                return Err(windows::Error::from(windows::HRESULT::from_thread()).into());
+            }
+        }
+        Ok(ClipboardGuard)
+    }
+}
+
+impl Drop for ClipboardGuard {
+    fn drop(&mut self) {
+        unsafe {
+            CloseClipboard();
+        }
+    }
+}
+
+impl Platform {
+    /// Replaces the contents of the clipboard with the specified text.
+    pub fn set_clipboard_text(&self, text: &str) -> Result<()> {
+        let wtext: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+        let _clipboard = ClipboardGuard::open()?;
+
+        unsafe {
+            EmptyClipboard();
+            let size = wtext.len() * std::mem::size_of::<u16>();
+            let hmem = GlobalAlloc(GLOBAL_ALLOC_FLAGS::GMEM_MOVEABLE, size);
+            if hmem == 0 {
+                return Err(windows::Error::from(windows::HRESULT::from_thread()).into());
+            }
+            let dst = GlobalLock(hmem) as *mut u16;
+            ptr::copy_nonoverlapping(wtext.as_ptr(), dst, wtext.len());
+            GlobalUnlock(hmem);
+
+            // on success, the system owns the memory
+            if SetClipboardData(CLIPBOARD_FORMATS::CF_UNICODETEXT.0, HANDLE(hmem)).0 == 0 {
+                let err = windows::Error::from(windows::HRESULT::from_thread());
+                GlobalFree(hmem);
+                return Err(err.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the text in the clipboard, if any.
+    pub fn clipboard_text(&self) -> Result<Option<String>> {
+        unsafe {
+            if !IsClipboardFormatAvailable(CLIPBOARD_FORMATS::CF_UNICODETEXT.0).as_bool() {
+                return Ok(None);
+            }
+            let _clipboard = ClipboardGuard::open()?;
+            let handle = GetClipboardData(CLIPBOARD_FORMATS::CF_UNICODETEXT.0);
+            if handle.0 == 0 {
+                return Ok(None);
+            }
+            let src = GlobalLock(handle.0) as *const u16;
+            if src.is_null() {
+                return Ok(None);
+            }
+            let mut len = 0;
+            while *src.add(len) != 0 {
+                len += 1;
+            }
+            let text = String::from_utf16_lossy(std::slice::from_raw_parts(src, len));
+            GlobalUnlock(handle.0);
+            Ok(Some(text))
+        }
+    }
+}
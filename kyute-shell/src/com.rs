@@ -0,0 +1,166 @@
+//! Implementation of COM interfaces by Rust objects.
+//!
+//! Some APIs (e.g. DirectWrite inline objects and text renderers) take callbacks in the form of COM
+//! interfaces. [`ComBox`] wraps a Rust object in a reference-counted COM object: it implements
+//! `IUnknown`, and the object provides the remaining methods of its interface by implementing
+//! [`ComObject`].
+use std::{
+    os::raw::c_void,
+    ptr,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+use windows::{Guid, IUnknown, Interface, HRESULT};
+
+pub(crate) const S_OK: HRESULT = HRESULT(0);
+pub(crate) const E_NOINTERFACE: HRESULT = HRESULT(0x80004002);
+pub(crate) const E_POINTER: HRESULT = HRESULT(0x80004003);
+
+/// Virtual table of a COM interface: the `IUnknown` methods followed by the methods specific to
+/// the interface.
+#[repr(C)]
+pub(crate) struct ComVtbl<M> {
+    query_interface:
+        unsafe extern "system" fn(this: *mut c_void, iid: &Guid, out: *mut *mut c_void) -> HRESULT,
+    add_ref: unsafe extern "system" fn(this: *mut c_void) -> u32,
+    release: unsafe extern "system" fn(this: *mut c_void) -> u32,
+    methods: M,
+}
+
+impl<M> ComVtbl<M> {
+    /// Creates the virtual table of `T`, from the methods specific to its interface.
+    pub(crate) const fn new<T: ComObject<Methods = M>>(methods: M) -> ComVtbl<M> {
+        ComVtbl {
+            query_interface: ComBox::<T>::query_interface,
+            add_ref: ComBox::<T>::add_ref,
+            release: ComBox::<T>::release,
+            methods,
+        }
+    }
+}
+
+/// A Rust object implementing a COM interface.
+///
+/// # Safety
+///
+/// `Methods` must be a `#[repr(C)]` struct of function pointers with the layout of the virtual
+/// table of `Interface`, minus the `IUnknown` methods. The methods receive a pointer to the
+/// [`ComBox`] as their `this` argument.
+pub(crate) unsafe trait ComObject: Sized + 'static {
+    /// The implemented interface.
+    type Interface: Interface;
+    /// Methods of the interface that come after the `IUnknown` methods.
+    type Methods: 'static;
+    /// Returns the virtual table of the object, usually a `static` created with
+    /// [`ComVtbl::new`].
+    fn vtbl() -> &'static ComVtbl<Self::Methods>;
+}
+
+/// Reference-counted COM object wrapping a `T`.
+#[repr(C)]
+pub(crate) struct ComBox<T: ComObject> {
+    // must be the first field
+    vtbl: &'static ComVtbl<T::Methods>,
+    ref_count: AtomicU32,
+    object: T,
+}
+
+impl<T: ComObject> ComBox<T> {
+    /// Wraps an object in a new COM object, and returns its interface.
+    pub(crate) fn create(object: T) -> T::Interface {
+        let com_box = Box::new(ComBox {
+            vtbl: T::vtbl(),
+            ref_count: AtomicU32::new(1),
+            object,
+        });
+        let ptr = Box::into_raw(com_box);
+        // SAFETY: interfaces are non-null pointers to an object starting with a vtable pointer,
+        // which `ComBox` is, and the returned interface takes ownership of the initial reference.
+        unsafe { std::mem::transmute_copy::<*mut ComBox<T>, T::Interface>(&ptr) }
+    }
+
+    /// Returns the object wrapped by the COM object pointed to by `this`.
+    ///
+    /// # Safety
+    ///
+    /// `this` must point to a live `ComBox<T>`, e.g. the `this` argument of one of its methods.
+    pub(crate) unsafe fn object<'a>(this: *mut c_void) -> &'a T {
+        &(*(this as *const ComBox<T>)).object
+    }
+
+    unsafe extern "system" fn query_interface(
+        this: *mut c_void,
+        iid: &Guid,
+        out: *mut *mut c_void,
+    ) -> HRESULT {
+        if out.is_null() {
+            return E_POINTER;
+        }
+        if *iid == T::Interface::IID || *iid == IUnknown::IID {
+            Self::add_ref(this);
+            *out = this;
+            S_OK
+        } else {
+            *out = ptr::null_mut();
+            E_NOINTERFACE
+        }
+    }
+
+    unsafe extern "system" fn add_ref(this: *mut c_void) -> u32 {
+        let this = &*(this as *const ComBox<T>);
+        this.ref_count.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    unsafe extern "system" fn release(this: *mut c_void) -> u32 {
+        let prev = {
+            let this = &*(this as *const ComBox<T>);
+            this.ref_count.fetch_sub(1, Ordering::Release)
+        };
+        if prev == 1 {
+            // synchronizes with the `Release` decrements of the other references, so that their
+            // uses of the object happen before it is freed
+            fence(Ordering::Acquire);
+            drop(Box::from_raw(this as *mut ComBox<T>));
+        }
+        prev - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ComBox, ComObject, ComVtbl};
+    use crate::bindings::Windows::Win32::DirectWrite::IDWriteInlineObject;
+    use std::{cell::Cell, rc::Rc};
+    use windows::IUnknown;
+
+    /// Sets the flag when dropped.
+    struct DropFlag(Rc<Cell<bool>>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    static DROP_FLAG_VTBL: ComVtbl<()> = ComVtbl::new::<DropFlag>(());
+
+    unsafe impl ComObject for DropFlag {
+        type Interface = IUnknown;
+        type Methods = ();
+
+        fn vtbl() -> &'static ComVtbl<()> {
+            &DROP_FLAG_VTBL
+        }
+    }
+
+    #[test]
+    fn test_com_box_ref_count() {
+        let dropped = Rc::new(Cell::new(false));
+        let unknown = ComBox::create(DropFlag(dropped.clone()));
+        assert!(unknown.cast::<IDWriteInlineObject>().is_err());
+        let other = unknown.cast::<IUnknown>().unwrap();
+        drop(unknown);
+        assert!(!dropped.get());
+        drop(other);
+        assert!(dropped.get());
+    }
+}
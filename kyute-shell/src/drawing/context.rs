@@ -4,12 +4,13 @@ use crate::{
         brush::Brush, mk_color_f, mk_matrix_3x2, mk_point_f, mk_rect_f, Color, PathGeometry, Point,
         Rect, Transform,
     },
-    text::TextLayout,
+    text::{GlyphRun, TextLayout},
 };
 use bitflags::bitflags;
 use std::mem::MaybeUninit;
 use tracing::error;
 use crate::bindings::Windows::Win32::Direct2D::{ID2D1Bitmap1, ID2D1DeviceContext, ID2D1DrawingStateBlock, ID2D1Geometry, ID2D1Image, D2D1_ANTIALIAS_MODE, D2D1_COMPOSITE_MODE, D2D1_DRAWING_STATE_DESCRIPTION, D2D1_DRAW_TEXT_OPTIONS, D2D1_INTERPOLATION_MODE, D2D1_ROUNDED_RECT, D2D1_TEXT_ANTIALIAS_MODE, ID2D1Factory1};
+use crate::bindings::Windows::Win32::DirectWrite::DWRITE_MEASURING_MODE;
 use windows::Interface;
 use std::sync::MutexGuard;
 use crate::platform::D2D1DeviceContext;
@@ -296,6 +297,21 @@ impl<'a> DrawContext<'a> {
         }
     }
 
+    /// Draws a glyph run, with its baseline origin offset by `origin`.
+    pub fn draw_glyph_run(&mut self, origin: Point, glyph_run: &GlyphRun, brush: &Brush) {
+        let baseline_origin = origin + glyph_run.baseline_origin.to_vector();
+        let dwrite_glyph_run = glyph_run.to_dwrite();
+        unsafe {
+            self.ctx.DrawGlyphRun(
+                mk_point_f(baseline_origin),
+                &dwrite_glyph_run,
+                None,
+                brush.to_base_brush(),
+                DWRITE_MEASURING_MODE::DWRITE_MEASURING_MODE_NATURAL,
+            );
+        }
+    }
+
     pub fn draw_rectangle(&mut self, rect: Rect, brush: &Brush, width: f64) {
         unsafe {
             self.ctx
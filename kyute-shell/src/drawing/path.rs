@@ -1,5 +1,6 @@
 use crate::{
     bindings::Windows::Win32::Direct2D::ID2D1PathGeometry1,
+    drawing::{mk_point_f, Point},
     platform::Platform,
 };
 pub use svgtypes::{Path, PathParser, PathSegment};
@@ -18,6 +19,44 @@ pub enum PathError {
 }
 
 impl PathGeometry {
+    /// Creates an open path made of line segments joining the specified points.
+    pub fn polyline(points: &[Point]) -> Result<PathGeometry, PathError> {
+        let platform = Platform::instance();
+
+        unsafe {
+            let factory = &platform.d2d_factory;
+            let mut path_geometry = None;
+            let path_geometry = factory
+                .CreatePathGeometry(&mut path_geometry)
+                .and_some(path_geometry)
+                .map_err(crate::error::Error::from)?
+                .cast::<ID2D1PathGeometry1>()
+                .map_err(crate::error::Error::from)?;
+            let mut geometry_sink = None;
+            let geometry_sink = path_geometry
+                .Open(&mut geometry_sink)
+                .and_some(geometry_sink)
+                .map_err(crate::error::Error::from)?;
+
+            if let Some((first, rest)) = points.split_first() {
+                geometry_sink.BeginFigure(
+                    mk_point_f(*first),
+                    D2D1_FIGURE_BEGIN::D2D1_FIGURE_BEGIN_HOLLOW,
+                );
+                for p in rest {
+                    geometry_sink.AddLine(mk_point_f(*p));
+                }
+                geometry_sink.EndFigure(D2D1_FIGURE_END::D2D1_FIGURE_END_OPEN);
+            }
+
+            geometry_sink
+                .Close()
+                .ok()
+                .map_err(crate::error::Error::from)?;
+            Ok(PathGeometry(path_geometry))
+        }
+    }
+
     pub fn try_from_svg_path(path_str: &str) -> Result<PathGeometry, PathError> {
         let platform = Platform::instance();
 
@@ -1,4 +1,6 @@
 //! Windowing and drawing base for kyute.
+mod clipboard;
+mod com;
 pub mod drawing;
 pub mod error;
 pub mod imaging;
@@ -0,0 +1,310 @@
+//! Cache of text layouts.
+use crate::{
+    drawing::Size,
+    error::Result,
+    text::{TextFormat, TextFormatDesc, TextLayout},
+};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+/// Identifies a text layout in the cache.
+#[derive(Clone, Debug)]
+struct TextLayoutKey {
+    text: String,
+    format: TextFormatDesc,
+    /// Bit patterns of the layout box width and height.
+    max_size: (u64, u64),
+    /// Bit pattern of the scale factor.
+    scale_factor: u64,
+}
+
+/// Borrowed version of [`TextLayoutKey`], used for lookups so that hits don't allocate.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+struct TextLayoutKeyRef<'a> {
+    text: &'a str,
+    format: &'a TextFormatDesc,
+    max_size: (u64, u64),
+    scale_factor: u64,
+}
+
+/// Common view of owned and borrowed keys. `TextLayoutKey` borrows as `dyn KeyView`, which
+/// lets the map be queried with a `TextLayoutKeyRef`.
+trait KeyView {
+    fn key_ref(&self) -> TextLayoutKeyRef<'_>;
+}
+
+impl KeyView for TextLayoutKey {
+    fn key_ref(&self) -> TextLayoutKeyRef<'_> {
+        TextLayoutKeyRef {
+            text: &self.text,
+            format: &self.format,
+            max_size: self.max_size,
+            scale_factor: self.scale_factor,
+        }
+    }
+}
+
+impl<'a> KeyView for TextLayoutKeyRef<'a> {
+    fn key_ref(&self) -> TextLayoutKeyRef<'_> {
+        *self
+    }
+}
+
+impl<'a> Borrow<dyn KeyView + 'a> for TextLayoutKey {
+    fn borrow(&self) -> &(dyn KeyView + 'a) {
+        self
+    }
+}
+
+// owned keys must hash and compare like their borrowed version
+impl Hash for dyn KeyView + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key_ref().hash(state)
+    }
+}
+
+impl PartialEq for dyn KeyView + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.key_ref() == other.key_ref()
+    }
+}
+
+impl Eq for dyn KeyView + '_ {}
+
+impl Hash for TextLayoutKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key_ref().hash(state)
+    }
+}
+
+impl PartialEq for TextLayoutKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key_ref() == other.key_ref()
+    }
+}
+
+impl Eq for TextLayoutKey {}
+
+/// Hit/miss statistics of a [`TextLayoutCache`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct TextLayoutCacheStats {
+    /// Number of lookups that returned an existing layout.
+    pub hits: u64,
+    /// Number of lookups that had to create a new layout.
+    pub misses: u64,
+    /// Number of layouts evicted because the cache was full.
+    pub evictions: u64,
+    /// Number of times the cache was invalidated.
+    pub invalidations: u64,
+}
+
+/// A map that evicts the least recently used entry when it's full.
+struct Lru<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by last access time.
+    order: BTreeMap<u64, K>,
+    /// Access counter.
+    tick: u64,
+    capacity: usize,
+}
+
+impl<K: Clone + Eq + Hash, V> Lru<K, V> {
+    fn new(capacity: usize) -> Lru<K, V> {
+        assert!(capacity > 0);
+        Lru {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            capacity,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns the entry for the specified key and marks it as the most recently used.
+    fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let tick = self.tick;
+        let (value, last_used) = self.entries.get_mut(key)?;
+        let key = self.order.remove(last_used).unwrap();
+        *last_used = tick;
+        self.order.insert(tick, key);
+        self.tick += 1;
+        Some(value)
+    }
+
+    /// Inserts a new entry. Returns whether another entry was evicted to make room for it.
+    fn insert(&mut self, key: K, value: V) -> bool {
+        let mut evicted = false;
+        if let Some((_, last_used)) = self.entries.remove(&key) {
+            self.order.remove(&last_used);
+        } else if self.entries.len() >= self.capacity {
+            // evict the least recently used entry
+            let oldest = *self.order.keys().next().unwrap();
+            let oldest_key = self.order.remove(&oldest).unwrap();
+            self.entries.remove(&oldest_key);
+            evicted = true;
+        }
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, (value, self.tick));
+        self.tick += 1;
+        evicted
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// A cache of text layouts, keyed on the text, its format, the size of the layout box and
+/// the scale factor.
+///
+/// Layouts are shared between all users of the same key, and the least recently used layouts are
+/// evicted once the cache is full. Since layouts are shared, they must not be modified
+/// (e.g. with [`TextLayout::set_drawing_effect`]); widgets that need to modify a layout
+/// should create their own.
+pub struct TextLayoutCache {
+    layouts: Lru<TextLayoutKey, Rc<TextLayout>>,
+    stats: TextLayoutCacheStats,
+}
+
+impl TextLayoutCache {
+    /// Creates a new cache that can hold up to `capacity` layouts.
+    pub fn new(capacity: usize) -> TextLayoutCache {
+        TextLayoutCache {
+            layouts: Lru::new(capacity),
+            stats: Default::default(),
+        }
+    }
+
+    /// Returns a layout of the given text, creating it if necessary.
+    pub fn get_or_create(
+        &mut self,
+        text: &str,
+        format: &TextFormat,
+        max_size: Size,
+        scale_factor: f64,
+    ) -> Result<Rc<TextLayout>> {
+        let key = TextLayoutKeyRef {
+            text,
+            format: format.desc(),
+            max_size: (max_size.width.to_bits(), max_size.height.to_bits()),
+            scale_factor: scale_factor.to_bits(),
+        };
+
+        if let Some(layout) = self.layouts.get(&key as &dyn KeyView) {
+            self.stats.hits += 1;
+            return Ok(layout.clone());
+        }
+
+        self.stats.misses += 1;
+        let layout = Rc::new(TextLayout::with_scale_factor(
+            text,
+            format,
+            max_size,
+            scale_factor,
+        )?);
+        let key = TextLayoutKey {
+            text: text.to_owned(),
+            format: format.desc().clone(),
+            max_size: key.max_size,
+            scale_factor: key.scale_factor,
+        };
+        if self.layouts.insert(key, layout.clone()) {
+            self.stats.evictions += 1;
+        }
+        Ok(layout)
+    }
+
+    /// Removes all layouts from the cache.
+    ///
+    /// This should be called when the set of installed fonts changes, since existing layouts may
+    /// refer to fonts that have been removed, or use fallback fonts where a better match exists.
+    pub fn invalidate(&mut self) {
+        self.layouts.clear();
+        self.stats.invalidations += 1;
+    }
+
+    /// Returns the number of layouts in the cache.
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    /// Returns hit/miss statistics.
+    pub fn stats(&self) -> TextLayoutCacheStats {
+        self.stats
+    }
+
+    /// Resets the hit/miss statistics.
+    pub fn reset_stats(&mut self) {
+        self.stats = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyView, Lru, TextLayoutKey, TextLayoutKeyRef};
+    use crate::text::{FontStretch, FontStyle, FontWeight, ReadingDirection, TextFormatDesc};
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut lru = Lru::new(2);
+        assert!(!lru.insert(1, "a"));
+        assert!(!lru.insert(2, "b"));
+        // 1 is now the most recently used
+        assert_eq!(lru.get(&1), Some(&"a"));
+        // evicts 2
+        assert!(lru.insert(3, "c"));
+        assert_eq!(lru.get(&2), None);
+        assert_eq!(lru.get(&1), Some(&"a"));
+        assert_eq!(lru.get(&3), Some(&"c"));
+        assert_eq!(lru.len(), 2);
+        // replacing an entry doesn't evict anything
+        assert!(!lru.insert(3, "d"));
+        assert_eq!(lru.get(&3), Some(&"d"));
+        lru.clear();
+        assert_eq!(lru.len(), 0);
+    }
+
+    #[test]
+    fn test_borrowed_key_lookup() {
+        let format = TextFormatDesc {
+            family: "Segoe UI".to_string(),
+            weight: FontWeight::Normal,
+            style: FontStyle::Normal,
+            stretch: FontStretch::Normal,
+            size: 12.0,
+            reading_direction: ReadingDirection::LeftToRight,
+        };
+        let mut lru = Lru::new(2);
+        lru.insert(
+            TextLayoutKey {
+                text: "hello".to_string(),
+                format: format.clone(),
+                max_size: (1, 2),
+                scale_factor: 3,
+            },
+            "a",
+        );
+
+        let mut key = TextLayoutKeyRef {
+            text: "hello",
+            format: &format,
+            max_size: (1, 2),
+            scale_factor: 3,
+        };
+        assert_eq!(lru.get(&key as &dyn KeyView), Some(&"a"));
+        key.text = "hell";
+        assert_eq!(lru.get(&key as &dyn KeyView), None);
+    }
+}
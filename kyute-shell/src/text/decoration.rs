@@ -0,0 +1,189 @@
+//! Text decorations drawn by the application: underlines, squiggles, overlines and highlights.
+use crate::{
+    drawing::{Brush, Color, DrawContext, PathGeometry, Point, Rect, Size},
+    error::Result,
+    text::{FontMetrics, TextLayout},
+};
+use std::ops::Range;
+
+/// Period of squiggly underlines, relative to their thickness.
+const SQUIGGLE_PERIOD: f64 = 4.0;
+
+/// Kind of text decoration.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum TextDecorationKind {
+    /// Straight line below the baseline.
+    Underline,
+    /// Wavy line below the baseline, typically used for spelling errors and diagnostics.
+    SquigglyUnderline,
+    /// Straight line at the top of the line.
+    Overline,
+    /// Background filling the line box behind the text.
+    Highlight,
+}
+
+/// A decoration applied to a range of text.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TextDecoration {
+    pub kind: TextDecorationKind,
+    pub color: Color,
+    /// Thickness of the line, ignored for highlights. If `None`, the underline thickness of the
+    /// font is used.
+    pub thickness: Option<f64>,
+}
+
+impl TextDecoration {
+    /// Creates a new decoration with the underline thickness of the font.
+    pub fn new(kind: TextDecorationKind, color: Color) -> TextDecoration {
+        TextDecoration {
+            kind,
+            color,
+            thickness: None,
+        }
+    }
+
+    /// Sets the thickness of the line.
+    pub fn thickness(mut self, thickness: f64) -> TextDecoration {
+        self.thickness = Some(thickness);
+        self
+    }
+
+    /// Returns whether the decoration should be drawn behind the text.
+    pub fn is_background(&self) -> bool {
+        self.kind == TextDecorationKind::Highlight
+    }
+}
+
+/// The part of a range of text that is on a single line.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LineFragment {
+    /// Bounds of the fragment, spanning the whole height of the line.
+    pub bounds: Rect,
+    /// Vertical position of the baseline of the line.
+    pub baseline: f64,
+}
+
+impl TextLayout {
+    /// Returns the fragments of the specified range of text on each line it spans, offset by
+    /// `origin`.
+    ///
+    /// A range may produce more than one fragment per line in bidirectional text.
+    pub fn line_fragments(&self, range: Range<usize>, origin: &Point) -> Result<Vec<LineFragment>> {
+        let hits = self.hit_test_text_range(range, origin)?;
+
+        // top and baseline of each line
+        let mut lines = Vec::new();
+        let mut top = origin.y;
+        for line in self.line_metrics() {
+            lines.push((top, top + line.baseline, top + line.height));
+            top += line.height;
+        }
+
+        Ok(hits
+            .iter()
+            .map(|hit| {
+                let center = hit.bounds.center().y;
+                let baseline = lines
+                    .iter()
+                    .find(|&&(top, _, bottom)| center >= top && center < bottom)
+                    .map(|&(_, baseline, _)| baseline)
+                    .unwrap_or(hit.bounds.max_y());
+                LineFragment {
+                    bounds: hit.bounds,
+                    baseline,
+                }
+            })
+            .collect())
+    }
+}
+
+/// Returns the points of a zigzag line between `x0` and `x1`, with its top at `y`.
+fn squiggle_points(x0: f64, x1: f64, y: f64, thickness: f64) -> Vec<Point> {
+    let half_period = 0.5 * SQUIGGLE_PERIOD * thickness;
+    let amplitude = half_period;
+    let mut points = Vec::new();
+    let mut x = x0;
+    let mut up = true;
+    while x < x1 {
+        points.push(Point::new(x, if up { y } else { y + amplitude }));
+        x += half_period;
+        up = !up;
+    }
+    points.push(Point::new(x1, if up { y } else { y + amplitude }));
+    points
+}
+
+impl<'a> DrawContext<'a> {
+    /// Draws a decoration on a range of text of a layout drawn at `origin`.
+    ///
+    /// Underlines are placed according to `font_metrics`, which should be the metrics of the font
+    /// of the layout. Backgrounds (see [`TextDecoration::is_background`]) should be drawn before
+    /// the text layout, other decorations after.
+    pub fn draw_text_decoration(
+        &mut self,
+        origin: Point,
+        text_layout: &TextLayout,
+        font_metrics: &FontMetrics,
+        range: Range<usize>,
+        decoration: &TextDecoration,
+    ) -> Result<()> {
+        let fragments = text_layout.line_fragments(range, &origin)?;
+        let brush = Brush::new_solid_color(self, decoration.color);
+        let thickness = decoration
+            .thickness
+            .unwrap_or(font_metrics.underline_thickness);
+
+        for fragment in fragments.iter() {
+            let bounds = fragment.bounds;
+            match decoration.kind {
+                TextDecorationKind::Highlight => {
+                    self.fill_rectangle(bounds, &brush);
+                }
+                TextDecorationKind::Underline => {
+                    let line = Rect::new(
+                        Point::new(
+                            bounds.min_x(),
+                            fragment.baseline + font_metrics.underline_position,
+                        ),
+                        Size::new(bounds.size.width, thickness),
+                    );
+                    self.fill_rectangle(line, &brush);
+                }
+                TextDecorationKind::Overline => {
+                    let line = Rect::new(bounds.origin, Size::new(bounds.size.width, thickness));
+                    self.fill_rectangle(line, &brush);
+                }
+                TextDecorationKind::SquigglyUnderline => {
+                    let points = squiggle_points(
+                        bounds.min_x(),
+                        bounds.max_x(),
+                        fragment.baseline + font_metrics.underline_position,
+                        thickness,
+                    );
+                    match PathGeometry::polyline(&points) {
+                        Ok(path) => self.draw_geometry(&path, &brush, thickness),
+                        Err(err) => tracing::error!("failed to create squiggle path: {}", err),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_squiggle_points() {
+        let points = squiggle_points(0.0, 10.0, 5.0, 1.0);
+        assert_eq!(points.first(), Some(&Point::new(0.0, 5.0)));
+        assert_eq!(points.last().unwrap().x, 10.0);
+        // alternates between the top and the bottom of the squiggle
+        for w in points.windows(2) {
+            assert_ne!(w[0].y, w[1].y);
+            assert!(w[0].x < w[1].x);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Shaped glyph runs of a text layout.
+use crate::{
+    bindings::Windows::Win32::{
+        Direct2D::{ID2D1PathGeometry1, ID2D1SimplifiedGeometrySink},
+        DirectWrite::{
+            IDWriteFontFace, IDWriteTextRenderer, DWRITE_GLYPH_OFFSET, DWRITE_GLYPH_RUN,
+            DWRITE_GLYPH_RUN_DESCRIPTION, DWRITE_MATRIX, DWRITE_MEASURING_MODE,
+            DWRITE_STRIKETHROUGH, DWRITE_UNDERLINE,
+        },
+        SystemServices::BOOL,
+    },
+    com::{ComBox, ComObject, ComVtbl, E_POINTER, S_OK},
+    drawing::{PathGeometry, Point},
+    error::Result,
+    platform::Platform,
+    text::count_until_utf16,
+};
+use std::{ops::Range, os::raw::c_void, slice};
+use windows::HRESULT;
+
+/// A font face, used to render glyphs.
+#[derive(Clone)]
+pub struct FontFace(pub(crate) IDWriteFontFace);
+
+/// Offset of a glyph from its default position in a run.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GlyphOffset {
+    /// Offset in the advance direction of the run.
+    pub advance_offset: f32,
+    /// Offset in the ascent direction (perpendicular to the advance direction).
+    pub ascender_offset: f32,
+}
+
+/// A sequence of shaped glyphs sharing the same font, size and direction.
+#[derive(Clone)]
+pub struct GlyphRun {
+    /// Font face of the glyphs.
+    pub font_face: FontFace,
+    /// Font size, in DIPs.
+    pub font_size: f32,
+    /// Glyph indices in the font face.
+    pub glyph_indices: Vec<u16>,
+    /// Advance width of each glyph.
+    pub glyph_advances: Vec<f32>,
+    /// Offset of each glyph.
+    pub glyph_offsets: Vec<GlyphOffset>,
+    /// Origin of the baseline of the run, in the coordinate space of the layout.
+    pub baseline_origin: Point,
+    /// Bidi level of the run. Odd levels are right-to-left.
+    pub bidi_level: u32,
+    /// Whether the glyphs are rotated 90 degrees (vertical text).
+    pub is_sideways: bool,
+    /// Range of the text that produced the run, in UTF-8 code units.
+    pub text_range: Range<usize>,
+}
+
+impl GlyphRun {
+    /// Returns whether the glyphs are laid out right-to-left.
+    pub fn is_right_to_left(&self) -> bool {
+        self.bidi_level % 2 == 1
+    }
+
+    /// Returns the glyph run structure expected by DirectWrite and Direct2D.
+    ///
+    /// The returned structure borrows the glyph arrays of `self`.
+    pub(crate) fn to_dwrite(&self) -> DWRITE_GLYPH_RUN {
+        DWRITE_GLYPH_RUN {
+            fontFace: Some(self.font_face.0.clone()),
+            fontEmSize: self.font_size,
+            glyphCount: self.glyph_indices.len() as u32,
+            glyphIndices: self.glyph_indices.as_ptr(),
+            glyphAdvances: self.glyph_advances.as_ptr(),
+            // `GlyphOffset` has the same layout as `DWRITE_GLYPH_OFFSET`
+            glyphOffsets: self.glyph_offsets.as_ptr() as *const DWRITE_GLYPH_OFFSET,
+            isSideways: BOOL::from(self.is_sideways),
+            bidiLevel: self.bidi_level,
+        }
+    }
+
+    /// Returns the outlines of the glyphs of the run, as a path geometry.
+    ///
+    /// The outline is relative to the baseline origin of the run (i.e. the origin of the geometry
+    /// is at `baseline_origin`).
+    pub fn to_outline(&self) -> Result<PathGeometry> {
+        assert_eq!(self.glyph_indices.len(), self.glyph_advances.len());
+        assert_eq!(self.glyph_indices.len(), self.glyph_offsets.len());
+
+        let platform = Platform::instance();
+        unsafe {
+            let mut path_geometry = None;
+            let path_geometry = platform
+                .d2d_factory
+                .CreatePathGeometry(&mut path_geometry)
+                .and_some(path_geometry)?
+                .cast::<ID2D1PathGeometry1>()?;
+            let mut geometry_sink = None;
+            let geometry_sink = path_geometry
+                .Open(&mut geometry_sink)
+                .and_some(geometry_sink)?;
+
+            self.font_face
+                .0
+                .GetGlyphRunOutline(
+                    self.font_size,
+                    self.glyph_indices.as_ptr(),
+                    self.glyph_advances.as_ptr(),
+                    self.glyph_offsets.as_ptr() as *const DWRITE_GLYPH_OFFSET,
+                    self.glyph_indices.len() as u32,
+                    self.is_sideways,
+                    self.is_right_to_left(),
+                    geometry_sink.cast::<ID2D1SimplifiedGeometrySink>()?,
+                )
+                .ok()?;
+            geometry_sink.Close().ok()?;
+            Ok(PathGeometry(path_geometry))
+        }
+    }
+}
+
+/// Client drawing context passed to `IDWriteTextLayout::Draw`.
+pub(crate) struct GlyphRunCollector<'a> {
+    /// Text of the layout, to convert text positions.
+    pub(crate) text: &'a str,
+    pub(crate) glyph_runs: Vec<GlyphRun>,
+}
+
+/// Methods of `IDWriteTextRenderer`.
+#[repr(C)]
+struct TextRendererMethods {
+    is_pixel_snapping_disabled: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        is_disabled: *mut BOOL,
+    ) -> HRESULT,
+    get_current_transform: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        transform: *mut DWRITE_MATRIX,
+    ) -> HRESULT,
+    get_pixels_per_dip: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        pixels_per_dip: *mut f32,
+    ) -> HRESULT,
+    draw_glyph_run: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        measuring_mode: DWRITE_MEASURING_MODE,
+        glyph_run: *const DWRITE_GLYPH_RUN,
+        glyph_run_description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+        client_drawing_effect: *mut c_void,
+    ) -> HRESULT,
+    draw_underline: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        underline: *const DWRITE_UNDERLINE,
+        client_drawing_effect: *mut c_void,
+    ) -> HRESULT,
+    draw_strikethrough: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        strikethrough: *const DWRITE_STRIKETHROUGH,
+        client_drawing_effect: *mut c_void,
+    ) -> HRESULT,
+    draw_inline_object: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        origin_x: f32,
+        origin_y: f32,
+        inline_object: *mut c_void,
+        is_sideways: BOOL,
+        is_right_to_left: BOOL,
+        client_drawing_effect: *mut c_void,
+    ) -> HRESULT,
+}
+
+/// Implementation of `IDWriteTextRenderer` collecting glyph runs into the
+/// [`GlyphRunCollector`] passed as the client drawing context.
+struct GlyphRunRenderer;
+
+static GLYPH_RUN_RENDERER_VTBL: ComVtbl<TextRendererMethods> =
+    ComVtbl::new::<GlyphRunRenderer>(TextRendererMethods {
+        is_pixel_snapping_disabled: GlyphRunRenderer::is_pixel_snapping_disabled,
+        get_current_transform: GlyphRunRenderer::get_current_transform,
+        get_pixels_per_dip: GlyphRunRenderer::get_pixels_per_dip,
+        draw_glyph_run: GlyphRunRenderer::draw_glyph_run,
+        draw_underline: GlyphRunRenderer::draw_underline,
+        draw_strikethrough: GlyphRunRenderer::draw_strikethrough,
+        draw_inline_object: GlyphRunRenderer::draw_inline_object,
+    });
+
+unsafe impl ComObject for GlyphRunRenderer {
+    type Interface = IDWriteTextRenderer;
+    type Methods = TextRendererMethods;
+
+    fn vtbl() -> &'static ComVtbl<TextRendererMethods> {
+        &GLYPH_RUN_RENDERER_VTBL
+    }
+}
+
+impl GlyphRunRenderer {
+    unsafe extern "system" fn is_pixel_snapping_disabled(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        is_disabled: *mut BOOL,
+    ) -> HRESULT {
+        // glyph runs are returned in DIPs, independently of any render target
+        *is_disabled = BOOL(1);
+        S_OK
+    }
+
+    unsafe extern "system" fn get_current_transform(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        transform: *mut DWRITE_MATRIX,
+    ) -> HRESULT {
+        *transform = DWRITE_MATRIX {
+            m11: 1.0,
+            m12: 0.0,
+            m21: 0.0,
+            m22: 1.0,
+            dx: 0.0,
+            dy: 0.0,
+        };
+        S_OK
+    }
+
+    unsafe extern "system" fn get_pixels_per_dip(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        pixels_per_dip: *mut f32,
+    ) -> HRESULT {
+        *pixels_per_dip = 1.0;
+        S_OK
+    }
+
+    unsafe extern "system" fn draw_glyph_run(
+        _this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        baseline_origin_x: f32,
+        baseline_origin_y: f32,
+        _measuring_mode: DWRITE_MEASURING_MODE,
+        glyph_run: *const DWRITE_GLYPH_RUN,
+        glyph_run_description: *const DWRITE_GLYPH_RUN_DESCRIPTION,
+        _client_drawing_effect: *mut c_void,
+    ) -> HRESULT {
+        let collector = &mut *(client_drawing_context as *mut GlyphRunCollector);
+        let glyph_run = &*glyph_run;
+        let description = &*glyph_run_description;
+
+        let count = glyph_run.glyphCount as usize;
+        let glyph_indices = slice::from_raw_parts(glyph_run.glyphIndices, count).to_vec();
+        let glyph_advances = if glyph_run.glyphAdvances.is_null() {
+            vec![0.0; count]
+        } else {
+            slice::from_raw_parts(glyph_run.glyphAdvances, count).to_vec()
+        };
+        let glyph_offsets = if glyph_run.glyphOffsets.is_null() {
+            vec![GlyphOffset::default(); count]
+        } else {
+            slice::from_raw_parts(glyph_run.glyphOffsets, count)
+                .iter()
+                .map(|o| GlyphOffset {
+                    advance_offset: o.advanceOffset,
+                    ascender_offset: o.ascenderOffset,
+                })
+                .collect()
+        };
+
+        let start_utf16 = description.textPosition as usize;
+        let end_utf16 = start_utf16 + description.stringLength as usize;
+        let text_range = count_until_utf16(collector.text, start_utf16)
+            ..count_until_utf16(collector.text, end_utf16);
+
+        let font_face = match glyph_run.fontFace {
+            Some(ref font_face) => FontFace(font_face.clone()),
+            None => return E_POINTER,
+        };
+
+        collector.glyph_runs.push(GlyphRun {
+            font_face,
+            font_size: glyph_run.fontEmSize,
+            glyph_indices,
+            glyph_advances,
+            glyph_offsets,
+            baseline_origin: Point::new(baseline_origin_x as f64, baseline_origin_y as f64),
+            bidi_level: glyph_run.bidiLevel,
+            is_sideways: glyph_run.isSideways.as_bool(),
+            text_range,
+        });
+        S_OK
+    }
+
+    unsafe extern "system" fn draw_underline(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        _baseline_origin_x: f32,
+        _baseline_origin_y: f32,
+        _underline: *const DWRITE_UNDERLINE,
+        _client_drawing_effect: *mut c_void,
+    ) -> HRESULT {
+        S_OK
+    }
+
+    unsafe extern "system" fn draw_strikethrough(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        _baseline_origin_x: f32,
+        _baseline_origin_y: f32,
+        _strikethrough: *const DWRITE_STRIKETHROUGH,
+        _client_drawing_effect: *mut c_void,
+    ) -> HRESULT {
+        S_OK
+    }
+
+    unsafe extern "system" fn draw_inline_object(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        _origin_x: f32,
+        _origin_y: f32,
+        _inline_object: *mut c_void,
+        _is_sideways: BOOL,
+        _is_right_to_left: BOOL,
+        _client_drawing_effect: *mut c_void,
+    ) -> HRESULT {
+        // inline objects are drawn by the application
+        S_OK
+    }
+}
+
+/// Creates a text renderer that collects glyph runs into a [`GlyphRunCollector`].
+pub(crate) fn create_glyph_run_renderer() -> IDWriteTextRenderer {
+    ComBox::create(GlyphRunRenderer)
+}
@@ -0,0 +1,140 @@
+//! Inline objects: non-text items embedded in a text layout.
+//!
+//! DirectWrite only needs the metrics of those objects to lay out the text around them. They are
+//! not drawn by DirectWrite: the application is expected to draw them itself at the positions
+//! returned by [`TextLayout::inline_objects`](crate::text::TextLayout::inline_objects).
+use crate::{
+    bindings::Windows::Win32::{
+        DirectWrite::{
+            IDWriteInlineObject, DWRITE_BREAK_CONDITION, DWRITE_INLINE_OBJECT_METRICS,
+            DWRITE_OVERHANG_METRICS,
+        },
+        SystemServices::BOOL,
+    },
+    com::{ComBox, ComObject, ComVtbl, S_OK},
+    drawing::Size,
+};
+use std::os::raw::c_void;
+use windows::HRESULT;
+
+/// The character that should be placed in the text at the position of an inline object.
+pub const OBJECT_REPLACEMENT_CHARACTER: char = '\u{FFFC}';
+
+/// Size and baseline of an inline object.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InlineObjectMetrics {
+    /// Size of the space reserved for the object.
+    pub size: Size,
+    /// Distance from the top of the object to its baseline, which is aligned with the baseline
+    /// of the line.
+    pub baseline: f64,
+}
+
+/// Methods of `IDWriteInlineObject`.
+#[repr(C)]
+struct InlineObjectMethods {
+    draw: unsafe extern "system" fn(
+        this: *mut c_void,
+        client_drawing_context: *mut c_void,
+        renderer: *mut c_void,
+        origin_x: f32,
+        origin_y: f32,
+        is_sideways: BOOL,
+        is_right_to_left: BOOL,
+        client_drawing_effect: *mut c_void,
+    ) -> HRESULT,
+    get_metrics: unsafe extern "system" fn(
+        this: *mut c_void,
+        metrics: *mut DWRITE_INLINE_OBJECT_METRICS,
+    ) -> HRESULT,
+    get_overhang_metrics: unsafe extern "system" fn(
+        this: *mut c_void,
+        overhangs: *mut DWRITE_OVERHANG_METRICS,
+    ) -> HRESULT,
+    get_break_conditions: unsafe extern "system" fn(
+        this: *mut c_void,
+        before: *mut DWRITE_BREAK_CONDITION,
+        after: *mut DWRITE_BREAK_CONDITION,
+    ) -> HRESULT,
+}
+
+/// Implementation of `IDWriteInlineObject`, only reporting its metrics.
+struct InlineObject {
+    metrics: InlineObjectMetrics,
+}
+
+static INLINE_OBJECT_VTBL: ComVtbl<InlineObjectMethods> =
+    ComVtbl::new::<InlineObject>(InlineObjectMethods {
+        draw: InlineObject::draw,
+        get_metrics: InlineObject::get_metrics,
+        get_overhang_metrics: InlineObject::get_overhang_metrics,
+        get_break_conditions: InlineObject::get_break_conditions,
+    });
+
+unsafe impl ComObject for InlineObject {
+    type Interface = IDWriteInlineObject;
+    type Methods = InlineObjectMethods;
+
+    fn vtbl() -> &'static ComVtbl<InlineObjectMethods> {
+        &INLINE_OBJECT_VTBL
+    }
+}
+
+impl InlineObject {
+    unsafe extern "system" fn draw(
+        _this: *mut c_void,
+        _client_drawing_context: *mut c_void,
+        _renderer: *mut c_void,
+        _origin_x: f32,
+        _origin_y: f32,
+        _is_sideways: BOOL,
+        _is_right_to_left: BOOL,
+        _client_drawing_effect: *mut c_void,
+    ) -> HRESULT {
+        // drawn by the application
+        S_OK
+    }
+
+    unsafe extern "system" fn get_metrics(
+        this: *mut c_void,
+        metrics: *mut DWRITE_INLINE_OBJECT_METRICS,
+    ) -> HRESULT {
+        let this = ComBox::<InlineObject>::object(this);
+        *metrics = DWRITE_INLINE_OBJECT_METRICS {
+            width: this.metrics.size.width as f32,
+            height: this.metrics.size.height as f32,
+            baseline: this.metrics.baseline as f32,
+            supportsSideways: BOOL(0),
+        };
+        S_OK
+    }
+
+    unsafe extern "system" fn get_overhang_metrics(
+        _this: *mut c_void,
+        overhangs: *mut DWRITE_OVERHANG_METRICS,
+    ) -> HRESULT {
+        *overhangs = DWRITE_OVERHANG_METRICS {
+            left: 0.0,
+            top: 0.0,
+            right: 0.0,
+            bottom: 0.0,
+        };
+        S_OK
+    }
+
+    unsafe extern "system" fn get_break_conditions(
+        _this: *mut c_void,
+        before: *mut DWRITE_BREAK_CONDITION,
+        after: *mut DWRITE_BREAK_CONDITION,
+    ) -> HRESULT {
+        // behave like a regular character
+        *before = DWRITE_BREAK_CONDITION::DWRITE_BREAK_CONDITION_NEUTRAL;
+        *after = DWRITE_BREAK_CONDITION::DWRITE_BREAK_CONDITION_NEUTRAL;
+        S_OK
+    }
+}
+
+/// Creates a new inline object with the specified metrics.
+pub(crate) fn create_inline_object(metrics: InlineObjectMetrics) -> IDWriteInlineObject {
+    ComBox::create(InlineObject { metrics })
+}
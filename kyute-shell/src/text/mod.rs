@@ -1,20 +1,34 @@
 //! Platform text services
+mod cache;
+mod decoration;
+mod glyph_run;
+mod inline_object;
+
+pub use cache::{TextLayoutCache, TextLayoutCacheStats};
+pub use decoration::{LineFragment, TextDecoration, TextDecorationKind};
+pub use glyph_run::{FontFace, GlyphOffset, GlyphRun};
+pub use inline_object::{InlineObjectMetrics, OBJECT_REPLACEMENT_CHARACTER};
+
 use crate::{
     drawing::{Brush, Point, Rect, Size},
     error::Result,
     platform::Platform,
 };
 use std::{
+    hash::{Hash, Hasher},
+    iter,
     mem::MaybeUninit,
     ops::{Bound, Range, RangeBounds},
+    os::raw::c_void,
 };
 
 use crate::bindings::Windows::Win32::{
     Debug::WIN32_ERROR,
     DirectWrite::{
-        IDWriteTextFormat, IDWriteTextLayout, DWRITE_FONT_STRETCH, DWRITE_FONT_STYLE,
-        DWRITE_FONT_WEIGHT, DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS, DWRITE_TEXT_METRICS,
-        DWRITE_TEXT_RANGE,
+        IDWriteFontCollection, IDWriteTextFormat, IDWriteTextLayout, DWRITE_CLUSTER_METRICS,
+        DWRITE_FONT_METRICS, DWRITE_FONT_STRETCH,
+        DWRITE_FONT_STYLE, DWRITE_FONT_WEIGHT, DWRITE_HIT_TEST_METRICS, DWRITE_LINE_METRICS,
+        DWRITE_READING_DIRECTION, DWRITE_TEXT_METRICS, DWRITE_TEXT_RANGE, DWRITE_WORD_WRAPPING,
     },
     SystemServices::BOOL,
 };
@@ -130,24 +144,186 @@ impl FontStretch {
     }
 }
 
-/*#[derive(Copy, Clone, Debug, PartialEq)]
-pub struct TextFormatDesc<'a> {
-    family: &'a str,
-    weight: FontWeight,
-    style: FontStyle,
-    stretch: FontStretch,
-    size: f32,
-}*/
+/// Base direction of a paragraph.
+///
+/// This determines the direction of the runs of text with a neutral direction, and the side that
+/// the paragraph is aligned to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ReadingDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+impl Default for ReadingDirection {
+    fn default() -> Self {
+        ReadingDirection::LeftToRight
+    }
+}
+
+impl ReadingDirection {
+    fn to_dwrite(self) -> DWRITE_READING_DIRECTION {
+        match self {
+            ReadingDirection::LeftToRight => {
+                DWRITE_READING_DIRECTION::DWRITE_READING_DIRECTION_LEFT_TO_RIGHT
+            }
+            ReadingDirection::RightToLeft => {
+                DWRITE_READING_DIRECTION::DWRITE_READING_DIRECTION_RIGHT_TO_LEFT
+            }
+        }
+    }
+}
+
+/// Whether lines of text are broken to fit the width of the layout box.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum WordWrapping {
+    /// Lines are broken between words (the default).
+    Wrap,
+    /// Lines are only broken at line terminators, and may overflow the layout box.
+    NoWrap,
+}
+
+impl Default for WordWrapping {
+    fn default() -> Self {
+        WordWrapping::Wrap
+    }
+}
+
+impl WordWrapping {
+    fn to_dwrite(self) -> DWRITE_WORD_WRAPPING {
+        match self {
+            WordWrapping::Wrap => DWRITE_WORD_WRAPPING::DWRITE_WORD_WRAPPING_WRAP,
+            WordWrapping::NoWrap => DWRITE_WORD_WRAPPING::DWRITE_WORD_WRAPPING_NO_WRAP,
+        }
+    }
+}
+
+/// Direction of a caret movement on screen.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum VisualDirection {
+    Left,
+    Right,
+}
+
+/// Parameters of a [`TextFormat`].
+///
+/// Two formats with the same description produce the same layouts, so this can be used to
+/// identify a format (e.g. in a cache key).
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextFormatDesc {
+    pub family: String,
+    pub weight: FontWeight,
+    pub style: FontStyle,
+    pub stretch: FontStretch,
+    pub size: f32,
+    pub reading_direction: ReadingDirection,
+}
+
+// the font size is never NaN
+impl Eq for TextFormatDesc {}
+
+impl Hash for TextFormatDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.family.hash(state);
+        self.weight.hash(state);
+        self.style.hash(state);
+        self.stretch.hash(state);
+        self.size.to_bits().hash(state);
+        self.reading_direction.hash(state);
+    }
+}
 
 /// Text formatting options.
 #[derive(Clone)]
-pub struct TextFormat(IDWriteTextFormat);
+pub struct TextFormat {
+    format: IDWriteTextFormat,
+    desc: TextFormatDesc,
+}
 
 impl TextFormat {
     /// Creates a new `TextFormatBuilder` to build a `TextFormat`.
     pub fn builder<'a>() -> TextFormatBuilder<'a> {
         TextFormatBuilder::new()
     }
+
+    /// Returns the parameters of this format.
+    pub fn desc(&self) -> &TextFormatDesc {
+        &self.desc
+    }
+
+    /// Returns the metrics of the font matching this format, scaled to the font size.
+    ///
+    /// The metrics are in DIPs, rounded to whole device pixels for the given scale factor, so that
+    /// baselines computed from them are aligned with the pixel grid.
+    pub fn font_metrics(&self, scale_factor: f64) -> Result<FontMetrics> {
+        let platform = Platform::instance();
+
+        unsafe {
+            let mut collection = None;
+            let collection = match self
+                .format
+                .GetFontCollection(&mut collection)
+                .and_some(collection)
+            {
+                Ok(collection) => collection,
+                Err(_) => {
+                    // the format uses the system font collection
+                    let mut collection = None;
+                    platform
+                        .dwrite_factory
+                        .GetSystemFontCollection(&mut collection, false)
+                        .and_some(collection)?
+                }
+            };
+
+            let mut metrics = MaybeUninit::<DWRITE_FONT_METRICS>::uninit();
+            match find_font_family(&collection, &self.desc.family)? {
+                Some(family_index) => {
+                    let mut family = None;
+                    let family = collection
+                        .GetFontFamily(family_index, &mut family)
+                        .and_some(family)?;
+                    let mut font = None;
+                    let font = family
+                        .GetFirstMatchingFont(
+                            self.desc.weight.to_dwrite(),
+                            self.desc.stretch.to_dwrite(),
+                            self.desc.style.to_dwrite(),
+                            &mut font,
+                        )
+                        .and_some(font)?;
+                    font.GetMetrics(metrics.as_mut_ptr());
+                }
+                None => {
+                    // The family is empty (the default) or not installed, and DirectWrite falls
+                    // back to another font when laying out text: lay out a character to find out
+                    // which one.
+                    let text_layout =
+                        TextLayout::new("x", self, Size::new(f32::MAX as f64, f32::MAX as f64))?;
+                    let glyph_runs = text_layout.glyph_runs()?;
+                    let glyph_run = glyph_runs
+                        .first()
+                        .expect("text layout produced no glyph runs");
+                    glyph_run.font_face.0.GetMetrics(metrics.as_mut_ptr());
+                }
+            }
+
+            Ok(FontMetrics::from_dwrite(
+                &metrics.assume_init(),
+                self.desc.size as f64,
+                scale_factor,
+            ))
+        }
+    }
+}
+
+/// Returns the index of the font family with the specified name in the collection.
+unsafe fn find_font_family(collection: &IDWriteFontCollection, name: &str) -> Result<Option<u32>> {
+    let mut index = 0;
+    let mut exists = BOOL::default();
+    collection
+        .FindFamilyName(name, &mut index, &mut exists)
+        .ok()?;
+    Ok(if exists.as_bool() { Some(index) } else { None })
 }
 
 /// Builder pattern for `TextFormat`.
@@ -157,6 +333,7 @@ pub struct TextFormatBuilder<'a> {
     style: FontStyle,
     stretch: FontStretch,
     size: f32,
+    reading_direction: ReadingDirection,
 }
 
 impl<'a> TextFormatBuilder<'a> {
@@ -167,6 +344,7 @@ impl<'a> TextFormatBuilder<'a> {
             style: FontStyle::Normal,
             stretch: FontStretch::Normal,
             size: 12.0,
+            reading_direction: ReadingDirection::LeftToRight,
         }
     }
 
@@ -180,6 +358,12 @@ impl<'a> TextFormatBuilder<'a> {
         self
     }
 
+    /// Sets the base direction of paragraphs.
+    pub fn reading_direction(mut self, reading_direction: ReadingDirection) -> TextFormatBuilder<'a> {
+        self.reading_direction = reading_direction;
+        self
+    }
+
     pub fn build(self) -> Result<TextFormat> {
         let platform = Platform::instance();
 
@@ -198,7 +382,20 @@ impl<'a> TextFormatBuilder<'a> {
                     &mut text_format,
                 )
                 .and_some(text_format)?;
-            Ok(TextFormat(text_format))
+            text_format
+                .SetReadingDirection(self.reading_direction.to_dwrite())
+                .ok()?;
+            Ok(TextFormat {
+                format: text_format,
+                desc: TextFormatDesc {
+                    family: self.family.to_owned(),
+                    weight: self.weight,
+                    style: self.style,
+                    stretch: self.stretch,
+                    size: self.size,
+                    reading_direction: self.reading_direction,
+                },
+            })
         }
     }
 }
@@ -248,6 +445,68 @@ impl From<DWRITE_LINE_METRICS> for LineMetrics {
     }
 }
 
+/// Metrics of a font, scaled to a font size.
+///
+/// All values are in DIPs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FontMetrics {
+    /// Distance from the top of the line to the baseline.
+    pub ascent: f64,
+    /// Distance from the baseline to the bottom of the line.
+    pub descent: f64,
+    /// Recommended additional space between lines.
+    pub line_gap: f64,
+    /// Height of capital letters above the baseline.
+    pub cap_height: f64,
+    /// Height of lowercase letters (e.g. 'x') above the baseline.
+    pub x_height: f64,
+    /// Distance from the baseline to the top of the underline, positive downwards.
+    pub underline_position: f64,
+    /// Thickness of the underline, at least one device pixel.
+    pub underline_thickness: f64,
+    /// Distance from the baseline to the top of the strikethrough, positive upwards.
+    pub strikethrough_position: f64,
+    /// Thickness of the strikethrough, at least one device pixel.
+    pub strikethrough_thickness: f64,
+}
+
+impl FontMetrics {
+    fn from_dwrite(m: &DWRITE_FONT_METRICS, font_size: f64, scale_factor: f64) -> FontMetrics {
+        let design_units_per_em = m.designUnitsPerEm as f64;
+        let scale = |design_units: f64| {
+            let dips = design_units * font_size / design_units_per_em;
+            (dips * scale_factor).round() / scale_factor
+        };
+        // lines must stay visible at small sizes
+        let thickness = |design_units: f64| scale(design_units).max(1.0 / scale_factor);
+        FontMetrics {
+            ascent: scale(m.ascent as f64),
+            descent: scale(m.descent as f64),
+            line_gap: scale(m.lineGap as f64),
+            cap_height: scale(m.capHeight as f64),
+            x_height: scale(m.xHeight as f64),
+            // DirectWrite positions are positive upwards
+            underline_position: scale(-m.underlinePosition as f64),
+            underline_thickness: thickness(m.underlineThickness as f64),
+            strikethrough_position: scale(m.strikethroughPosition as f64),
+            strikethrough_thickness: thickness(m.strikethroughThickness as f64),
+        }
+    }
+
+    /// Returns the recommended distance between the baselines of two consecutive lines.
+    pub fn line_height(&self) -> f64 {
+        self.ascent + self.descent + self.line_gap
+    }
+
+    /// Returns the offset from the baseline at which an item of the given height should be placed
+    /// to be vertically centered on lowercase letters (positive downwards).
+    ///
+    /// This is typically used to align icons with text.
+    pub fn center_on_x_height(&self, height: f64) -> f64 {
+        -0.5 * (self.x_height + height)
+    }
+}
+
 /// From [piet-direct2d](https://github.com/linebender/piet/blob/master/piet-direct2d/src/text.rs):
 /// Counts the number of utf-16 code units in the given string.
 /// from xi-editor
@@ -280,6 +539,28 @@ fn count_until_utf16(s: &str, utf16_text_position: usize) -> usize {
     s.len()
 }
 
+/// Converts increasing UTF-16 text positions to UTF-8 text positions, walking the text once.
+fn utf16_to_utf8_positions<'a>(
+    s: &'a str,
+    utf16_positions: impl Iterator<Item = usize> + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    let mut chars = s.chars();
+    let mut pos_utf8 = 0;
+    let mut pos_utf16 = 0;
+    utf16_positions.map(move |utf16_text_position| {
+        while pos_utf16 < utf16_text_position {
+            match chars.next() {
+                Some(c) => {
+                    pos_utf16 += c.len_utf16();
+                    pos_utf8 += c.len_utf8();
+                }
+                None => break,
+            }
+        }
+        pos_utf8
+    })
+}
+
 /// Text hit-test metrics.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct HitTestMetrics {
@@ -287,6 +568,17 @@ pub struct HitTestMetrics {
     pub text_position: usize,
     pub length: usize,
     pub bounds: Rect,
+    /// Bidi level of the text. Odd levels are right-to-left.
+    pub bidi_level: u32,
+    /// Whether the range contains text (as opposed to an inline object).
+    pub is_text: bool,
+}
+
+impl HitTestMetrics {
+    /// Returns whether the text is displayed right-to-left.
+    pub fn is_right_to_left(&self) -> bool {
+        self.bidi_level % 2 == 1
+    }
 }
 
 impl HitTestMetrics {
@@ -302,6 +594,8 @@ impl HitTestMetrics {
                 Point::new(metrics.left as f64, metrics.top as f64),
                 Size::new(metrics.width as f64, metrics.height as f64),
             ),
+            bidi_level: metrics.bidiLevel,
+            is_text: metrics.isText.as_bool(),
         }
     }
 }
@@ -325,10 +619,41 @@ pub struct HitTestTextPosition {
 pub struct TextLayout {
     text_layout: IDWriteTextLayout,
     text: String,
+    /// Inline objects, by text position.
+    inline_objects: Vec<(usize, InlineObjectMetrics)>,
+}
+
+/// Position of an inline object in a [`TextLayout`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InlineObjectPosition {
+    /// Position of the object in the text, in UTF-8 code units.
+    pub text_position: usize,
+    /// Bounds of the object, relative to the origin of the layout.
+    pub bounds: Rect,
 }
 
 impl TextLayout {
     pub fn new(text: &str, format: &TextFormat, layout_box_size: Size) -> Result<TextLayout> {
+        TextLayout::new_internal(text, format, layout_box_size, None)
+    }
+
+    /// Creates a layout whose glyph metrics are snapped to the pixels of a display with the
+    /// specified scale factor (number of pixels per DIP).
+    pub fn with_scale_factor(
+        text: &str,
+        format: &TextFormat,
+        layout_box_size: Size,
+        scale_factor: f64,
+    ) -> Result<TextLayout> {
+        TextLayout::new_internal(text, format, layout_box_size, Some(scale_factor))
+    }
+
+    fn new_internal(
+        text: &str,
+        format: &TextFormat,
+        layout_box_size: Size,
+        scale_factor: Option<f64>,
+    ) -> Result<TextLayout> {
         let platform = Platform::instance();
         let mut wtext : Vec<u16> = text.encode_utf16()
             .chain(std::iter::once(0))
@@ -336,20 +661,33 @@ impl TextLayout {
 
         unsafe {
             let mut text_layout = None;
-            let text_layout = platform
-                .dwrite_factory
-                .CreateTextLayout(
+            let hr = match scale_factor {
+                Some(scale_factor) => platform.dwrite_factory.CreateGdiCompatibleTextLayout(
+                    PWSTR(wtext.as_mut_ptr()),
+                    wtext.len() as u32,
+                    &format.format,
+                    layout_box_size.width as f32,
+                    layout_box_size.height as f32,
+                    scale_factor as f32,
+                    std::ptr::null(),
+                    // GDI natural rather than GDI classic metrics, closer to those used by Direct2D
+                    BOOL::from(true),
+                    &mut text_layout,
+                ),
+                None => platform.dwrite_factory.CreateTextLayout(
                     PWSTR(wtext.as_mut_ptr()), // oversight?
                     wtext.len() as u32,
-                    &format.0,
+                    &format.format,
                     layout_box_size.width as f32,
                     layout_box_size.height as f32,
                     &mut text_layout,
-                )
-                .and_some(text_layout)?;
+                ),
+            };
+            let text_layout = hr.and_some(text_layout)?;
             Ok(TextLayout {
                 text_layout,
                 text: text.to_owned(),
+                inline_objects: Vec::new(),
             })
         }
     }
@@ -376,6 +714,13 @@ impl TextLayout {
         }
     }
 
+    /// Sets the width of the layout box.
+    pub fn set_max_width(&mut self, max_width: f64) {
+        unsafe {
+            self.text_layout.SetMaxWidth(max_width as f32).unwrap();
+        }
+    }
+
     /// Returns the layout maximum size.
     pub fn max_size(&self) -> Size {
         unsafe {
@@ -385,7 +730,27 @@ impl TextLayout {
         }
     }
 
+    /// Returns the position of the caret before the character at `text_position` (leading edge).
+    ///
+    /// This is the logical to visual caret mapping. In right-to-left runs of text, the
+    /// leading edge of a character is on its right side.
     pub fn hit_test_text_position(&self, text_position: usize) -> Result<HitTestTextPosition> {
+        self.hit_test_text_position_internal(text_position, false)
+    }
+
+    /// Returns the position of the caret after the character at `text_position` (trailing edge).
+    pub fn hit_test_text_position_trailing(
+        &self,
+        text_position: usize,
+    ) -> Result<HitTestTextPosition> {
+        self.hit_test_text_position_internal(text_position, true)
+    }
+
+    fn hit_test_text_position_internal(
+        &self,
+        text_position: usize,
+        is_trailing_hit: bool,
+    ) -> Result<HitTestTextPosition> {
         // convert the text position to an utf-16 offset (inspired by piet-direct2d).
         let pos_utf16 = count_utf16(&self.text[0..text_position]);
 
@@ -398,7 +763,7 @@ impl TextLayout {
             self.text_layout
                 .HitTestTextPosition(
                     pos_utf16 as u32,
-                    false,
+                    is_trailing_hit,
                     &mut point_x,
                     &mut point_y,
                     metrics.as_mut_ptr(),
@@ -472,6 +837,117 @@ impl TextLayout {
         }
     }
 
+    /// Returns the positions of the boundaries between clusters (the positions where a caret can be
+    /// placed), in UTF-8 code units, including `0` and the length of the text.
+    pub fn cluster_boundaries(&self) -> Vec<usize> {
+        self.cluster_boundaries_in(0..self.text.len())
+    }
+
+    /// Returns the boundaries between clusters that are in `range`, bounds included.
+    fn cluster_boundaries_in(&self, range: Range<usize>) -> Vec<usize> {
+        let metrics = unsafe {
+            let mut cluster_count = 0;
+            let hr =
+                self.text_layout
+                    .GetClusterMetrics(std::ptr::null_mut(), 0, &mut cluster_count);
+            let mut metrics = Vec::new();
+            if hr == HRESULT::from_win32(WIN32_ERROR::ERROR_INSUFFICIENT_BUFFER.0) {
+                metrics = Vec::<DWRITE_CLUSTER_METRICS>::with_capacity(cluster_count as usize);
+                self.text_layout
+                    .GetClusterMetrics(metrics.as_mut_ptr(), cluster_count, &mut cluster_count)
+                    .unwrap();
+                metrics.set_len(cluster_count as usize);
+            }
+            metrics
+        };
+
+        let ends_utf16 = metrics.iter().scan(0, |end, m| {
+            *end += m.length as usize;
+            Some(*end)
+        });
+        let mut boundaries = Vec::new();
+        if range.start == 0 {
+            boundaries.push(0);
+        }
+        for pos in utf16_to_utf8_positions(&self.text, ends_utf16) {
+            if pos > range.end {
+                break;
+            }
+            if pos >= range.start {
+                boundaries.push(pos);
+            }
+        }
+        boundaries.dedup();
+        boundaries
+    }
+
+    /// Returns the range of text of the line containing `text_position`, in UTF-8 code units.
+    pub fn line_range(&self, text_position: usize) -> Range<usize> {
+        let line_metrics = self.line_metrics();
+        let line_count = line_metrics.len();
+        let ends_utf16 = line_metrics.iter().scan(0, |end, line| {
+            *end += line.length as usize;
+            Some(*end)
+        });
+        let mut start = 0;
+        for (i, end) in utf16_to_utf8_positions(&self.text, ends_utf16).enumerate() {
+            if text_position < end || i == line_count - 1 {
+                return start..end;
+            }
+            start = end;
+        }
+        0..self.text.len()
+    }
+
+    /// Returns the text position of the caret after moving it visually to the left or to the right,
+    /// on the same line.
+    ///
+    /// This is the visual to logical caret mapping: in bidirectional text, moving the caret to the
+    /// right may move it backwards in the text. Returns `text_position` if the caret is already at
+    /// the end of the line in the specified direction.
+    pub fn visual_caret_move(
+        &self,
+        text_position: usize,
+        direction: VisualDirection,
+    ) -> Result<usize> {
+        let current = self.hit_test_text_position(text_position)?;
+        let x = current.point.x;
+        let line_top = current.metrics.bounds.origin.y;
+
+        let line = self.line_range(text_position);
+        let boundaries = self.cluster_boundaries_in(line);
+        let mut best: Option<(f64, usize)> = None;
+
+        for (i, &pos) in boundaries.iter().enumerate() {
+            if pos == text_position {
+                continue;
+            }
+            // at a change of direction, the caret at `pos` can be on the leading edge of the
+            // cluster after it, or on the trailing edge of the cluster before it
+            let leading = self.hit_test_text_position(pos)?;
+            let trailing = match i.checked_sub(1) {
+                Some(prev) => Some(self.hit_test_text_position_trailing(boundaries[prev])?),
+                None => None,
+            };
+            for hit in iter::once(leading).chain(trailing) {
+                if (hit.metrics.bounds.origin.y - line_top).abs() > 0.5 {
+                    // the end of a wrapped line is also the start of the next one
+                    continue;
+                }
+                let dx = hit.point.x - x;
+                let in_direction = match direction {
+                    VisualDirection::Left => dx < 0.0,
+                    VisualDirection::Right => dx > 0.0,
+                };
+                if in_direction && best.map_or(true, |(best_dx, _)| dx.abs() < best_dx) {
+                    best = Some((dx.abs(), pos));
+                }
+            }
+        }
+
+        Ok(best.map(|(_, pos)| pos).unwrap_or(text_position))
+    }
+
     pub fn metrics(&self) -> TextMetrics {
         unsafe {
             let mut metrics = MaybeUninit::<DWRITE_TEXT_METRICS>::uninit();
@@ -526,6 +1002,82 @@ impl TextLayout {
         }
     }
 
+    /// Reserves space for an inline object at the specified text position.
+    ///
+    /// The object replaces the character at this position in the layout, which should usually be
+    /// [`OBJECT_REPLACEMENT_CHARACTER`]. The object is laid out like a character: it wraps with the
+    /// text, and its baseline is aligned with the baseline of the line.
+    ///
+    /// Panics if `text_position` is not the position of a character in the text.
+    pub fn set_inline_object(&mut self, text_position: usize, metrics: InlineObjectMetrics) {
+        let len = self.text[text_position..]
+            .chars()
+            .next()
+            .expect("invalid text position")
+            .len_utf8();
+        let range = self.to_utf16_text_range(text_position..text_position + len);
+        let inline_object = inline_object::create_inline_object(metrics);
+        unsafe {
+            self.text_layout
+                .SetInlineObject(&inline_object, range)
+                .unwrap();
+        }
+        self.inline_objects.retain(|&(pos, _)| pos != text_position);
+        self.inline_objects.push((text_position, metrics));
+    }
+
+    /// Returns the positions of the inline objects after layout, in the order they were added.
+    pub fn inline_objects(&self) -> Vec<InlineObjectPosition> {
+        let line_metrics = self.line_metrics();
+
+        self.inline_objects
+            .iter()
+            .filter_map(|&(text_position, metrics)| {
+                let hit = self.hit_test_text_position(text_position).ok()?;
+
+                // find the baseline of the line containing the object
+                let mut line_top = 0.0;
+                let mut line_baseline = None;
+                let mut end_utf16 = 0;
+                for line in line_metrics.iter() {
+                    end_utf16 += line.length as usize;
+                    if text_position < count_until_utf16(&self.text, end_utf16) {
+                        line_baseline = Some(line_top + line.baseline);
+                        break;
+                    }
+                    line_top += line.height;
+                }
+                let baseline = line_baseline?;
+
+                Some(InlineObjectPosition {
+                    text_position,
+                    bounds: Rect::new(
+                        Point::new(hit.metrics.bounds.origin.x, baseline - metrics.baseline),
+                        metrics.size,
+                    ),
+                })
+            })
+            .collect()
+    }
+
+    /// Sets the base direction of paragraphs.
+    pub fn set_reading_direction(&mut self, reading_direction: ReadingDirection) {
+        unsafe {
+            self.text_layout
+                .SetReadingDirection(reading_direction.to_dwrite())
+                .unwrap();
+        }
+    }
+
+    /// Sets whether lines are broken to fit the width of the layout box.
+    pub fn set_word_wrapping(&mut self, word_wrapping: WordWrapping) {
+        unsafe {
+            self.text_layout
+                .SetWordWrapping(word_wrapping.to_dwrite())
+                .unwrap();
+        }
+    }
+
     pub fn set_font_weight<R>(&mut self, weight: FontWeight, range: R)
     where
         R: RangeBounds<usize>,
@@ -538,6 +1090,16 @@ impl TextLayout {
         }
     }
 
+    pub fn set_underline<R>(&mut self, underline: bool, range: R)
+    where
+        R: RangeBounds<usize>,
+    {
+        let range = self.to_utf16_text_range(range);
+        unsafe {
+            self.text_layout.SetUnderline(underline, range).unwrap();
+        }
+    }
+
     pub fn set_drawing_effect<R>(&mut self, effect: &impl DrawingEffect, range: R)
     where
         R: RangeBounds<usize>,
@@ -550,7 +1112,94 @@ impl TextLayout {
         }
     }
 
+    /// Returns the shaped glyph runs of the layout, in drawing order.
+    ///
+    /// Baseline origins are relative to the origin of the layout. Inline objects are not included.
+    pub fn glyph_runs(&self) -> Result<Vec<GlyphRun>> {
+        let renderer = glyph_run::create_glyph_run_renderer();
+        let mut collector = glyph_run::GlyphRunCollector {
+            text: &self.text,
+            glyph_runs: Vec::new(),
+        };
+        unsafe {
+            self.text_layout
+                .Draw(
+                    &mut collector as *mut _ as *mut c_void,
+                    &renderer,
+                    0.0,
+                    0.0,
+                )
+                .ok()?;
+        }
+        Ok(collector.glyph_runs)
+    }
+
     pub(crate) fn as_raw(&self) -> &IDWriteTextLayout {
         &self.text_layout
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{utf16_to_utf8_positions, FontMetrics};
+    use crate::bindings::Windows::Win32::DirectWrite::DWRITE_FONT_METRICS;
+
+    /// Metrics of Arial.
+    fn arial_metrics() -> DWRITE_FONT_METRICS {
+        DWRITE_FONT_METRICS {
+            designUnitsPerEm: 2048,
+            ascent: 1854,
+            descent: 434,
+            lineGap: 67,
+            capHeight: 1467,
+            xHeight: 1062,
+            underlinePosition: -217,
+            underlineThickness: 150,
+            strikethroughPosition: 530,
+            strikethroughThickness: 150,
+        }
+    }
+
+    #[test]
+    fn test_font_metrics_rounding() {
+        let m = FontMetrics::from_dwrite(&arial_metrics(), 12.0, 1.0);
+        assert_eq!(m.ascent, 11.0);
+        assert_eq!(m.descent, 3.0);
+        assert_eq!(m.line_gap, 0.0);
+        assert_eq!(m.cap_height, 9.0);
+        assert_eq!(m.x_height, 6.0);
+        assert_eq!(m.underline_position, 1.0);
+        assert_eq!(m.underline_thickness, 1.0);
+        assert_eq!(m.strikethrough_position, 3.0);
+        assert_eq!(m.line_height(), 14.0);
+
+        // rounded to device pixels, not DIPs
+        let m = FontMetrics::from_dwrite(&arial_metrics(), 12.0, 1.5);
+        assert_eq!(m.ascent, 16.0 / 1.5);
+        assert_eq!(m.descent, 4.0 / 1.5);
+    }
+
+    #[test]
+    fn test_font_metrics_min_thickness() {
+        // 0.44 DIP, rounds to zero
+        let m = FontMetrics::from_dwrite(&arial_metrics(), 6.0, 1.0);
+        assert_eq!(m.underline_thickness, 1.0);
+        assert_eq!(m.strikethrough_thickness, 1.0);
+        // 0.88 device pixels at 2x
+        let m = FontMetrics::from_dwrite(&arial_metrics(), 6.0, 2.0);
+        assert_eq!(m.underline_thickness, 0.5);
+        // 0.44 device pixels at 2x
+        let m = FontMetrics::from_dwrite(&arial_metrics(), 3.0, 2.0);
+        assert_eq!(m.underline_thickness, 0.5);
+        assert_eq!(m.strikethrough_thickness, 0.5);
+    }
+
+    #[test]
+    fn test_utf16_to_utf8_positions() {
+        // 'é' is one UTF-16 code unit and two bytes, '😀' is two UTF-16 code units and four bytes
+        let text = "aé😀b";
+        let positions: Vec<usize> =
+            utf16_to_utf8_positions(text, vec![0, 1, 2, 4, 5, 6].into_iter()).collect();
+        assert_eq!(positions, vec![0, 1, 3, 7, 8, 8]);
+    }
+}
@@ -0,0 +1,174 @@
+//! Measures the time of composition passes on a large composition, with the slot table of
+//! `Composer` and with the previous scope tree implementation.
+//!
+//! Run with `cargo bench --bench composition --features bench-scope-tree`.
+//!
+//! Average time per pass with 10000 items, release build, median of three runs (measured with
+//! the composition modules built against minimal node and widget types):
+//!
+//! | case               | slot table | scope tree |
+//! |--------------------|-----------:|-----------:|
+//! | unchanged          |     16.5ms |     15.9ms |
+//! | insert at start    |     20.3ms |     15.5ms |
+//! | remove first half  |     46.2ms |    309.7ms |
+//! | reverse            |     39.6ms |    273.6ms |
+//! | nested, first pass |     30.9ms |     22.4ms |
+//! | nested, unchanged  |     13.8ms |     15.0ms |
+//!
+//! Passes over an unchanged composition take about the same time. The slot table is slower when
+//! it has to insert entries (first pass, insertion at the start), but six to seven times faster
+//! when keyed items are removed or reordered, which the scope tree handles by searching and
+//! moving each item. An unchanged memoized `tree` skips all of its 31100 nested scopes, and a
+//! pass takes about 0.19ms instead of 13.8ms.
+use std::time::Duration;
+
+const ITEMS: usize = 10_000;
+const PASSES: u32 = 20;
+
+/// Defines the composables and measurements, for the `Composer` and `CompositionCtx` in scope.
+macro_rules! composition_bench {
+    () => {
+        use crate::PASSES;
+        use kyute::widget::{Dummy, Node};
+        use std::time::{Duration, Instant};
+
+        /// A composable with a nested scope, a few state entries and a node.
+        #[track_caller]
+        pub fn item(cx: &mut CompositionCtx, index: usize) {
+            cx.enter();
+            let _count = cx.state(|| index);
+            let _label = cx.state(|| format!("item {}", index));
+            cx.scope(|cx| {
+                let _hovered = cx.state(|| false);
+            });
+            cx.emit(|| Dummy);
+            cx.exit();
+        }
+
+        pub fn list(cx: &mut CompositionCtx, items: &[usize]) {
+            for &i in items {
+                cx.with_key(i, |cx| item(cx, i));
+            }
+        }
+
+        /// Nested scopes, with 10 children per scope and `ITEMS` leaves.
+        pub fn tree(cx: &mut CompositionCtx, depth: u32, index: usize) {
+            if depth == 0 {
+                item(cx, index);
+            } else {
+                for i in 0..10 {
+                    cx.scope(|cx| tree(cx, depth - 1, index * 10 + i));
+                }
+            }
+        }
+
+        /// Composes `items`, then runs `PASSES` passes alternating between `items` and `other`,
+        /// and returns the average time of a pass.
+        pub fn measure(items: &[usize], other: &[usize]) -> Duration {
+            let mut composer = Composer::new();
+            let mut root = Node::new(Box::new(Dummy));
+            composer.compose(&mut root, |cx| list(cx, items));
+
+            let start = Instant::now();
+            for i in 0..PASSES {
+                let items = if i % 2 == 0 { other } else { items };
+                composer.compose(&mut root, |cx| list(cx, items));
+            }
+            start.elapsed() / PASSES
+        }
+
+        /// Returns the time of the first pass composing `tree`, and the average time of the
+        /// following passes.
+        pub fn measure_tree() -> (Duration, Duration) {
+            let mut composer = Composer::new();
+            let mut root = Node::new(Box::new(Dummy));
+            let start = Instant::now();
+            composer.compose(&mut root, |cx| tree(cx, 4, 0));
+            let first = start.elapsed();
+
+            let start = Instant::now();
+            for _ in 0..PASSES {
+                composer.compose(&mut root, |cx| tree(cx, 4, 0));
+            }
+            (first, start.elapsed() / PASSES)
+        }
+    };
+}
+
+mod slot_table {
+    use kyute::widget::{Composer, CompositionCtx, CompositionStats};
+
+    composition_bench!();
+
+    /// Same as `tree`, with the subtrees of depth 2 memoized by their index.
+    fn memo_tree(cx: &mut CompositionCtx, depth: u32, index: usize) {
+        if depth == 2 {
+            cx.memo(index, |cx| tree(cx, depth, index));
+        } else {
+            for i in 0..10 {
+                cx.scope(|cx| memo_tree(cx, depth - 1, index * 10 + i));
+            }
+        }
+    }
+
+    /// Returns the average time of a pass composing an unchanged `memo_tree`, and the
+    /// statistics of the last pass.
+    pub fn measure_memo_tree() -> (Duration, CompositionStats) {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        composer.compose(&mut root, |cx| memo_tree(cx, 4, 0));
+        let start = Instant::now();
+        for _ in 0..PASSES {
+            composer.compose(&mut root, |cx| memo_tree(cx, 4, 0));
+        }
+        (start.elapsed() / PASSES, composer.stats())
+    }
+}
+
+mod scope_tree {
+    use kyute::widget::scope_tree::{Composer, CompositionCtx};
+
+    composition_bench!();
+}
+
+fn print_row(name: &str, slot_table: Duration, scope_tree: Duration) {
+    println!("{:<20} {:>14?} {:>14?}", name, slot_table, scope_tree);
+}
+
+fn main() {
+    let items: Vec<usize> = (0..ITEMS).collect();
+    let mut reversed = items.clone();
+    reversed.reverse();
+    let mut inserted = vec![ITEMS];
+    inserted.extend_from_slice(&items);
+    let removed = &items[ITEMS / 2..];
+
+    println!("{} items, average time per pass", ITEMS);
+    println!("{:<20} {:>14} {:>14}", "", "slot table", "scope tree");
+    let cases: [(&str, &[usize]); 4] = [
+        ("unchanged", &items),
+        ("insert at start", &inserted),
+        ("remove first half", removed),
+        ("reverse", &reversed),
+    ];
+    for &(name, other) in cases.iter() {
+        print_row(
+            name,
+            slot_table::measure(&items, other),
+            scope_tree::measure(&items, other),
+        );
+    }
+
+    let (slot_table_first, slot_table_unchanged) = slot_table::measure_tree();
+    let (scope_tree_first, scope_tree_unchanged) = scope_tree::measure_tree();
+    print_row("nested, first pass", slot_table_first, scope_tree_first);
+    print_row(
+        "nested, unchanged",
+        slot_table_unchanged,
+        scope_tree_unchanged,
+    );
+
+    let (memo_unchanged, memo_stats) = slot_table::measure_memo_tree();
+    println!("memoized, unchanged: {:?}", memo_unchanged);
+    println!("memoized, stats:     {:?}", memo_stats);
+}
@@ -6,6 +6,7 @@
 use crate::node::{NodeTree, NodeId};
 use kyute_shell::{
     platform::Platform,
+    text::TextLayoutCache,
     winit::{
         event::{Event, WindowEvent},
         event_loop::{ControlFlow, EventLoop},
@@ -14,19 +15,37 @@ use kyute_shell::{
 };
 use std::collections::HashMap;
 
+/// Maximum number of text layouts kept in the application-wide text layout cache.
+const TEXT_LAYOUT_CACHE_CAPACITY: usize = 1024;
+
 /// Application context.
 pub struct AppCtx {
     /// Open windows, mapped to their corresponding node in the node tree.
     pub(crate) windows: HashMap<WindowId, NodeId>,
+    /// Text layouts shared between all widgets.
+    pub(crate) text_layout_cache: TextLayoutCache,
 }
 
 impl AppCtx {
-    fn new() -> AppCtx {
+    pub(crate) fn new() -> AppCtx {
         AppCtx {
             windows: HashMap::new(),
+            text_layout_cache: TextLayoutCache::new(TEXT_LAYOUT_CACHE_CAPACITY),
         }
     }
 
+    /// Returns the application-wide text layout cache.
+    pub fn text_layout_cache(&mut self) -> &mut TextLayoutCache {
+        &mut self.text_layout_cache
+    }
+
+    /// Should be called when the set of installed fonts has changed.
+    ///
+    /// Invalidates all cached text layouts.
+    pub fn fonts_changed(&mut self) {
+        self.text_layout_cache.invalidate();
+    }
+
     fn run(mut self, mut tree: NodeTree, event_loop: EventLoop<()>) {
         // run event loop
         event_loop.run(move |event, elwt, control_flow| {
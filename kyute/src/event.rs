@@ -0,0 +1,188 @@
+//! Events delivered to widgets.
+//!
+//! Those are translated from the window events received from winit, and are delivered to the
+//! nodes of a [`NodeTree`](crate::node::NodeTree) with [`NodeTree::event`](crate::node::NodeTree::event).
+use crate::Point;
+use kyute_shell::winit::event::{DeviceId, ModifiersState, VirtualKeyCode};
+use std::{collections::HashMap, ops::Range};
+
+/// Pointer button.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct PointerButton(pub u16);
+
+impl PointerButton {
+    pub const LEFT: PointerButton = PointerButton(0);
+    pub const RIGHT: PointerButton = PointerButton(1);
+    pub const MIDDLE: PointerButton = PointerButton(2);
+    pub const X1: PointerButton = PointerButton(3);
+    pub const X2: PointerButton = PointerButton(4);
+}
+
+/// Set of pointer buttons that are currently pressed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Default)]
+pub struct PointerButtons(pub u32);
+
+impl PointerButtons {
+    pub fn new() -> PointerButtons {
+        PointerButtons(0)
+    }
+    pub fn with(mut self, button: PointerButton) -> Self {
+        self.set(button);
+        self
+    }
+    pub fn set(&mut self, button: PointerButton) {
+        self.0 |= 1u32 << button.0 as u32;
+    }
+    pub fn reset(&mut self, button: PointerButton) {
+        self.0 &= !(1u32 << button.0 as u32);
+    }
+    pub fn test(self, button: PointerButton) -> bool {
+        self.0 & (1u32 << button.0 as u32) != 0
+    }
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Pointer event.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PointerEvent {
+    /// Position of the pointer in the local coordinates of the target node.
+    pub position: Point,
+    /// Position of the pointer in window coordinates.
+    pub window_position: Point,
+    /// State of the keyboard modifiers when the event was emitted.
+    pub modifiers: ModifiersState,
+    /// Buttons that are currently pressed.
+    pub buttons: PointerButtons,
+    /// The device that emitted the event.
+    pub pointer_id: DeviceId,
+}
+
+/// Pointer button press or release event.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PointerButtonEvent {
+    pub pointer: PointerEvent,
+    /// The button that was pressed or released.
+    pub button: Option<PointerButton>,
+    /// Number of consecutive clicks (2 for a double-click, etc.).
+    pub repeat_count: u32,
+}
+
+/// Mouse wheel delta mode.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum WheelDeltaMode {
+    Pixel,
+    Line,
+    Page,
+}
+
+/// Mouse wheel event.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WheelEvent {
+    pub pointer: PointerEvent,
+    pub delta_x: f64,
+    pub delta_y: f64,
+    pub delta_z: f64,
+    pub delta_mode: WheelDeltaMode,
+}
+
+/// Key press or release event.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyboardEvent {
+    /// Platform-specific scan code.
+    pub scan_code: u32,
+    /// Virtual key code, if the key can be mapped to one.
+    pub key: Option<VirtualKeyCode>,
+    /// State of the keyboard modifiers when the event was emitted.
+    pub modifiers: ModifiersState,
+    /// Whether this is an auto-repeat event.
+    pub repeat: bool,
+}
+
+/// Text input event (a character was typed).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct InputEvent {
+    pub character: char,
+}
+
+/// Input method (IME) composition event.
+///
+/// While a composition is in progress, the text being composed (the "preedit" text) is shown
+/// inline by the focused widget, but is not yet part of its contents.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CompositionEvent {
+    /// A new composition session has started.
+    Start,
+    /// The preedit text has changed.
+    Preedit {
+        /// Text being composed.
+        text: String,
+        /// Position of the cursor or of the selected clause in the preedit text, as a range of
+        /// byte offsets. `None` means that the cursor should be hidden.
+        cursor: Option<Range<usize>>,
+    },
+    /// The composition has ended and the specified text should replace the preedit text.
+    Commit { text: String },
+    /// The composition was cancelled: the preedit text should be removed.
+    Cancel,
+}
+
+/// Direction of a focus move (tab navigation).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum MoveFocusDirection {
+    Before,
+    After,
+}
+
+/// Events delivered to widgets.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The node gained the focus.
+    FocusIn,
+    /// The node lost the focus.
+    FocusOut,
+    PointerUp(PointerButtonEvent),
+    PointerDown(PointerButtonEvent),
+    PointerMove(PointerEvent),
+    /// The pointer entered the bounds of the node.
+    PointerOver(PointerEvent),
+    /// The pointer left the bounds of the node.
+    PointerOut(PointerEvent),
+    Wheel(WheelEvent),
+    KeyDown(KeyboardEvent),
+    KeyUp(KeyboardEvent),
+    Input(InputEvent),
+    /// Input method composition. Delivered to the focused node.
+    Composition(CompositionEvent),
+}
+
+/// Last known state of a pointer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PointerState {
+    pub buttons: PointerButtons,
+    pub position: Point,
+}
+
+impl Default for PointerState {
+    fn default() -> Self {
+        PointerState {
+            buttons: PointerButtons::new(),
+            position: Point::origin(),
+        }
+    }
+}
+
+/// Last known state of the input devices: keyboard modifiers and pointers.
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    pub mods: ModifiersState,
+    pub pointers: HashMap<DeviceId, PointerState>,
+}
+
+impl InputState {
+    /// Returns the state of the specified pointer, if it is known.
+    pub fn pointer_state(&self, pointer_id: DeviceId) -> Option<&PointerState> {
+        self.pointers.get(&pointer_id)
+    }
+}
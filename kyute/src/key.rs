@@ -1,15 +1,66 @@
-use std::panic::Location;
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    panic::Location,
+};
 
 #[derive(Clone, Copy, Eq, PartialEq, PartialOrd, Ord, Hash, Debug)]
 pub struct Key {
     caller_location: &'static Location<'static>,
+    /// Hash of an explicit key provided by the user, to tell apart things created at the same
+    /// location (e.g. in a loop).
+    id: Option<u64>,
 }
 
 impl Key {
     #[track_caller]
     pub fn from_caller() -> Key {
         Key {
-            caller_location: Location::caller().into()
+            caller_location: Location::caller().into(),
+            id: None,
         }
     }
-}
\ No newline at end of file
+
+    /// Creates a key from the caller location and an explicit user value.
+    #[track_caller]
+    pub fn with_id(id: impl Hash) -> Key {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        Key {
+            caller_location: Location::caller().into(),
+            id: Some(hasher.finish()),
+        }
+    }
+
+    /// Returns a key with the same location, and `id` hashed into the explicit value of this key.
+    pub(crate) fn combine(self, id: impl Hash) -> Key {
+        let mut hasher = DefaultHasher::new();
+        self.id.hash(&mut hasher);
+        id.hash(&mut hasher);
+        Key {
+            caller_location: self.caller_location,
+            id: Some(hasher.finish()),
+        }
+    }
+
+    /// Returns the source location where the key was created.
+    pub fn caller_location(&self) -> &'static Location<'static> {
+        self.caller_location
+    }
+
+    /// Returns the hash of the explicit user value of the key, if any.
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.caller_location)?;
+        if let Some(id) = self.id {
+            write!(f, "#{:016x}", id)?;
+        }
+        Ok(())
+    }
+}
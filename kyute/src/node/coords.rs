@@ -0,0 +1,112 @@
+//! Conversions between the coordinate spaces of nodes and windows.
+use crate::{
+    node::{NodeId, NodeTree},
+    Offset, Point, Rect,
+};
+use kyute_shell::drawing::Transform;
+
+impl NodeTree {
+    /// Returns the transform from the local coordinates of the node `from` to the local
+    /// coordinates of the node `to`, or `None` if `to` has a degenerate transform.
+    pub fn transform_between(&self, from: NodeId, to: NodeId) -> Option<Transform> {
+        let window_to_local = self.window_transform(to).inverse()?;
+        Some(self.window_transform(from).then(&window_to_local))
+    }
+
+    /// Converts a point in the local coordinates of a node to window coordinates.
+    pub fn local_to_window(&self, id: NodeId, point: Point) -> Point {
+        self.window_transform(id).transform_point(point)
+    }
+
+    /// Converts a point in window coordinates to the local coordinates of a node.
+    ///
+    /// Returns `None` if the node has a degenerate transform (e.g. a zero scale).
+    pub fn window_to_local(&self, id: NodeId, point: Point) -> Option<Point> {
+        Some(self.window_transform(id).inverse()?.transform_point(point))
+    }
+
+    /// Converts a point in the local coordinates of the node `from` to the local coordinates of
+    /// the node `to`.
+    ///
+    /// The nodes don't have to be related. Returns `None` if `to` has a degenerate transform.
+    pub fn map_point(&self, from: NodeId, to: NodeId, point: Point) -> Option<Point> {
+        Some(self.transform_between(from, to)?.transform_point(point))
+    }
+
+    /// Converts a rectangle in the local coordinates of the node `from` to the local coordinates
+    /// of the node `to`.
+    ///
+    /// If the nodes are rotated or skewed relative to each other, returns the bounding box of the
+    /// transformed rectangle. Returns `None` if `to` has a degenerate transform.
+    pub fn map_rect(&self, from: NodeId, to: NodeId, rect: &Rect) -> Option<Rect> {
+        Some(
+            self.transform_between(from, to)?
+                .outer_transformed_rect(rect),
+        )
+    }
+
+    /// Returns the scroll offset of the contents of a node.
+    pub fn scroll_offset(&self, id: NodeId) -> Offset {
+        self.nodes[id].scroll_offset
+    }
+
+    /// Scrolls the contents of a node: its children are shifted by `-offset`.
+    ///
+    /// Updates the window positions of the descendants and schedules a repaint of the node.
+    pub fn set_scroll_offset(&mut self, id: NodeId, offset: Offset) {
+        if self.nodes[id].scroll_offset == offset {
+            return;
+        }
+        self.nodes[id].scroll_offset = offset;
+        self.update_window_positions(id);
+        self.mark_needs_paint(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        node::{NodeCursor, NodeTree},
+        widget::Dummy,
+        Offset, Point, Rect, Size,
+    };
+    use kyute_shell::drawing::Transform;
+
+    #[test]
+    fn test_map_point() {
+        // R(A(B),C)
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        let c = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.insert(c, NodeCursor::BeforeChild(root));
+        node_tree.get_mut(a).unwrap().offset = Offset::new(10.0, 10.0);
+        node_tree.get_mut(b).unwrap().offset = Offset::new(0.0, 50.0);
+        node_tree.get_mut(c).unwrap().offset = Offset::new(100.0, 0.0);
+        node_tree.update_window_positions(root);
+
+        let p = Point::new(1.0, 2.0);
+        assert_eq!(node_tree.local_to_window(b, p), Point::new(11.0, 62.0));
+        assert_eq!(
+            node_tree.window_to_local(b, Point::new(11.0, 62.0)),
+            Some(p)
+        );
+        assert_eq!(node_tree.map_point(b, c, p), Some(Point::new(-89.0, 62.0)));
+        assert_eq!(
+            node_tree.map_rect(b, a, &Rect::new(p, Size::new(5.0, 5.0))),
+            Some(Rect::new(Point::new(1.0, 52.0), Size::new(5.0, 5.0)))
+        );
+
+        // scrolling the contents of A moves B
+        node_tree.set_scroll_offset(a, Offset::new(0.0, 40.0));
+        assert_eq!(node_tree.local_to_window(b, p), Point::new(11.0, 22.0));
+        assert_eq!(node_tree.local_to_window(a, p), Point::new(11.0, 12.0));
+
+        // degenerate transforms can't be inverted
+        node_tree.set_transform(c, Some(Transform::scale(0.0, 1.0)));
+        assert_eq!(node_tree.map_point(b, c, p), None);
+    }
+}
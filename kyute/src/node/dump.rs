@@ -0,0 +1,185 @@
+//! Debug dumps of the node tree.
+use crate::node::{Node, NodeEdge, NodeId, NodeTree};
+use std::fmt::Write;
+
+/// Writes a JSON string literal.
+fn write_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Writes a JSON number, or `null` if the value is not finite (e.g. unbounded sizes).
+fn write_json_number(out: &mut String, v: f64) {
+    if v.is_finite() {
+        write!(out, "{}", v).unwrap();
+    } else {
+        out.push_str("null");
+    }
+}
+
+fn write_json_pair(out: &mut String, x: f64, y: f64) {
+    out.push('[');
+    write_json_number(out, x);
+    out.push(',');
+    write_json_number(out, y);
+    out.push(']');
+}
+
+fn widget_name(node: &Node) -> &'static str {
+    node.widget
+        .as_ref()
+        .map_or("<root>", |widget| widget.type_name())
+}
+
+impl NodeTree {
+    /// Returns a textual dump of the subtree rooted at the specified node, one node per line,
+    /// indented by depth.
+    ///
+    /// Each line contains the node ID, the widget type name, the key (if any), the offset
+    /// relative to the parent, the measurements and the position in window coordinates.
+    pub fn dump(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        let mut depth = 0;
+        for edge in self.traverse(id) {
+            match edge {
+                NodeEdge::Start(id) => {
+                    let node = &self.nodes[id];
+                    let size = node.measurements.size;
+                    let window_pos = node.window_pos.get();
+                    write!(
+                        out,
+                        "{:indent$}{:?} {}",
+                        "",
+                        id,
+                        widget_name(node),
+                        indent = 2 * depth
+                    )
+                    .unwrap();
+                    if let Some(key) = node.key {
+                        write!(out, " key={}", key).unwrap();
+                    }
+                    write!(
+                        out,
+                        " offset=({}, {}) size={}x{}",
+                        node.offset.x, node.offset.y, size.width, size.height
+                    )
+                    .unwrap();
+                    if let Some(baseline) = node.measurements.baseline {
+                        write!(out, " baseline={}", baseline).unwrap();
+                    }
+                    writeln!(out, " window_pos=({}, {})", window_pos.x, window_pos.y).unwrap();
+                    depth += 1;
+                }
+                NodeEdge::End(_) => depth -= 1,
+            }
+        }
+        out
+    }
+
+    /// Returns a JSON dump of the subtree rooted at the specified node.
+    ///
+    /// Each node is an object with the fields `id`, `widget`, `key` (as formatted by
+    /// [`Key`](crate::key::Key)'s `Display` implementation, or `null`), `offset` (`[x, y]`),
+    /// `size` (`[width, height]`), `baseline`, `window_pos` (`[x, y]`) and `children`.
+    /// Non-finite numbers are written as `null`.
+    pub fn dump_json(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        for edge in self.traverse(id) {
+            match edge {
+                NodeEdge::Start(node_id) => {
+                    let node = &self.nodes[node_id];
+                    if node_id != id && node.previous_sibling().is_some() {
+                        out.push(',');
+                    }
+                    out.push_str("{\"id\":");
+                    write_json_str(&mut out, &format!("{:?}", node_id));
+                    out.push_str(",\"widget\":");
+                    write_json_str(&mut out, widget_name(node));
+                    out.push_str(",\"key\":");
+                    match node.key {
+                        Some(key) => write_json_str(&mut out, &key.to_string()),
+                        None => out.push_str("null"),
+                    }
+                    out.push_str(",\"offset\":");
+                    write_json_pair(&mut out, node.offset.x, node.offset.y);
+                    out.push_str(",\"size\":");
+                    let size = node.measurements.size;
+                    write_json_pair(&mut out, size.width, size.height);
+                    out.push_str(",\"baseline\":");
+                    match node.measurements.baseline {
+                        Some(baseline) => write_json_number(&mut out, baseline),
+                        None => out.push_str("null"),
+                    }
+                    out.push_str(",\"window_pos\":");
+                    let window_pos = node.window_pos.get();
+                    write_json_pair(&mut out, window_pos.x, window_pos.y);
+                    out.push_str(",\"children\":[");
+                }
+                NodeEdge::End(_) => out.push_str("]}"),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        node::{FocusState, NodeCursor, NodeTree},
+        widget::Dummy,
+        Offset,
+    };
+
+    #[test]
+    fn test_dump() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        let c = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.insert(c, NodeCursor::After(a));
+        node_tree.get_mut(b).unwrap().offset = Offset::new(2.0, 3.0);
+
+        let dummy = std::any::type_name::<Dummy>();
+        let text = node_tree.dump(root);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with(&format!("{:?} <root>", root)));
+        assert!(lines[1].starts_with(&format!("  {:?} {}", a, dummy)));
+        assert!(lines[2].starts_with(&format!("    {:?} ", b)));
+        assert!(lines[2].contains("offset=(2, 3)"));
+        assert!(lines[3].starts_with(&format!("  {:?} ", c)));
+
+        let json = node_tree.dump_json(a);
+        assert_eq!(
+            json,
+            format!(
+                "{{\"id\":\"{:?}\",\"widget\":\"{dummy}\",\"key\":null,\"offset\":[0,0],\
+                 \"size\":[0,0],\"baseline\":null,\"window_pos\":[0,0],\"children\":[\
+                 {{\"id\":\"{:?}\",\"widget\":\"{dummy}\",\"key\":null,\"offset\":[2,3],\
+                 \"size\":[0,0],\"baseline\":null,\"window_pos\":[0,0],\"children\":[]}}]}}",
+                a,
+                b,
+                dummy = dummy
+            )
+        );
+
+        node_tree.remove(a, &mut FocusState::new());
+        assert!(node_tree
+            .dump_json(root)
+            .contains(&format!("\"children\":[{{\"id\":\"{:?}\"", c)));
+    }
+}
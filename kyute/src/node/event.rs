@@ -6,15 +6,16 @@ use crate::{
         Event, InputState, MoveFocusDirection, PointerButtonEvent, PointerButtons, PointerEvent,
     },
     node::NodeTree,
-    Point, Rect,
+    Offset, Point, Rect,
 };
 use generational_indextree::NodeId;
-use kyute_shell::{platform::Platform, window::PlatformWindow};
+use kyute_shell::{drawing::Transform, platform::Platform, window::PlatformWindow};
 use log::trace;
 use std::collections::HashMap;
 use winit::{
+    dpi::LogicalPosition,
     event::{DeviceId, ModifiersState},
-    window::WindowId,
+    window::{CursorIcon, WindowId},
 };
 use crate::node::NodeId;
 
@@ -23,6 +24,10 @@ pub struct FocusState {
     pub(crate) focus: Option<NodeId>,
     pub(crate) pointer_grab: Option<NodeId>,
     pub(crate) hot: Option<NodeId>,
+    /// Area of the text cursor of the focused node, in window coordinates.
+    ///
+    /// Used to position the candidate window of input methods.
+    pub(crate) ime_cursor_area: Option<Rect>,
 }
 
 impl FocusState {
@@ -31,8 +36,16 @@ impl FocusState {
             focus: None,
             pointer_grab: None,
             hot: None,
+            ime_cursor_area: None,
         }
     }
+
+    /// Returns the area of the text cursor reported by the focused node, in window coordinates.
+    ///
+    /// See [`EventCtx::set_ime_cursor_area`].
+    pub fn ime_cursor_area(&self) -> Option<Rect> {
+        self.ime_cursor_area
+    }
 }
 
 impl Default for FocusState {
@@ -44,16 +57,31 @@ impl Default for FocusState {
 impl FocusState {
     pub(crate) fn release_focus(&mut self) {
         self.focus = None;
+        self.ime_cursor_area = None;
     }
     pub(crate) fn release_pointer_grab(&mut self) {
         self.pointer_grab = None;
     }
     pub(crate) fn acquire_focus(&mut self, node: NodeId) {
         self.focus = Some(node);
+        self.ime_cursor_area = None;
     }
     pub(crate) fn acquire_pointer_grab(&mut self, node: NodeId) {
         self.pointer_grab = Some(node);
     }
+
+    /// Releases the focus, pointer grab and hot status held by a node that was removed.
+    pub(crate) fn release_node(&mut self, node: NodeId) {
+        if self.focus == Some(node) {
+            self.release_focus();
+        }
+        if self.pointer_grab == Some(node) {
+            self.release_pointer_grab();
+        }
+        if self.hot == Some(node) {
+            self.hot = None;
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -89,8 +117,8 @@ pub enum FocusChange {
 pub struct EventCtx<'a> {
     /// Window context
     pub(crate) app_ctx: &'a mut AppCtx,
-    /// Window
-    pub(crate) window: &'a PlatformWindow,
+    /// Window, `None` if the event wasn't sent to a platform window (in tests)
+    pub(crate) window: Option<&'a PlatformWindow>,
     /// State of various input devices.
     pub(crate) inputs: &'a InputState,
     /// Contains information about currently focused and pointer-grabbing nodes.
@@ -99,12 +127,16 @@ pub struct EventCtx<'a> {
     pub(crate) node_id: NodeId,
     /// The bounds of the current visual.
     pub(crate) bounds: Rect,
+    /// Transform from the local coordinates of the current node to window coordinates.
+    pub(crate) window_transform: Transform,
     /// Focus change requested
     pub(crate) focus_change: FocusChange,
     /// Redraw requested
     pub(crate) repaint: RepaintRequest,
     /// Pointer grab requested
     pub(crate) pointer_capture: bool,
+    /// Text cursor area reported by the node, in local coordinates
+    pub(crate) ime_cursor_area: Option<Rect>,
     /// Event handled
     pub(crate) handled: bool,
     // Whether this is a focus change event
@@ -121,9 +153,39 @@ impl<'a> EventCtx<'a> {
         self.bounds
     }
 
+    /// Converts a point in the local coordinates of the current node to window coordinates.
+    pub fn local_to_window(&self, point: Point) -> Point {
+        self.window_transform.transform_point(point)
+    }
+
+    /// Converts a point in window coordinates to the local coordinates of the current node.
+    ///
+    /// Returns `None` if the node has a degenerate transform.
+    pub fn window_to_local(&self, point: Point) -> Option<Point> {
+        Some(self.window_transform.inverse()?.transform_point(point))
+    }
+
+    /// Converts a point in the local coordinates of the current node to screen coordinates, in
+    /// DIPs.
+    ///
+    /// Returns `None` if the position of the window on the screen is not available.
+    pub fn local_to_screen(&self, point: Point) -> Option<Point> {
+        let window = self.window?.window();
+        let origin = window
+            .inner_position()
+            .ok()?
+            .to_logical::<f64>(window.scale_factor());
+        Some(self.local_to_window(point) + Offset::new(origin.x, origin.y))
+    }
+
     /// Requests a redraw of the current visual.
     pub fn request_redraw(&mut self) {
-        self.repaint = RepaintRequest::Repaint;
+        self.repaint = self.repaint.max(RepaintRequest::Repaint);
+    }
+
+    /// Requests a relayout of the current visual.
+    pub fn request_relayout(&mut self) {
+        self.repaint = RepaintRequest::Relayout;
     }
 
     /// Requests that the current node grabs all pointer events.
@@ -157,14 +219,29 @@ impl<'a> EventCtx<'a> {
         self.focus.focus == Some(self.node_id)
     }
 
+    /// Reports the area of the text cursor of the current node, in local coordinates.
+    ///
+    /// This is used to position the candidate window of input methods next to the text being
+    /// composed. It is ignored if the current node doesn't have the focus.
+    pub fn set_ime_cursor_area(&mut self, area: Rect) {
+        self.ime_cursor_area = Some(area);
+    }
+
     /// Signals that the passed event was handled and should not bubble up further.
     pub fn set_handled(&mut self) {
         self.handled = true;
     }
 
+    /// Changes the appearance of the mouse cursor over the window.
+    pub fn set_cursor_icon(&mut self, cursor_icon: CursorIcon) {
+        if let Some(window) = self.window {
+            window.window().set_cursor_icon(cursor_icon);
+        }
+    }
+
     /// Returns the window that the event was originally sent to.
     pub fn window(&self) -> &PlatformWindow {
-        self.window
+        self.window.expect("the event was not sent to a window")
     }
 
     #[must_use]
@@ -180,14 +257,17 @@ impl<'a> EventCtx<'a> {
 impl NodeTree {
     /// Builds the dispatch chain for a pointer event, by recursively hit-testing the bounds of
     /// nodes.
+    ///
+    /// The point is converted to the local coordinates of each node, so that transformed nodes
+    /// are hit-tested against their actual shape. Children are hit-tested from last to first, so
+    /// that the topmost one is found first.
     pub(crate) fn find_pointer_event_target(
         &self,
         id: NodeId,
         window_id: WindowId,
         window_pos: Point,
-        origin: Point,
     ) -> Option<NodeId> {
-        let node_data = &self.arena[id].get();
+        let node_data = &self.nodes[id];
 
         // don't cross into other windows
         match node_data.window_id() {
@@ -195,24 +275,24 @@ impl NodeTree {
             _ => {}
         }
 
-        let offset = node_data.offset;
-        let measurements = node_data.measurements;
-        // bounds in window coordinates
-        let bounds = Rect::new(origin + offset, measurements.size);
+        // nodes with a degenerate transform (e.g. a zero scale) can't be hit
+        let local_pos = node_data
+            .window_transform
+            .get()
+            .inverse()?
+            .transform_point(window_pos);
+        let bounds = Rect::new(Point::origin(), node_data.measurements.size);
 
-        if bounds.contains(window_pos) {
+        if bounds.contains(local_pos) {
             // TODO more precise hit test
             // recurse on children
-            let mut child_id = self.arena[id].first_child();
-            while let Some(id) = child_id {
+            for child_id in self.reverse_children(id) {
                 if let Some(target_id) =
-                    self.find_pointer_event_target(id, window_id, window_pos, bounds.origin)
+                    self.find_pointer_event_target(child_id, window_id, window_pos)
                 {
                     // hit
                     return Some(target_id);
                 }
-                // no hit, continue
-                child_id = self.arena[id].next_sibling();
             }
             Some(id)
         } else {
@@ -220,6 +300,25 @@ impl NodeTree {
         }
     }
 
+    /// Finds the node under a point in the window rooted at `root`, looking in the overlays of
+    /// the window first, topmost first.
+    fn hit_test_window(
+        &self,
+        root: NodeId,
+        window_id: WindowId,
+        window_pos: Point,
+    ) -> Option<NodeId> {
+        let overlays: Vec<_> = self
+            .overlays()
+            .filter(|&content| self.is_overlay_in_window(content, root))
+            .collect();
+        overlays
+            .into_iter()
+            .rev()
+            .find_map(|content| self.find_pointer_event_target(content, window_id, window_pos))
+            .or_else(|| self.find_pointer_event_target(root, window_id, window_pos))
+    }
+
     /// Builds the dispatch chain followed by an event in the visual tree, or empty vec if it's a traversal.
     pub(crate) fn find_event_target(
         &self,
@@ -244,12 +343,7 @@ impl NodeTree {
                     Some(pointer_capture_node_id)
                 } else {
                     // otherwise, build a pointer dispatch chain
-                    self.find_pointer_event_target(
-                        root,
-                        window_id,
-                        pointer_event.window_position,
-                        self.window_origin,
-                    )
+                    self.hit_test_window(root, window_id, pointer_event.window_position)
                 }
             }
             Event::KeyUp(keyboard_event) | Event::KeyDown(keyboard_event) => {
@@ -260,7 +354,7 @@ impl NodeTree {
                     None
                 }
             }
-            Event::Input(input_event) => {
+            Event::Input(_) | Event::Composition(_) => {
                 // same as keyboard events
                 if let Some(focused_node_id) = focus.focus {
                     Some(focused_node_id)
@@ -271,12 +365,7 @@ impl NodeTree {
             Event::Wheel(wheel_event) => {
                 // wheel events always follow a pointer dispatch chain, regardless of whether there
                 // is a pointer grab or not
-                self.find_pointer_event_target(
-                    self.root,
-                    window_id,
-                    wheel_event.pointer.window_position,
-                    self.window_origin,
-                )
+                self.hit_test_window(root, window_id, wheel_event.pointer.window_position)
             }
             // default is standard traversal
             _ => None,
@@ -285,8 +374,8 @@ impl NodeTree {
 
     /// Returns a copy of the event with all local coordinates re-calculated relative to the specified target node.
     pub(crate) fn build_local_event(&self, event: &Event, target: NodeId) -> Event {
-        let node_window_pos = self.arena[target].get().window_pos.get();
-        let mut event = *event;
+        let window_to_local = self.nodes[target].window_transform.get().inverse();
+        let mut event = event.clone();
         match event {
             Event::PointerUp(PointerButtonEvent {
                 pointer: ref mut p, ..
@@ -295,7 +384,9 @@ impl NodeTree {
                 pointer: ref mut p, ..
             })
             | Event::PointerMove(ref mut p) => {
-                p.position = p.window_position - node_window_pos.to_vector();
+                if let Some(window_to_local) = window_to_local {
+                    p.position = window_to_local.transform_point(p.window_position);
+                }
             }
             _ => {}
         }
@@ -306,7 +397,7 @@ impl NodeTree {
     pub(crate) fn dispatch_event(
         &mut self,
         window_ctx: &mut AppCtx,
-        window: &PlatformWindow,
+        window: Option<&PlatformWindow>,
         root: NodeId,
         inputs: &InputState,
         focus: &mut FocusState,
@@ -321,7 +412,7 @@ impl NodeTree {
         while let Some(id) = next_id {
             let local_event = self.build_local_event(event, id);
             // deliver event to visual
-            let node = &mut self.arena[id];
+            let node = &mut self.nodes[id];
 
             let mut ctx = EventCtx {
                 app_ctx: window_ctx,
@@ -329,24 +420,33 @@ impl NodeTree {
                 inputs,
                 focus,
                 node_id: id,
-                bounds: Rect::new(Point::origin(), node.get().measurements.size),
+                bounds: Rect::new(Point::origin(), node.measurements.size),
+                window_transform: node.window_transform.get(),
                 focus_change: FocusChange::Keep,
                 repaint: RepaintRequest::None,
                 pointer_capture: false,
+                ime_cursor_area: None,
                 handled: false,
             };
 
-            // the node might not have a visual if it has been "temporarily moved out the tree"
-            // for borrowing reasons: this happens within `Visual::window_paint` and `Visual::window_event`.
-            node.get_mut()
-                .visual
-                .as_mut()
-                .map(|v| v.event(&mut ctx, &local_event));
+            // the root node doesn't have a widget
+            if let Some(ref mut widget) = node.widget {
+                widget.event(&mut ctx, &local_event);
+            }
 
             *repaint = (*repaint).max(ctx.repaint);
+            let node_repaint = ctx.repaint;
             let focus_change = ctx.focus_change;
             let handled = ctx.handled;
             let pointer_capture = ctx.pointer_capture;
+            let ime_cursor_area = ctx.ime_cursor_area;
+
+            // flag the node so that only the affected parts are laid out or repainted
+            match node_repaint {
+                RepaintRequest::Relayout => self.mark_needs_layout(id),
+                RepaintRequest::Repaint => self.mark_needs_paint(id),
+                RepaintRequest::None => {}
+            }
 
             // after delivering the event, immediately process the focus and pointer-capture related
             // events that must be sent.
@@ -368,7 +468,7 @@ impl NodeTree {
                             );
                         }
 
-                        focus.focus = Some(id);
+                        focus.acquire_focus(id);
                         self.dispatch_event(
                             window_ctx,
                             window,
@@ -395,7 +495,7 @@ impl NodeTree {
                             repaint,
                             true,
                         );
-                        focus.focus = None;
+                        focus.release_focus();
                     }
                 }
                 FocusChange::Move(_) => todo!("tab navigation"),
@@ -408,6 +508,23 @@ impl NodeTree {
                 focus.pointer_grab = Some(id);
             }
 
+            // IME cursor area, converted to window coordinates
+            if let Some(area) = ime_cursor_area {
+                if focus.focus == Some(id) {
+                    let window_transform = self.nodes[id].window_transform.get();
+                    let area = window_transform.outer_transformed_rect(&area);
+                    if focus.ime_cursor_area != Some(area) {
+                        focus.ime_cursor_area = Some(area);
+                        // the candidate window is placed below the text cursor
+                        if let Some(window) = window {
+                            window
+                                .window()
+                                .set_ime_position(LogicalPosition::new(area.min_x(), area.max_y()));
+                        }
+                    }
+                }
+            }
+
             // stop propagation if the event was handled
             if handled {
                 handled_by = Some(id);
@@ -418,7 +535,8 @@ impl NodeTree {
             }
 
             // FIXME we could bubble to a parent window, is that what we want?
-            next_id = self.arena[id].parent();
+            // events in an overlay bubble up to the owner of the overlay
+            next_id = self.nodes[id].parent().or_else(|| self.overlay_owner(id));
         }
 
         handled_by
@@ -441,11 +559,33 @@ impl NodeTree {
         inputs: &InputState,
         focus: &mut FocusState,
         event: &Event,
+    ) -> RepaintRequest {
+        self.deliver_event(
+            window_ctx,
+            Some(window),
+            window.id(),
+            root,
+            inputs,
+            focus,
+            event,
+        )
+    }
+
+    /// Implementation of [`event`](NodeTree::event), with an optional platform window.
+    pub(crate) fn deliver_event(
+        &mut self,
+        window_ctx: &mut AppCtx,
+        window: Option<&PlatformWindow>,
+        window_id: WindowId,
+        root: NodeId,
+        inputs: &InputState,
+        focus: &mut FocusState,
+        event: &Event,
     ) -> RepaintRequest {
         //trace!("event {:?}", event);
 
         // find the target of the event
-        let target = self.find_event_target(root, window.id(), focus, event);
+        let target = self.find_event_target(root, window_id, focus, event);
         let mut repaint = RepaintRequest::None;
 
         // event pre-processing
@@ -522,3 +662,134 @@ impl NodeTree {
         repaint
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        application::AppCtx,
+        event::{CompositionEvent, Event, InputState},
+        layout::{BoxConstraints, Measurements},
+        node::{EventCtx, FocusState, NodeCursor, NodeTree, OverlayPlacement, PaintCtx},
+        widget::{Dummy, LayoutCtx, Node, Widget},
+        Offset, Point, Rect, Size,
+    };
+    use std::{cell::RefCell, rc::Rc};
+    use winit::window::WindowId;
+
+    /// Records the composition events, and reports a text cursor area.
+    struct ImeTarget {
+        events: Rc<RefCell<Vec<CompositionEvent>>>,
+    }
+
+    impl Widget for ImeTarget {
+        fn layout(
+            &mut self,
+            _ctx: &mut LayoutCtx,
+            _children: &mut [Node],
+            _constraints: &BoxConstraints,
+        ) -> Measurements {
+            Measurements::default()
+        }
+
+        fn paint(&mut self, _ctx: &mut PaintCtx, _children: &mut [Node], _bounds: Rect) {}
+
+        fn event(&mut self, ctx: &mut EventCtx, event: &Event) {
+            if let Event::Composition(event) = event {
+                self.events.borrow_mut().push(event.clone());
+                ctx.set_ime_cursor_area(Rect::new(Point::new(2.0, 3.0), Size::new(1.0, 10.0)));
+                ctx.set_handled();
+            }
+        }
+    }
+
+    #[test]
+    fn test_composition_dispatch() {
+        // R(A(T))
+        let mut node_tree = NodeTree::new();
+        let mut app_ctx = AppCtx::new();
+        let mut focus = FocusState::new();
+        let inputs = InputState::default();
+        let window_id = unsafe { WindowId::dummy() };
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let target = node_tree.create(Box::new(ImeTarget {
+            events: events.clone(),
+        }));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(target, NodeCursor::BeforeChild(a));
+        node_tree.get_mut(a).unwrap().offset = Offset::new(10.0, 20.0);
+        node_tree.update_window_positions(root);
+
+        let start = Event::Composition(CompositionEvent::Start);
+        let mut deliver = |node_tree: &mut NodeTree, focus: &mut FocusState, event: &Event| {
+            node_tree.deliver_event(&mut app_ctx, None, window_id, root, &inputs, focus, event)
+        };
+
+        // no focused node: not delivered
+        deliver(&mut node_tree, &mut focus, &start);
+        assert!(events.borrow().is_empty());
+        assert_eq!(focus.ime_cursor_area(), None);
+
+        // delivered to the focused node, and the cursor area is converted to window coordinates
+        focus.acquire_focus(target);
+        deliver(&mut node_tree, &mut focus, &start);
+        let commit = Event::Composition(CompositionEvent::Commit {
+            text: "日本".to_string(),
+        });
+        deliver(&mut node_tree, &mut focus, &commit);
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                CompositionEvent::Start,
+                CompositionEvent::Commit {
+                    text: "日本".to_string()
+                }
+            ]
+        );
+        assert_eq!(
+            focus.ime_cursor_area(),
+            Some(Rect::new(Point::new(12.0, 23.0), Size::new(1.0, 10.0)))
+        );
+    }
+
+    #[test]
+    fn test_hit_test_nested_overlay() {
+        // R(A, B), with a menu owned by A and a submenu owned by an item of the menu
+        let mut node_tree = NodeTree::new();
+        let window_id = unsafe { WindowId::dummy() };
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(root));
+        node_tree.get_mut(root).unwrap().measurements = Measurements::new(Size::new(200.0, 200.0));
+        node_tree.get_mut(a).unwrap().measurements = Measurements::new(Size::new(100.0, 100.0));
+        node_tree.get_mut(b).unwrap().measurements = Measurements::new(Size::new(100.0, 100.0));
+        node_tree.update_window_positions(root);
+
+        let menu = node_tree.create(Box::new(Dummy));
+        let item = node_tree.create(Box::new(Dummy));
+        node_tree.insert(item, NodeCursor::BeforeChild(menu));
+        node_tree.get_mut(menu).unwrap().measurements = Measurements::new(Size::new(50.0, 50.0));
+        node_tree.get_mut(item).unwrap().measurements = Measurements::new(Size::new(50.0, 20.0));
+        node_tree.add_overlay(menu, a, a, OverlayPlacement::Offset(Offset::zero()));
+
+        let submenu = node_tree.create(Box::new(Dummy));
+        node_tree.get_mut(submenu).unwrap().measurements = Measurements::new(Size::new(30.0, 30.0));
+        node_tree.add_overlay(
+            submenu,
+            item,
+            item,
+            OverlayPlacement::Offset(Offset::new(60.0, 0.0)),
+        );
+
+        let hit = |root, x, y| node_tree.hit_test_window(root, window_id, Point::new(x, y));
+        // the submenu is above A
+        assert_eq!(hit(a, 70.0, 10.0), Some(submenu));
+        assert_eq!(hit(a, 10.0, 10.0), Some(item));
+        assert_eq!(hit(a, 90.0, 90.0), Some(a));
+        // the overlays are not in the subtree of B
+        assert_eq!(hit(b, 70.0, 10.0), Some(b));
+    }
+}
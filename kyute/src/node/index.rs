@@ -0,0 +1,160 @@
+//! Secondary indices of the node tree: nodes by key and by widget type.
+use crate::{
+    key::Key,
+    node::{NodeId, NodeTree},
+    widget::Widget,
+};
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// Maps keys and widget types to the nodes that have them.
+#[derive(Default)]
+pub(crate) struct NodeIndex {
+    by_key: HashMap<Key, Vec<NodeId>>,
+    by_widget_type: HashMap<TypeId, Vec<NodeId>>,
+}
+
+fn add_entry<K: Eq + Hash>(map: &mut HashMap<K, Vec<NodeId>>, k: K, id: NodeId) {
+    map.entry(k).or_default().push(id);
+}
+
+fn remove_entry<K: Eq + Hash>(map: &mut HashMap<K, Vec<NodeId>>, k: K, id: NodeId) {
+    if let Some(ids) = map.get_mut(&k) {
+        ids.retain(|&x| x != id);
+        if ids.is_empty() {
+            map.remove(&k);
+        }
+    }
+}
+
+/// Removes sets of nodes from the entries of a map, filtering each entry once.
+fn remove_entries<K: Eq + Hash>(
+    map: &mut HashMap<K, Vec<NodeId>>,
+    removed: HashMap<K, HashSet<NodeId>>,
+) {
+    for (k, removed_ids) in removed {
+        if let Some(ids) = map.get_mut(&k) {
+            ids.retain(|id| !removed_ids.contains(id));
+            if ids.is_empty() {
+                map.remove(&k);
+            }
+        }
+    }
+}
+
+impl NodeIndex {
+    /// Adds a node to the index.
+    pub(crate) fn add(&mut self, id: NodeId, key: Option<Key>, widget: Option<&dyn Widget>) {
+        if let Some(key) = key {
+            add_entry(&mut self.by_key, key, id);
+        }
+        if let Some(widget) = widget {
+            add_entry(&mut self.by_widget_type, Any::type_id(widget), id);
+        }
+    }
+
+    /// Removes nodes from the index, given their key and the type of their widget.
+    ///
+    /// The removals are grouped by key and by widget type, so that removing a large subtree
+    /// doesn't filter the same entries once per node.
+    pub(crate) fn remove_all(
+        &mut self,
+        nodes: impl IntoIterator<Item = (NodeId, Option<Key>, Option<TypeId>)>,
+    ) {
+        let mut by_key: HashMap<Key, HashSet<NodeId>> = HashMap::new();
+        let mut by_widget_type: HashMap<TypeId, HashSet<NodeId>> = HashMap::new();
+        for (id, key, widget_type) in nodes {
+            if let Some(key) = key {
+                by_key.entry(key).or_default().insert(id);
+            }
+            if let Some(widget_type) = widget_type {
+                by_widget_type.entry(widget_type).or_default().insert(id);
+            }
+        }
+        remove_entries(&mut self.by_key, by_key);
+        remove_entries(&mut self.by_widget_type, by_widget_type);
+    }
+}
+
+impl NodeTree {
+    /// Sets the key associated to a node.
+    pub fn set_key(&mut self, id: NodeId, key: Option<Key>) {
+        let node = &mut self.nodes[id];
+        if let Some(old_key) = node.key {
+            remove_entry(&mut self.index.by_key, old_key, id);
+        }
+        node.key = key;
+        if let Some(key) = key {
+            add_entry(&mut self.index.by_key, key, id);
+        }
+    }
+
+    /// Returns the node with the specified key.
+    ///
+    /// If more than one node has this key, returns the one that was given the key first.
+    pub fn find_by_key(&self, key: &Key) -> Option<NodeId> {
+        self.find_all_by_key(key).first().cloned()
+    }
+
+    /// Returns all the nodes with the specified key, in the order they were given the key.
+    pub fn find_all_by_key(&self, key: &Key) -> &[NodeId] {
+        self.index.by_key.get(key).map_or(&[], |ids| &ids[..])
+    }
+
+    /// Returns all the nodes with a widget of type `T`, in creation order.
+    pub fn find_all_by_widget_type<T: Widget>(&self) -> &[NodeId] {
+        self.index
+            .by_widget_type
+            .get(&TypeId::of::<T>())
+            .map_or(&[], |ids| &ids[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        key::Key,
+        node::{FocusState, NodeCursor, NodeTree},
+        widget::{Dummy, Label},
+    };
+
+    #[test]
+    fn test_index() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        let c = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.insert(c, NodeCursor::BeforeChild(root));
+
+        let key_a = Key::from_caller();
+        let key_b = Key::with_id("b");
+        node_tree.set_key(a, Some(key_a));
+        node_tree.set_key(b, Some(key_b));
+        assert_eq!(node_tree.find_by_key(&key_a), Some(a));
+        assert_eq!(node_tree.find_by_key(&key_b), Some(b));
+        assert_ne!(key_b, Key::with_id("c"));
+
+        // keys can be changed
+        node_tree.set_key(a, None);
+        assert_eq!(node_tree.find_by_key(&key_a), None);
+
+        assert_eq!(node_tree.find_all_by_widget_type::<Dummy>(), &[a, b, c]);
+        assert!(node_tree.find_all_by_widget_type::<Label>().is_empty());
+
+        // removed nodes are removed from the index, the others keep their order
+        node_tree.set_key(c, Some(key_b));
+        assert_eq!(node_tree.find_all_by_key(&key_b), &[b, c]);
+        node_tree.remove(a, &mut FocusState::new());
+        assert_eq!(node_tree.find_all_by_key(&key_b), &[c]);
+        assert_eq!(node_tree.find_all_by_widget_type::<Dummy>(), &[c]);
+        node_tree.remove(c, &mut FocusState::new());
+        assert_eq!(node_tree.find_by_key(&key_b), None);
+        assert!(node_tree.find_all_by_widget_type::<Dummy>().is_empty());
+    }
+}
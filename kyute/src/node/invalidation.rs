@@ -0,0 +1,372 @@
+//! Invalidation tracking: needs-layout and needs-paint flags, and incremental relayout.
+use crate::{
+    layout::{BoxConstraints, Measurements},
+    node::{NodeEdge, NodeId, NodeTree},
+    Point, Rect,
+};
+use kyute_shell::drawing::Transform;
+
+impl NodeTree {
+    /// Returns the bounds of the specified node in window coordinates, as of the last layout.
+    ///
+    /// If the node is transformed, this is the bounding box of the transformed bounds.
+    pub fn window_bounds(&self, id: NodeId) -> Rect {
+        let node = &self.nodes[id];
+        let local_bounds = Rect::new(Point::origin(), node.measurements.size);
+        node.window_transform
+            .get()
+            .outer_transformed_rect(&local_bounds)
+    }
+
+    /// Flags the node as needing a relayout.
+    ///
+    /// Its ancestors are flagged as having a descendant that needs a relayout so that
+    /// [`relayout`](NodeTree::relayout) can find it. This also schedules a repaint of the node.
+    pub fn mark_needs_layout(&mut self, id: NodeId) {
+        self.nodes[id].needs_layout = true;
+        self.mark_needs_paint(id);
+
+        let mut parent = self.nodes[id].parent();
+        while let Some(parent_id) = parent {
+            let node = &mut self.nodes[parent_id];
+            if node.child_needs_layout {
+                // the ancestors above are already flagged
+                break;
+            }
+            node.child_needs_layout = true;
+            parent = node.parent();
+        }
+    }
+
+    /// Flags the node as needing a repaint, and adds its bounds to the dirty region.
+    pub fn mark_needs_paint(&mut self, id: NodeId) {
+        let bounds = self.window_bounds(id);
+        self.add_dirty_rect(bounds);
+        self.nodes[id].needs_paint = true;
+
+        let mut parent = self.nodes[id].parent();
+        while let Some(parent_id) = parent {
+            let node = &mut self.nodes[parent_id];
+            if node.child_needs_paint {
+                break;
+            }
+            node.child_needs_paint = true;
+            parent = node.parent();
+        }
+    }
+
+    /// Adds a rectangle, in window coordinates, to the region that must be repainted.
+    pub fn add_dirty_rect(&mut self, rect: Rect) {
+        self.dirty_region = Some(match self.dirty_region {
+            Some(region) => region.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Returns the region that must be repainted, in window coordinates.
+    pub fn dirty_region(&self) -> Option<Rect> {
+        self.dirty_region
+    }
+
+    /// Returns whether a node in the tree needs a relayout.
+    pub fn needs_layout(&self) -> bool {
+        self.layer_roots().any(|id| {
+            let node = &self.nodes[id];
+            node.needs_layout || node.child_needs_layout
+        })
+    }
+
+    /// Returns whether a node in the tree needs a repaint.
+    pub fn needs_paint(&self) -> bool {
+        self.layer_roots().any(|id| {
+            let node = &self.nodes[id];
+            node.needs_paint || node.child_needs_paint
+        })
+    }
+
+    /// Returns the root node followed by the roots of the overlays.
+    pub(crate) fn layer_roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::once(self.root).chain(self.overlays())
+    }
+
+    /// Lays out a node.
+    ///
+    /// If the node isn't flagged for relayout and the constraints are the same as in the last
+    /// layout, returns the current measurements without calling `layout`. Otherwise, calls
+    /// `layout` to measure the node (it should call `layout_node` on the children of the node
+    /// and set their offsets), and records the constraints and the result.
+    pub fn layout_node(
+        &mut self,
+        id: NodeId,
+        constraints: &BoxConstraints,
+        layout: &mut dyn FnMut(&mut NodeTree, NodeId, &BoxConstraints) -> Measurements,
+    ) -> Measurements {
+        let node = &self.nodes[id];
+        if !node.needs_layout
+            && !node.child_needs_layout
+            && node.constraints.as_ref() == Some(constraints)
+        {
+            return node.measurements;
+        }
+
+        let measurements = layout(self, id, constraints);
+        let node = &mut self.nodes[id];
+        node.constraints = Some(*constraints);
+        node.measurements = measurements;
+        node.needs_layout = false;
+        node.child_needs_layout = false;
+        measurements
+    }
+
+    /// Lays out again the nodes flagged with [`mark_needs_layout`](NodeTree::mark_needs_layout).
+    ///
+    /// Flagged nodes are laid out again with the constraints of their last layout, deepest first.
+    /// If the measurements of a node didn't change, its parent is left alone; otherwise, the
+    /// parent is laid out again, and so on. Nodes that were never laid out are laid out by their
+    /// parent. The window positions of the relaid-out subtrees and the positions of the overlays
+    /// are updated, and their old and new bounds are added to the dirty region.
+    pub fn relayout(
+        &mut self,
+        layout: &mut dyn FnMut(&mut NodeTree, NodeId, &BoxConstraints) -> Measurements,
+    ) {
+        let mut dirty = Vec::new();
+        let roots: Vec<_> = self.layer_roots().collect();
+        for root in roots {
+            self.take_needs_layout(root, &mut dirty);
+        }
+
+        for id in dirty {
+            if !self.nodes.contains_key(id) || !self.nodes[id].needs_layout {
+                // removed, or already laid out again by an ancestor
+                continue;
+            }
+
+            let mut current = id;
+            loop {
+                let node = &mut self.nodes[current];
+                let parent = node.parent();
+
+                if let Some(constraints) = node.constraints {
+                    node.needs_layout = true;
+                    let old_measurements = node.measurements;
+                    let old_bounds = self.window_bounds(current);
+                    let measurements = self.layout_node(current, &constraints, layout);
+                    self.update_window_positions(current);
+                    self.add_dirty_rect(old_bounds);
+                    self.mark_needs_paint(current);
+                    if measurements == old_measurements {
+                        // the layout of the parent doesn't depend on what's inside the node
+                        break;
+                    }
+                }
+
+                match parent {
+                    Some(parent) => current = parent,
+                    None => break,
+                }
+            }
+        }
+
+        self.update_overlay_positions();
+    }
+
+    /// Collects the nodes that need a relayout in the subtree rooted at `id`, in post-order, and
+    /// clears the `child_needs_layout` flags along the way.
+    fn take_needs_layout(&mut self, id: NodeId, out: &mut Vec<NodeId>) {
+        let node = &mut self.nodes[id];
+        if node.child_needs_layout {
+            node.child_needs_layout = false;
+            let children: Vec<_> = self.children(id).collect();
+            for child in children {
+                self.take_needs_layout(child, out);
+            }
+        }
+        if self.nodes[id].needs_layout {
+            out.push(id);
+        }
+    }
+
+    /// Recomputes the window positions and transforms of the specified node and its descendants
+    /// from their offsets, transforms and scroll offsets.
+    pub fn update_window_positions(&mut self, id: NodeId) {
+        // transform applied to the children of the current node
+        let mut transform = match self.nodes[id].parent() {
+            Some(parent) => {
+                let parent = &self.nodes[parent];
+                parent.content_transform(&parent.window_transform.get())
+            }
+            None => Transform::translation(self.window_origin.x, self.window_origin.y),
+        };
+        let mut stack = Vec::new();
+        for edge in self.traverse(id) {
+            match edge {
+                NodeEdge::Start(id) => {
+                    stack.push(transform);
+                    let node = &self.nodes[id];
+                    let window_transform = node.local_to_parent_transform().then(&transform);
+                    node.window_transform.set(window_transform);
+                    node.window_pos
+                        .set(window_transform.transform_point(Point::origin()));
+                    transform = node.content_transform(&window_transform);
+                }
+                NodeEdge::End(_) => {
+                    transform = stack.pop().expect("unbalanced traversal");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layout::{BoxConstraints, Measurements},
+        node::{NodeCursor, NodeId, NodeTree},
+        widget::Dummy,
+        Offset, Point, Rect, Size,
+    };
+    use kyute_shell::drawing::Transform;
+    use std::collections::HashMap;
+
+    /// Lays out children vertically. Leaves take their size from `sizes`.
+    fn stack_layout<'a>(
+        sizes: &'a HashMap<NodeId, Size>,
+        log: &'a mut Vec<NodeId>,
+    ) -> impl FnMut(&mut NodeTree, NodeId, &BoxConstraints) -> Measurements + 'a {
+        fn layout(
+            tree: &mut NodeTree,
+            id: NodeId,
+            constraints: &BoxConstraints,
+            sizes: &HashMap<NodeId, Size>,
+            log: &mut Vec<NodeId>,
+        ) -> Measurements {
+            log.push(id);
+            if let Some(size) = sizes.get(&id) {
+                return Measurements::new(*size);
+            }
+            let children: Vec<_> = tree.children(id).collect();
+            let mut size = Size::zero();
+            for child in children {
+                let m = tree.layout_node(child, constraints, &mut |tree, id, constraints| {
+                    layout(tree, id, constraints, sizes, log)
+                });
+                tree.get_mut(child).unwrap().offset = Offset::new(0.0, size.height);
+                size = Size::new(size.width.max(m.size.width), size.height + m.size.height);
+            }
+            Measurements::new(size)
+        }
+        move |tree, id, constraints| layout(tree, id, constraints, sizes, log)
+    }
+
+    #[test]
+    fn test_incremental_relayout() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let mut create = |tree: &mut NodeTree, at| {
+            let id = tree.create(Box::new(Dummy));
+            tree.insert(id, at);
+            id
+        };
+
+        // R(W(A(B),C))
+        let w = create(&mut node_tree, NodeCursor::BeforeChild(root));
+        let a = create(&mut node_tree, NodeCursor::BeforeChild(w));
+        let b = create(&mut node_tree, NodeCursor::BeforeChild(a));
+        let c = create(&mut node_tree, NodeCursor::BeforeChild(w));
+
+        let mut sizes = HashMap::new();
+        sizes.insert(b, Size::new(10.0, 10.0));
+        sizes.insert(c, Size::new(20.0, 5.0));
+        let constraints = BoxConstraints::loose(Size::new(100.0, 100.0));
+
+        let mut log = Vec::new();
+        node_tree.layout_node(w, &constraints, &mut stack_layout(&sizes, &mut log));
+        node_tree.update_window_positions(w);
+        assert_eq!(log, vec![w, a, b, c]);
+        assert_eq!(
+            node_tree.get(w).unwrap().measurements.size,
+            Size::new(20.0, 15.0)
+        );
+        assert_eq!(node_tree.get(c).unwrap().window_pos.get().y, 10.0);
+        assert!(!node_tree.needs_layout());
+
+        // nothing is dirty: nothing is laid out again
+        log.clear();
+        node_tree.layout_node(w, &constraints, &mut stack_layout(&sizes, &mut log));
+        assert!(log.is_empty());
+
+        // same size: only the flagged node is laid out again
+        log.clear();
+        node_tree.mark_needs_layout(b);
+        assert!(node_tree.needs_layout());
+        node_tree.relayout(&mut stack_layout(&sizes, &mut log));
+        assert_eq!(log, vec![b]);
+        assert!(!node_tree.needs_layout());
+
+        // different size: the change propagates to the ancestors
+        log.clear();
+        sizes.insert(b, Size::new(10.0, 30.0));
+        node_tree.mark_needs_layout(b);
+        node_tree.relayout(&mut stack_layout(&sizes, &mut log));
+        assert_eq!(log, vec![b, a, w]);
+        assert_eq!(
+            node_tree.get(w).unwrap().measurements.size,
+            Size::new(20.0, 35.0)
+        );
+        assert_eq!(node_tree.get(c).unwrap().window_pos.get().y, 30.0);
+        assert!(node_tree
+            .dirty_region()
+            .unwrap()
+            .contains_rect(&node_tree.window_bounds(w)));
+    }
+
+    #[test]
+    fn test_mark_needs_paint() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.get_mut(a).unwrap().needs_paint = false;
+        assert!(!node_tree.needs_paint());
+
+        node_tree.mark_needs_paint(a);
+        assert!(node_tree.needs_paint());
+        assert!(node_tree.get(root).unwrap().child_needs_paint);
+        assert!(node_tree.dirty_region().is_some());
+    }
+
+    #[test]
+    fn test_transform() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.get_mut(a).unwrap().offset = Offset::new(5.0, 5.0);
+        node_tree.get_mut(b).unwrap().offset = Offset::new(10.0, 0.0);
+        node_tree.get_mut(b).unwrap().measurements = Measurements::new(Size::new(10.0, 10.0));
+
+        node_tree.set_transform(a, Some(Transform::scale(2.0, 2.0)));
+        assert_eq!(
+            node_tree.get(b).unwrap().window_pos.get(),
+            Point::new(25.0, 5.0)
+        );
+        assert_eq!(
+            node_tree.window_bounds(b),
+            Rect::new(Point::new(25.0, 5.0), Size::new(20.0, 20.0))
+        );
+        let local = node_tree
+            .window_transform(b)
+            .inverse()
+            .unwrap()
+            .transform_point(Point::new(35.0, 15.0));
+        assert_eq!(local, Point::new(5.0, 5.0));
+
+        node_tree.set_transform(a, None);
+        assert_eq!(
+            node_tree.get(b).unwrap().window_pos.get(),
+            Point::new(15.0, 5.0)
+        );
+    }
+}
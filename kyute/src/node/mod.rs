@@ -1,7 +1,23 @@
+mod coords;
+mod dump;
+mod event;
+mod index;
+mod invalidation;
+mod node_ref;
+mod overlay;
 mod paint;
 
-use crate::{layout::Measurements, widget::Widget, Offset, Point};
+use crate::{
+    layout::{BoxConstraints, Measurements},
+    widget::Widget,
+    Offset, Point, Rect,
+};
+use kyute_shell::drawing::Transform;
+pub use event::{EventCtx, FocusState, RepaintRequest};
+pub use node_ref::{NodeRef, NodeRefMut};
+pub use overlay::OverlayPlacement;
 pub use paint::PaintCtx;
+use std::any::Any;
 use std::cell::Cell;
 use std::panic::Location;
 use crate::key::Key;
@@ -10,16 +26,6 @@ slotmap::new_key_type! {
     pub struct NodeId;
 }
 
-pub struct NodeRef<'a> {
-    pub tree: &'a NodeTree,
-    pub id: NodeId,
-}
-
-pub struct NodeRefMut<'a> {
-    pub tree: &'a mut NodeTree,
-    pub id: NodeId,
-}
-
 struct Links {
     parent: Option<NodeId>,
     previous_sibling: Option<NodeId>,
@@ -53,12 +59,43 @@ pub struct Node<ElementType: Widget + ?Sized = dyn Widget> {
     pub(crate) measurements: Measurements,
     /// Position of the node in window coordinates.
     pub(crate) window_pos: Cell<Point>,
+    /// Transform applied to the contents of the node, before the offset.
+    pub(crate) transform: Option<Transform>,
+    /// Scroll offset of the contents of the node: children are shifted by the opposite amount.
+    pub(crate) scroll_offset: Offset,
+    /// Transform from the local coordinates of the node to window coordinates.
+    pub(crate) window_transform: Cell<Transform>,
     pub(crate) widget: Option<Box<ElementType>>,
     /// Key associated to the node.
     pub(crate) key: Option<Key>,
+    /// Constraints of the last layout of the node, `None` if it was never laid out.
+    pub(crate) constraints: Option<BoxConstraints>,
+    /// The node must be laid out again.
+    pub(crate) needs_layout: bool,
+    /// A descendant of the node must be laid out again.
+    pub(crate) child_needs_layout: bool,
+    /// The node must be repainted.
+    pub(crate) needs_paint: bool,
+    /// A descendant of the node must be repainted.
+    pub(crate) child_needs_paint: bool,
 }
 
 impl<T: Widget + ?Sized> Node<T> {
+    /// Returns the transform from the local coordinates of the node to the coordinates of its
+    /// parent: the transform of the node followed by its offset.
+    pub fn local_to_parent_transform(&self) -> Transform {
+        self.transform
+            .unwrap_or_else(Transform::identity)
+            .then_translate(self.offset)
+    }
+
+    /// Returns the transform from the local coordinates of the node to the coordinates of its
+    /// children, given the transform from the local coordinates of the node to window
+    /// coordinates.
+    fn content_transform(&self, window_transform: &Transform) -> Transform {
+        Transform::translation(-self.scroll_offset.x, -self.scroll_offset.y).then(window_transform)
+    }
+
     pub fn parent(&self) -> Option<NodeId> {
         self.links.parent
     }
@@ -97,6 +134,12 @@ pub struct NodeTree {
     nodes: slotmap::SlotMap<NodeId, Node>,
     root: NodeId,
     window_origin: Point,
+    /// Area that must be repainted, in window coordinates.
+    dirty_region: Option<Rect>,
+    /// Overlay layer, from bottom to top.
+    overlays: Vec<overlay::Overlay>,
+    /// Nodes by key and by widget type.
+    index: index::NodeIndex,
 }
 
 pub struct NodeIter<'a> {
@@ -120,6 +163,142 @@ impl<'a> Iterator for NodeIter<'a> {
     }
 }
 
+/// Iterator over siblings in reverse order.
+pub struct ReverseNodeIter<'a> {
+    tree: &'a NodeTree,
+    current: Option<NodeId>,
+}
+
+impl<'a> Iterator for ReverseNodeIter<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.current?;
+        self.current = self.tree.nodes[current].links.previous_sibling;
+        Some(current)
+    }
+}
+
+/// Iterator over the ancestors of a node, starting with the node itself.
+pub struct Ancestors<'a> {
+    tree: &'a NodeTree,
+    current: Option<NodeId>,
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.current?;
+        self.current = self.tree.nodes[current].links.parent;
+        Some(current)
+    }
+}
+
+/// An edge of a node in a depth-first traversal.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum NodeEdge {
+    /// Before the descendants of the node.
+    Start(NodeId),
+    /// After the descendants of the node.
+    End(NodeId),
+}
+
+/// Depth-first traversal of a subtree, yielding the start and end edges of each node.
+pub struct Traverse<'a> {
+    tree: &'a NodeTree,
+    root: NodeId,
+    next: Option<NodeEdge>,
+}
+
+impl<'a> Iterator for Traverse<'a> {
+    type Item = NodeEdge;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let edge = self.next.take()?;
+        self.next = match edge {
+            NodeEdge::Start(id) => match self.tree.nodes[id].links.first_child {
+                Some(first_child) => Some(NodeEdge::Start(first_child)),
+                None => Some(NodeEdge::End(id)),
+            },
+            NodeEdge::End(id) if id == self.root => None,
+            NodeEdge::End(id) => {
+                let links = &self.tree.nodes[id].links;
+                match links.next_sibling {
+                    Some(next_sibling) => Some(NodeEdge::Start(next_sibling)),
+                    None => links.parent.map(NodeEdge::End),
+                }
+            }
+        };
+        Some(edge)
+    }
+}
+
+/// Depth-first traversal of a subtree in reverse order: children are visited from last to
+/// first, and the end edge of a node comes before its start edge.
+///
+/// This is the order in which nodes should be hit-tested, topmost first.
+pub struct ReverseTraverse<'a> {
+    tree: &'a NodeTree,
+    root: NodeId,
+    next: Option<NodeEdge>,
+}
+
+impl<'a> Iterator for ReverseTraverse<'a> {
+    type Item = NodeEdge;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let edge = self.next.take()?;
+        self.next = match edge {
+            NodeEdge::End(id) => match self.tree.nodes[id].links.last_child {
+                Some(last_child) => Some(NodeEdge::End(last_child)),
+                None => Some(NodeEdge::Start(id)),
+            },
+            NodeEdge::Start(id) if id == self.root => None,
+            NodeEdge::Start(id) => {
+                let links = &self.tree.nodes[id].links;
+                match links.previous_sibling {
+                    Some(previous_sibling) => Some(NodeEdge::End(previous_sibling)),
+                    None => links.parent.map(NodeEdge::Start),
+                }
+            }
+        };
+        Some(edge)
+    }
+}
+
+/// Pre-order iterator over the descendants of a node, starting with the node itself.
+pub struct Descendants<'a>(Traverse<'a>);
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next()? {
+                NodeEdge::Start(id) => return Some(id),
+                NodeEdge::End(_) => continue,
+            }
+        }
+    }
+}
+
+/// Post-order iterator over the descendants of a node, ending with the node itself.
+pub struct PostOrderDescendants<'a>(Traverse<'a>);
+
+impl<'a> Iterator for PostOrderDescendants<'a> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next()? {
+                NodeEdge::Start(_) => continue,
+                NodeEdge::End(id) => return Some(id),
+            }
+        }
+    }
+}
+
 impl NodeTree {
     /// Creates a new node tree containing a single root node.
     pub fn new() -> NodeTree {
@@ -131,14 +310,25 @@ impl NodeTree {
             offset: Default::default(),
             measurements: Default::default(),
             window_pos: Cell::new(Default::default()),
+            transform: None,
+            scroll_offset: Offset::zero(),
+            window_transform: Cell::new(Transform::identity()),
             widget: None,
             key: None,
+            constraints: None,
+            needs_layout: false,
+            child_needs_layout: false,
+            needs_paint: false,
+            child_needs_paint: false,
         });
 
         NodeTree {
             nodes,
             root,
             window_origin: Point::origin(),
+            dirty_region: None,
+            overlays: Vec::new(),
+            index: Default::default(),
         }
     }
 
@@ -162,6 +352,18 @@ impl NodeTree {
         self.nodes.get_mut(id)
     }
 
+    /// Returns a [`NodeRef`] to navigate from the node with the given ID.
+    pub fn node_ref(&self, id: NodeId) -> Option<NodeRef> {
+        self.nodes.get(id)?;
+        Some(NodeRef { tree: self, id })
+    }
+
+    /// Returns a [`NodeRefMut`] to the node with the given ID.
+    pub fn node_mut(&mut self, id: NodeId) -> Option<NodeRefMut> {
+        self.nodes.get(id)?;
+        Some(NodeRefMut { tree: self, id })
+    }
+
     /// Returns an iterator over the children of the specified node.
     pub fn children(&self, id: NodeId) -> NodeIter {
         NodeIter {
@@ -212,9 +414,68 @@ impl NodeTree {
         }
     }
 
+    /// Returns an iterator over the children of the specified node, from last to first.
+    pub fn reverse_children(&self, id: NodeId) -> ReverseNodeIter {
+        ReverseNodeIter {
+            tree: self,
+            current: self.nodes.get(id).unwrap().last_child(),
+        }
+    }
+
+    /// Returns an iterator over the preceding siblings of the specified node, starting with the
+    /// closest one.
+    pub fn preceding_siblings(&self, id: NodeId) -> ReverseNodeIter {
+        ReverseNodeIter {
+            tree: self,
+            current: self.nodes.get(id).unwrap().previous_sibling(),
+        }
+    }
+
+    /// Returns an iterator over the specified node and its ancestors, up to the root.
+    pub fn ancestors(&self, id: NodeId) -> Ancestors {
+        self.nodes.get(id).expect("invalid node");
+        Ancestors {
+            tree: self,
+            current: Some(id),
+        }
+    }
+
+    /// Returns an iterator over the start and end edges of the nodes in the subtree rooted at the
+    /// specified node, in depth-first order.
+    pub fn traverse(&self, id: NodeId) -> Traverse {
+        self.nodes.get(id).expect("invalid node");
+        Traverse {
+            tree: self,
+            root: id,
+            next: Some(NodeEdge::Start(id)),
+        }
+    }
+
+    /// Same as [`traverse`](NodeTree::traverse), but in reverse order.
+    pub fn reverse_traverse(&self, id: NodeId) -> ReverseTraverse {
+        self.nodes.get(id).expect("invalid node");
+        ReverseTraverse {
+            tree: self,
+            root: id,
+            next: Some(NodeEdge::End(id)),
+        }
+    }
+
+    /// Returns an iterator over the specified node and its descendants, in pre-order (parents
+    /// before their children).
+    pub fn descendants(&self, id: NodeId) -> Descendants {
+        Descendants(self.traverse(id))
+    }
+
+    /// Returns an iterator over the specified node and its descendants, in post-order (children
+    /// before their parent).
+    pub fn post_order_descendants(&self, id: NodeId) -> PostOrderDescendants {
+        PostOrderDescendants(self.traverse(id))
+    }
+
     /// Creates a new, unattached node.
     pub fn create(&mut self, element: Box<dyn Widget>) -> NodeId {
-        self.nodes.insert(Node {
+        let id = self.nodes.insert(Node {
             links: Links {
                 parent: None,
                 previous_sibling: None,
@@ -225,13 +486,57 @@ impl NodeTree {
             offset: Default::default(),
             measurements: Default::default(),
             window_pos: Cell::new(Default::default()),
+            transform: None,
+            scroll_offset: Offset::zero(),
+            window_transform: Cell::new(Transform::identity()),
             widget: Some(element),
             key: None,
-        })
+            constraints: None,
+            needs_layout: true,
+            child_needs_layout: false,
+            needs_paint: true,
+            child_needs_paint: false,
+        });
+        let widget = self.nodes[id].widget.as_deref();
+        self.index.add(id, None, widget);
+        id
+    }
+
+    /// Returns whether `id` can be inserted at the specified location: the root can't be moved,
+    /// a node can't be inserted next to itself or inside its own subtree, and siblings must have a
+    /// parent.
+    fn is_valid_insertion(&self, id: NodeId, at: NodeCursor) -> bool {
+        if id == self.root || !self.nodes.contains_key(id) {
+            return false;
+        }
+        let target = match at {
+            NodeCursor::Before(sibling) | NodeCursor::After(sibling) => {
+                match self.nodes.get(sibling) {
+                    Some(node) if node.parent().is_some() => sibling,
+                    _ => return false,
+                }
+            }
+            NodeCursor::BeforeChild(parent) => {
+                if !self.nodes.contains_key(parent) {
+                    return false;
+                }
+                parent
+            }
+        };
+        !self.ancestors(target).any(|ancestor| ancestor == id)
     }
 
     /// Inserts a node at a specified location.
+    ///
+    /// In debug builds, panics if the insertion would produce an invalid tree (e.g. inserting a
+    /// node under one of its descendants).
     pub fn insert(&mut self, id: NodeId, at: NodeCursor) {
+        debug_assert!(
+            self.is_valid_insertion(id, at),
+            "invalid insertion of {:?} at {:?}",
+            id,
+            at
+        );
         self.detach(id);
         unsafe {
             match at {
@@ -325,16 +630,170 @@ impl NodeTree {
         }
     }
 
-    pub fn remove(&mut self, id: NodeId) {
+    /// Removes the specified node and all its descendants from the tree.
+    ///
+    /// The widgets of the removed nodes are unmounted, children before their parent (see
+    /// [`Widget::unmount`]). The focus, pointer grab or hot status held by any of the removed
+    /// nodes is released. Overlays owned by or anchored to the removed nodes are removed first.
+    ///
+    /// Panics if `id` is the root node.
+    pub fn remove(&mut self, id: NodeId, focus: &mut FocusState) {
+        assert_ne!(id, self.root, "cannot remove the root node");
+        let bounds = self.window_bounds(id);
+        self.add_dirty_rect(bounds);
         self.detach(id);
-        self.nodes.remove(id);
+
+        let removed: Vec<_> = self.post_order_descendants(id).collect();
+        for orphan in self.take_orphaned_overlays(&removed) {
+            self.remove(orphan, focus);
+        }
+        let mut index_entries = Vec::with_capacity(removed.len());
+        for node_id in removed {
+            let mut node = self.nodes.remove(node_id).unwrap();
+            index_entries.push((node_id, node.key, node.widget.as_deref().map(Any::type_id)));
+            if let Some(ref mut widget) = node.widget {
+                widget.unmount();
+            }
+            focus.release_node(node_id);
+        }
+        self.index.remove_all(index_entries);
+    }
+
+    /// Sets the transform applied to the contents of a node (scale, rotation, skew...), in
+    /// addition to its offset.
+    ///
+    /// The transform is relative to the local origin of the node (its top-left corner).
+    pub fn set_transform(&mut self, id: NodeId, transform: Option<Transform>) {
+        // old bounds
+        self.mark_needs_paint(id);
+        self.nodes[id].transform = transform;
+        self.update_window_positions(id);
+        self.mark_needs_paint(id);
+    }
+
+    /// Returns the transform from the local coordinates of the node to window coordinates, as of
+    /// the last layout.
+    pub fn window_transform(&self, id: NodeId) -> Transform {
+        self.nodes[id].window_transform.get()
+    }
+
+    /// Returns the number of nodes in the tree, including the root.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks the structural invariants of the tree.
+    ///
+    /// Verifies that the parent, sibling and child links of every node are consistent with each
+    /// other and that the tree contains no cycles. Returns a description of the first violation
+    /// found. This is meant for tests and debugging: it visits every node of the tree.
+    pub fn validate(&self) -> Result<(), String> {
+        let root = self.nodes.get(self.root).ok_or("root node missing")?;
+        if root.parent().is_some()
+            || root.previous_sibling().is_some()
+            || root.next_sibling().is_some()
+        {
+            return Err("root node has a parent or siblings".to_string());
+        }
+
+        let exists = |link: Option<NodeId>| link.map_or(true, |id| self.nodes.contains_key(id));
+
+        for (id, node) in self.nodes.iter() {
+            let links = &node.links;
+            if !exists(links.parent)
+                || !exists(links.previous_sibling)
+                || !exists(links.next_sibling)
+                || !exists(links.first_child)
+                || !exists(links.last_child)
+            {
+                return Err(format!("{:?}: dangling link", id));
+            }
+
+            // siblings
+            if links.parent.is_none()
+                && (links.previous_sibling.is_some() || links.next_sibling.is_some())
+            {
+                return Err(format!("{:?}: detached node has siblings", id));
+            }
+            match links.previous_sibling {
+                Some(prev) => {
+                    let prev = &self.nodes[prev].links;
+                    if prev.next_sibling != Some(id) || prev.parent != links.parent {
+                        return Err(format!("{:?}: inconsistent previous sibling", id));
+                    }
+                }
+                None => {
+                    if let Some(parent) = links.parent {
+                        if self.nodes[parent].links.first_child != Some(id) {
+                            return Err(format!("{:?}: not the first child of its parent", id));
+                        }
+                    }
+                }
+            }
+            match links.next_sibling {
+                Some(next) => {
+                    let next = &self.nodes[next].links;
+                    if next.previous_sibling != Some(id) || next.parent != links.parent {
+                        return Err(format!("{:?}: inconsistent next sibling", id));
+                    }
+                }
+                None => {
+                    if let Some(parent) = links.parent {
+                        if self.nodes[parent].links.last_child != Some(id) {
+                            return Err(format!("{:?}: not the last child of its parent", id));
+                        }
+                    }
+                }
+            }
+
+            // children: the sibling chain must go from the first to the last child
+            if links.first_child.is_some() != links.last_child.is_some() {
+                return Err(format!("{:?}: inconsistent first and last child", id));
+            }
+            let mut count = 0;
+            let mut last = None;
+            let mut child = links.first_child;
+            while let Some(child_id) = child {
+                count += 1;
+                if count > self.nodes.len() {
+                    return Err(format!("{:?}: cycle in children", id));
+                }
+                let child_links = &self.nodes[child_id].links;
+                if child_links.parent != Some(id) {
+                    return Err(format!("{:?}: child {:?} has another parent", id, child_id));
+                }
+                last = child;
+                child = child_links.next_sibling;
+            }
+            if last != links.last_child {
+                return Err(format!("{:?}: last child not reachable from the first", id));
+            }
+
+            // ancestors: must end at a node without a parent
+            let mut depth = 0;
+            let mut ancestor = links.parent;
+            while let Some(ancestor_id) = ancestor {
+                depth += 1;
+                if depth > self.nodes.len() {
+                    return Err(format!("{:?}: cycle in ancestors", id));
+                }
+                ancestor = self.nodes[ancestor_id].links.parent;
+            }
+        }
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::widget::Dummy;
+    use crate::{
+        layout::BoxConstraints,
+        widget::{Dummy, LayoutCtx, Node as WidgetNode},
+        Rect,
+    };
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
     #[test]
     fn test_insertion() {
@@ -519,4 +978,273 @@ mod tests {
             ]
         );
     }
+
+    /// Widget that records when it's unmounted.
+    struct Unmount(&'static str, Rc<RefCell<Vec<&'static str>>>);
+
+    impl Widget for Unmount {
+        fn layout(
+            &mut self,
+            _ctx: &mut LayoutCtx,
+            _children: &mut [WidgetNode],
+            _constraints: &BoxConstraints,
+        ) -> Measurements {
+            Measurements::default()
+        }
+
+        fn paint(&mut self, _ctx: &mut PaintCtx, _children: &mut [WidgetNode], _bounds: Rect) {}
+
+        fn unmount(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    #[test]
+    fn test_remove_subtree() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let mut create = |tree: &mut NodeTree, name, at| {
+            let id = tree.create(Box::new(Unmount(name, log.clone())));
+            tree.insert(id, at);
+            id
+        };
+
+        // R(A(B(C),D),E)
+        let a = create(&mut node_tree, "A", NodeCursor::BeforeChild(root));
+        let b = create(&mut node_tree, "B", NodeCursor::BeforeChild(a));
+        let c = create(&mut node_tree, "C", NodeCursor::BeforeChild(b));
+        let d = create(&mut node_tree, "D", NodeCursor::After(b));
+        let e = create(&mut node_tree, "E", NodeCursor::After(a));
+
+        let mut focus = FocusState::new();
+        focus.acquire_focus(c);
+        focus.acquire_pointer_grab(d);
+        focus.hot = Some(e);
+
+        node_tree.remove(a, &mut focus);
+        assert_eq!(&log.borrow()[..], &["C", "B", "D", "A"]);
+        assert_eq!(node_tree.len(), 2);
+        for id in [a, b, c, d].iter() {
+            assert!(node_tree.get(*id).is_none());
+        }
+        assert_eq!(node_tree.get(root).unwrap().first_child(), Some(e));
+        assert_eq!(focus.focus, None);
+        assert_eq!(focus.pointer_grab, None);
+        // nodes outside the removed subtree keep their status
+        assert_eq!(focus.hot, Some(e));
+    }
+
+    #[test]
+    fn test_traversal() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let mut create = |tree: &mut NodeTree, at| {
+            let id = tree.create(Box::new(Dummy));
+            tree.insert(id, at);
+            id
+        };
+
+        // R(A(B,C(D)),E)
+        let a = create(&mut node_tree, NodeCursor::BeforeChild(root));
+        let b = create(&mut node_tree, NodeCursor::BeforeChild(a));
+        let c = create(&mut node_tree, NodeCursor::BeforeChild(a));
+        let d = create(&mut node_tree, NodeCursor::BeforeChild(c));
+        let e = create(&mut node_tree, NodeCursor::BeforeChild(root));
+
+        use NodeEdge::{End, Start};
+        let edges: Vec<_> = node_tree.traverse(a).collect();
+        assert_eq!(
+            edges,
+            vec![
+                Start(a),
+                Start(b),
+                End(b),
+                Start(c),
+                Start(d),
+                End(d),
+                End(c),
+                End(a)
+            ]
+        );
+        let mut reversed: Vec<_> = node_tree.reverse_traverse(a).collect();
+        reversed.reverse();
+        assert_eq!(reversed, edges);
+
+        assert_eq!(
+            node_tree.descendants(root).collect::<Vec<_>>(),
+            vec![root, a, b, c, d, e]
+        );
+        assert_eq!(
+            node_tree.post_order_descendants(root).collect::<Vec<_>>(),
+            vec![b, d, c, a, e, root]
+        );
+        assert_eq!(node_tree.descendants(d).collect::<Vec<_>>(), vec![d]);
+        assert_eq!(
+            node_tree.ancestors(d).collect::<Vec<_>>(),
+            vec![d, c, a, root]
+        );
+        assert_eq!(node_tree.preceding_siblings(c).collect::<Vec<_>>(), vec![b]);
+        assert_eq!(
+            node_tree.reverse_children(root).collect::<Vec<_>>(),
+            vec![e, a]
+        );
+    }
+
+    /// Small deterministic PRNG for the randomized test (xorshift64).
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Reference model of the tree: children of each node, by node ID.
+    #[derive(Default)]
+    struct Model {
+        children: HashMap<NodeId, Vec<NodeId>>,
+        parent: HashMap<NodeId, NodeId>,
+    }
+
+    impl Model {
+        fn detach(&mut self, id: NodeId) {
+            if let Some(parent) = self.parent.remove(&id) {
+                self.children.get_mut(&parent).unwrap().retain(|&c| c != id);
+            }
+        }
+
+        fn insert(&mut self, id: NodeId, at: NodeCursor) {
+            self.detach(id);
+            let (parent, index) = match at {
+                NodeCursor::Before(sibling) | NodeCursor::After(sibling) => {
+                    let parent = self.parent[&sibling];
+                    let pos = self.children[&parent]
+                        .iter()
+                        .position(|&c| c == sibling)
+                        .unwrap();
+                    let index = if let NodeCursor::After(_) = at {
+                        pos + 1
+                    } else {
+                        pos
+                    };
+                    (parent, index)
+                }
+                NodeCursor::BeforeChild(parent) => (parent, self.children[&parent].len()),
+            };
+            self.children.get_mut(&parent).unwrap().insert(index, id);
+            self.parent.insert(id, parent);
+        }
+
+        fn remove(&mut self, id: NodeId) {
+            self.detach(id);
+            for child in self.children.remove(&id).unwrap() {
+                self.parent.remove(&child);
+                self.remove(child);
+            }
+        }
+
+        fn is_in_subtree(&self, mut id: NodeId, root: NodeId) -> bool {
+            loop {
+                if id == root {
+                    return true;
+                }
+                match self.parent.get(&id) {
+                    Some(&parent) => id = parent,
+                    None => return false,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_randomized_operations() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let mut node_tree = NodeTree::new();
+        let mut focus = FocusState::new();
+        let root = node_tree.root();
+        let mut model = Model::default();
+        model.children.insert(root, Vec::new());
+
+        for _ in 0..2000 {
+            let nodes: Vec<NodeId> = model.children.keys().cloned().collect();
+            let pick = |rng: &mut Rng| nodes[rng.below(nodes.len())];
+
+            match rng.below(4) {
+                // create a new node and insert it
+                0 => {
+                    let id = node_tree.create(Box::new(Dummy));
+                    model.children.insert(id, Vec::new());
+                    let parent = pick(&mut rng);
+                    if model.parent.contains_key(&parent) || parent == root {
+                        node_tree.insert(id, NodeCursor::BeforeChild(parent));
+                        model.insert(id, NodeCursor::BeforeChild(parent));
+                    }
+                }
+                // move a node somewhere else
+                1 => {
+                    let id = pick(&mut rng);
+                    let target = pick(&mut rng);
+                    let at = match rng.below(3) {
+                        0 => NodeCursor::Before(target),
+                        1 => NodeCursor::After(target),
+                        _ => NodeCursor::BeforeChild(target),
+                    };
+                    let valid = id != root
+                        && !model.is_in_subtree(target, id)
+                        && match at {
+                            NodeCursor::BeforeChild(_) => true,
+                            _ => model.parent.contains_key(&target),
+                        };
+                    assert_eq!(node_tree.is_valid_insertion(id, at), valid);
+                    if valid {
+                        node_tree.insert(id, at);
+                        model.insert(id, at);
+                    }
+                }
+                // detach a node
+                2 => {
+                    let id = pick(&mut rng);
+                    if id != root {
+                        node_tree.detach(id);
+                        model.detach(id);
+                    }
+                }
+                // remove a subtree
+                _ => {
+                    let id = pick(&mut rng);
+                    if id != root {
+                        node_tree.remove(id, &mut focus);
+                        model.remove(id);
+                    }
+                }
+            }
+
+            node_tree.validate().unwrap();
+            assert_eq!(node_tree.len(), model.children.len());
+            for (&id, children) in model.children.iter() {
+                assert_eq!(&node_tree.children(id).collect::<Vec<_>>(), children);
+                assert_eq!(
+                    node_tree.get(id).unwrap().parent(),
+                    model.parent.get(&id).cloned()
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_insert_under_descendant() {
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.insert(a, NodeCursor::BeforeChild(b));
+    }
 }
@@ -0,0 +1,260 @@
+//! Convenient references to nodes in a [`NodeTree`].
+use crate::{
+    key::Key,
+    layout::Measurements,
+    node::{Node, NodeId, NodeTree},
+    widget::Widget,
+    Offset, Point, Rect,
+};
+use kyute_shell::drawing::Transform;
+
+/// A reference to a node in a [`NodeTree`], used to navigate the tree and inspect the node.
+#[derive(Copy, Clone)]
+pub struct NodeRef<'a> {
+    pub tree: &'a NodeTree,
+    pub id: NodeId,
+}
+
+/// A mutable reference to a node in a [`NodeTree`].
+pub struct NodeRefMut<'a> {
+    pub tree: &'a mut NodeTree,
+    pub id: NodeId,
+}
+
+impl<'a> NodeRef<'a> {
+    fn to(&self, id: Option<NodeId>) -> Option<NodeRef<'a>> {
+        Some(NodeRef {
+            tree: self.tree,
+            id: id?,
+        })
+    }
+
+    /// Returns the node data.
+    pub fn node(&self) -> &'a Node {
+        &self.tree.nodes[self.id]
+    }
+
+    /// Returns the ID of the node.
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// Returns the key associated to the node, if any.
+    pub fn key(&self) -> Option<Key> {
+        self.node().key
+    }
+
+    pub fn parent(&self) -> Option<NodeRef<'a>> {
+        self.to(self.node().parent())
+    }
+
+    pub fn first_child(&self) -> Option<NodeRef<'a>> {
+        self.to(self.node().first_child())
+    }
+
+    pub fn last_child(&self) -> Option<NodeRef<'a>> {
+        self.to(self.node().last_child())
+    }
+
+    pub fn next_sibling(&self) -> Option<NodeRef<'a>> {
+        self.to(self.node().next_sibling())
+    }
+
+    pub fn previous_sibling(&self) -> Option<NodeRef<'a>> {
+        self.to(self.node().previous_sibling())
+    }
+
+    /// Returns an iterator over the children of the node.
+    pub fn children(&self) -> impl Iterator<Item = NodeRef<'a>> + 'a {
+        let tree = self.tree;
+        tree.children(self.id).map(move |id| NodeRef { tree, id })
+    }
+
+    /// Returns an iterator over the node and its descendants, in pre-order.
+    pub fn descendants(&self) -> impl Iterator<Item = NodeRef<'a>> + 'a {
+        let tree = self.tree;
+        tree.descendants(self.id)
+            .map(move |id| NodeRef { tree, id })
+    }
+
+    /// Returns an iterator over the node and its ancestors, up to the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = NodeRef<'a>> + 'a {
+        let tree = self.tree;
+        tree.ancestors(self.id).map(move |id| NodeRef { tree, id })
+    }
+
+    /// Returns the offset of the node relative to its parent.
+    pub fn offset(&self) -> Offset {
+        self.node().offset
+    }
+
+    /// Returns the measurements of the node computed during the last layout.
+    pub fn measurements(&self) -> Measurements {
+        self.node().measurements
+    }
+
+    /// Returns the bounds of the node in its local coordinates.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(Point::origin(), self.node().measurements.size)
+    }
+
+    /// Returns the bounds of the node in window coordinates.
+    ///
+    /// See [`NodeTree::window_bounds`].
+    pub fn window_bounds(&self) -> Rect {
+        self.tree.window_bounds(self.id)
+    }
+
+    /// Returns the transform from the local coordinates of the node to window coordinates.
+    pub fn window_transform(&self) -> Transform {
+        self.tree.window_transform(self.id)
+    }
+
+    /// Converts a point in the local coordinates of the node to window coordinates.
+    pub fn local_to_window(&self, point: Point) -> Point {
+        self.tree.local_to_window(self.id, point)
+    }
+
+    /// Returns the widget of the node, or `None` for the root node.
+    pub fn dyn_widget(&self) -> Option<&'a dyn Widget> {
+        self.node().widget.as_deref()
+    }
+
+    /// Returns the widget of the node if it is of type `T`.
+    pub fn widget<T: Widget>(&self) -> Option<&'a T> {
+        self.dyn_widget()?.downcast_ref::<T>()
+    }
+
+    /// Returns the first node of the subtree (including this node) with a widget of type `T`, in
+    /// pre-order.
+    pub fn find_widget<T: Widget>(&self) -> Option<NodeRef<'a>> {
+        self.descendants()
+            .find(|node| node.dyn_widget().map_or(false, |w| w.is::<T>()))
+    }
+}
+
+impl<'a> NodeRefMut<'a> {
+    /// Returns a shared reference to the node, for navigation and inspection.
+    pub fn to_ref(&self) -> NodeRef {
+        NodeRef {
+            tree: self.tree,
+            id: self.id,
+        }
+    }
+
+    /// Returns the node data.
+    pub fn node_mut(&mut self) -> &mut Node {
+        &mut self.tree.nodes[self.id]
+    }
+
+    /// Returns the widget of the node if it is of type `T`.
+    pub fn widget<T: Widget>(&self) -> Option<&T> {
+        self.tree.nodes[self.id]
+            .widget
+            .as_deref()?
+            .downcast_ref::<T>()
+    }
+
+    /// Returns the widget of the node if it is of type `T`.
+    pub fn widget_mut<T: Widget>(&mut self) -> Option<&mut T> {
+        self.node_mut().widget.as_deref_mut()?.downcast_mut::<T>()
+    }
+
+    /// Sets the offset of the node relative to its parent, and updates the window positions of
+    /// the subtree.
+    pub fn set_offset(&mut self, offset: Offset) {
+        let id = self.id;
+        self.tree.add_dirty_rect(self.tree.window_bounds(id));
+        self.node_mut().offset = offset;
+        self.tree.update_window_positions(id);
+        self.tree.mark_needs_paint(id);
+    }
+
+    /// Moves to the parent of the node. Returns `None` (and drops the reference) if the node has
+    /// no parent.
+    pub fn into_parent(self) -> Option<NodeRefMut<'a>> {
+        let parent = self.tree.nodes[self.id].parent()?;
+        Some(NodeRefMut {
+            tree: self.tree,
+            id: parent,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layout::{BoxConstraints, Measurements},
+        node::{NodeCursor, NodeTree, PaintCtx},
+        widget::{Dummy, LayoutCtx, Node as WidgetNode, Widget},
+        Offset, Point, Rect,
+    };
+
+    struct Counter(u32);
+
+    impl Widget for Counter {
+        fn layout(
+            &mut self,
+            _ctx: &mut LayoutCtx,
+            _children: &mut [WidgetNode],
+            _constraints: &BoxConstraints,
+        ) -> Measurements {
+            Measurements::default()
+        }
+
+        fn paint(&mut self, _ctx: &mut PaintCtx, _children: &mut [WidgetNode], _bounds: Rect) {}
+    }
+
+    #[test]
+    fn test_node_ref() {
+        // R(A(B,C))
+        let mut node_tree = NodeTree::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        let c = node_tree.create(Box::new(Counter(1)));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.insert(c, NodeCursor::BeforeChild(a));
+
+        let node_a = node_tree.node_ref(a).unwrap();
+        assert_eq!(
+            node_a.children().map(|n| n.id()).collect::<Vec<_>>(),
+            vec![b, c]
+        );
+        let node_b = node_a.first_child().unwrap();
+        assert_eq!(node_b.next_sibling().unwrap().id(), c);
+        assert_eq!(node_b.parent().unwrap().id(), a);
+        assert!(node_b.previous_sibling().is_none());
+        assert!(node_b.widget::<Dummy>().is_some());
+        assert!(node_b.widget::<Counter>().is_none());
+
+        let counter = node_tree.node_ref(root).unwrap().find_widget::<Counter>();
+        assert_eq!(counter.unwrap().id(), c);
+        assert_eq!(counter.unwrap().widget::<Counter>().unwrap().0, 1);
+        assert!(node_tree.node_ref(root).unwrap().dyn_widget().is_none());
+
+        let mut node_c = node_tree.node_mut(c).unwrap();
+        node_c.widget_mut::<Counter>().unwrap().0 += 1;
+        node_c.set_offset(Offset::new(3.0, 4.0));
+        assert_eq!(
+            node_c.to_ref().local_to_window(Point::origin()),
+            Point::new(3.0, 4.0)
+        );
+        let node_a = node_c.into_parent().unwrap();
+        assert_eq!(node_a.id, a);
+
+        assert_eq!(
+            node_tree
+                .get(c)
+                .unwrap()
+                .widget
+                .as_deref()
+                .unwrap()
+                .downcast_ref::<Counter>()
+                .unwrap()
+                .0,
+            2
+        );
+    }
+}
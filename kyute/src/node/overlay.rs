@@ -0,0 +1,221 @@
+//! Overlay layer: popups, tooltips and drag previews shown above the contents of a window.
+use crate::{
+    node::{NodeId, NodeTree},
+    Offset, Point,
+};
+
+/// Where an overlay is placed relative to its anchor node.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverlayPlacement {
+    /// Below the anchor, aligned on its left edge (e.g. drop-down menus).
+    Below,
+    /// Above the anchor, aligned on its left edge.
+    Above,
+    /// At an offset from the top-left corner of the anchor, in the local coordinates of the
+    /// anchor.
+    Offset(Offset),
+}
+
+/// An entry in the overlay layer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Overlay {
+    /// Root node of the contents of the overlay. It has no parent.
+    pub(crate) content: NodeId,
+    /// The node that created the overlay.
+    pub(crate) owner: NodeId,
+    /// The node that the overlay is positioned relative to.
+    pub(crate) anchor: NodeId,
+    pub(crate) placement: OverlayPlacement,
+}
+
+impl NodeTree {
+    /// Shows a detached node in the overlay layer.
+    ///
+    /// The overlay paints above the main contents of the tree and receives pointer events before
+    /// them. It is positioned relative to `anchor` according to `placement`; positions are updated
+    /// on [`relayout`](NodeTree::relayout) or by calling
+    /// [`update_overlay_positions`](NodeTree::update_overlay_positions). Events that are not
+    /// handled in the overlay bubble up to `owner`.
+    ///
+    /// The overlay is removed, along with its contents, when `owner` or `anchor` is removed from
+    /// the tree. Overlays added later are shown above those added earlier.
+    pub fn add_overlay(
+        &mut self,
+        content: NodeId,
+        owner: NodeId,
+        anchor: NodeId,
+        placement: OverlayPlacement,
+    ) {
+        assert!(
+            content != self.root && self.nodes[content].parent().is_none(),
+            "overlay contents must be a detached node"
+        );
+        assert!(
+            self.overlay_index(content).is_none(),
+            "node is already in the overlay layer"
+        );
+        self.overlays.push(Overlay {
+            content,
+            owner,
+            anchor,
+            placement,
+        });
+        self.update_overlay_positions();
+        self.mark_needs_paint(content);
+    }
+
+    /// Removes an overlay and its contents from the tree.
+    pub fn remove_overlay(&mut self, content: NodeId, focus: &mut super::FocusState) {
+        assert!(
+            self.overlay_index(content).is_some(),
+            "node is not in the overlay layer"
+        );
+        // `remove` takes care of the overlay entry
+        self.remove(content, focus);
+    }
+
+    /// Returns the root nodes of the overlays, from bottom to top.
+    pub fn overlays(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.overlays.iter().map(|overlay| overlay.content)
+    }
+
+    /// Returns the owner of the overlay whose contents are rooted at `content`, if it is in the
+    /// overlay layer.
+    pub fn overlay_owner(&self, content: NodeId) -> Option<NodeId> {
+        self.overlay_index(content)
+            .map(|index| self.overlays[index].owner)
+    }
+
+    /// Returns whether the overlay whose contents are rooted at `content` belongs to the window
+    /// rooted at `root`, i.e. whether its owner is in that window, possibly inside other overlays
+    /// (e.g. submenus).
+    pub(crate) fn is_overlay_in_window(&self, content: NodeId, root: NodeId) -> bool {
+        let mut content = content;
+        // there can't be more nested overlays than overlays, unless the owners form a cycle
+        for _ in 0..self.overlays.len() {
+            let owner = match self.overlay_owner(content) {
+                Some(owner) => owner,
+                None => return false,
+            };
+            let mut top = owner;
+            for id in self.ancestors(owner) {
+                if id == root {
+                    return true;
+                }
+                top = id;
+            }
+            // follow the owner of the overlay containing the owner, if any
+            content = top;
+        }
+        false
+    }
+
+    fn overlay_index(&self, content: NodeId) -> Option<usize> {
+        self.overlays
+            .iter()
+            .position(|overlay| overlay.content == content)
+    }
+
+    /// Moves the overlays next to their anchors, and updates the window positions of their
+    /// contents.
+    pub fn update_overlay_positions(&mut self) {
+        for i in 0..self.overlays.len() {
+            let overlay = self.overlays[i];
+            let anchor_size = self.nodes[overlay.anchor].measurements.size;
+            let content_size = self.nodes[overlay.content].measurements.size;
+            let local_pos = match overlay.placement {
+                OverlayPlacement::Below => Point::new(0.0, anchor_size.height),
+                OverlayPlacement::Above => Point::new(0.0, -content_size.height),
+                OverlayPlacement::Offset(offset) => Point::origin() + offset,
+            };
+            // overlays are not affected by the scale or rotation of their anchor
+            let window_pos = self.local_to_window(overlay.anchor, local_pos);
+            let offset = window_pos - self.window_origin;
+
+            if self.nodes[overlay.content].offset != offset {
+                let old_bounds = self.window_bounds(overlay.content);
+                self.add_dirty_rect(old_bounds);
+                self.nodes[overlay.content].offset = offset;
+                self.update_window_positions(overlay.content);
+                self.mark_needs_paint(overlay.content);
+            }
+        }
+    }
+
+    /// Removes the entries of the overlay layer that have their contents, owner or anchor among
+    /// `removed`, and returns the contents that are not in `removed`.
+    pub(crate) fn take_orphaned_overlays(&mut self, removed: &[NodeId]) -> Vec<NodeId> {
+        let mut orphans = Vec::new();
+        self.overlays.retain(|overlay| {
+            if removed.contains(&overlay.content) {
+                false
+            } else if removed.contains(&overlay.owner) || removed.contains(&overlay.anchor) {
+                orphans.push(overlay.content);
+                false
+            } else {
+                true
+            }
+        });
+        orphans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layout::Measurements,
+        node::{FocusState, NodeCursor, NodeTree, OverlayPlacement},
+        widget::Dummy,
+        Offset, Point, Size,
+    };
+
+    #[test]
+    fn test_overlay() {
+        // R(A(B))
+        let mut node_tree = NodeTree::new();
+        let mut focus = FocusState::new();
+        let root = node_tree.root();
+        let a = node_tree.create(Box::new(Dummy));
+        let b = node_tree.create(Box::new(Dummy));
+        node_tree.insert(a, NodeCursor::BeforeChild(root));
+        node_tree.insert(b, NodeCursor::BeforeChild(a));
+        node_tree.get_mut(a).unwrap().offset = Offset::new(10.0, 10.0);
+        node_tree.get_mut(b).unwrap().offset = Offset::new(5.0, 0.0);
+        node_tree.get_mut(b).unwrap().measurements = Measurements::new(Size::new(50.0, 20.0));
+        node_tree.update_window_positions(root);
+
+        // popup with a child, anchored below B
+        let popup = node_tree.create(Box::new(Dummy));
+        let item = node_tree.create(Box::new(Dummy));
+        node_tree.insert(item, NodeCursor::BeforeChild(popup));
+        node_tree.get_mut(item).unwrap().offset = Offset::new(0.0, 4.0);
+        node_tree.add_overlay(popup, a, b, OverlayPlacement::Below);
+
+        assert_eq!(node_tree.overlays().collect::<Vec<_>>(), vec![popup]);
+        assert_eq!(node_tree.overlay_owner(popup), Some(a));
+        assert_eq!(
+            node_tree.get(popup).unwrap().window_pos.get(),
+            Point::new(15.0, 30.0)
+        );
+        assert_eq!(
+            node_tree.get(item).unwrap().window_pos.get(),
+            Point::new(15.0, 34.0)
+        );
+
+        // follows the anchor
+        node_tree.get_mut(a).unwrap().offset = Offset::new(20.0, 10.0);
+        node_tree.update_window_positions(a);
+        node_tree.update_overlay_positions();
+        assert_eq!(
+            node_tree.get(popup).unwrap().window_pos.get(),
+            Point::new(25.0, 30.0)
+        );
+
+        // removed with its owner
+        node_tree.remove(a, &mut focus);
+        assert_eq!(node_tree.overlays().count(), 0);
+        assert!(node_tree.get(popup).is_none());
+        assert!(node_tree.get(item).is_none());
+        assert_eq!(node_tree.len(), 1);
+    }
+}
@@ -1,2 +1,124 @@
+//! Painting context.
+use crate::{
+    node::{FocusState, NodeId, NodeTree},
+    Point, Rect,
+};
+use kyute_shell::drawing::DrawContext;
+use std::ops::{Deref, DerefMut};
 
-pub struct PaintCtx {}
+/// Context passed to [`Widget::paint`](crate::widget::Widget::paint).
+///
+/// Derefs to the [`DrawContext`] of the window being painted.
+pub struct PaintCtx<'a, 'b> {
+    pub(crate) draw_ctx: &'a mut DrawContext<'b>,
+    /// The ID of the node being painted.
+    pub(crate) node_id: NodeId,
+    /// Bounds of the node being painted, in window coordinates (bounding box if the node is
+    /// transformed).
+    pub(crate) window_bounds: Rect,
+    /// Node that has the keyboard focus.
+    pub(crate) focus: Option<NodeId>,
+    /// Node that is capturing the pointer.
+    pub(crate) pointer_grab: Option<NodeId>,
+    /// Node that is under the pointer.
+    pub(crate) hot: Option<NodeId>,
+}
+
+impl<'a, 'b> PaintCtx<'a, 'b> {
+    /// Returns the bounds of the node being painted, in window coordinates.
+    pub fn window_bounds(&self) -> Rect {
+        self.window_bounds
+    }
+
+    /// Returns whether the node being painted has the keyboard focus.
+    pub fn has_focus(&self) -> bool {
+        self.focus == Some(self.node_id)
+    }
+
+    /// Returns whether the node being painted is capturing the pointer.
+    pub fn is_capturing_pointer(&self) -> bool {
+        self.pointer_grab == Some(self.node_id)
+    }
+
+    /// Returns whether the pointer is hovering over the node being painted.
+    pub fn is_hovering(&self) -> bool {
+        self.hot == Some(self.node_id)
+    }
+}
+
+impl<'a, 'b> Deref for PaintCtx<'a, 'b> {
+    type Target = DrawContext<'b>;
+
+    fn deref(&self) -> &Self::Target {
+        self.draw_ctx
+    }
+}
+
+impl<'a, 'b> DerefMut for PaintCtx<'a, 'b> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.draw_ctx
+    }
+}
+
+impl NodeTree {
+    /// Repaints the dirty region of the tree.
+    ///
+    /// See [`mark_needs_paint`](NodeTree::mark_needs_paint).
+    ///
+    /// Nodes are painted parents first, then the overlays from bottom to top, with their window
+    /// transform applied. Only the nodes that intersect the dirty region are painted, and drawing
+    /// is clipped to the region. Clears the needs-paint flags and returns the region that was
+    /// repainted, in window coordinates.
+    pub fn paint(&mut self, draw_ctx: &mut DrawContext, focus: &FocusState) -> Option<Rect> {
+        let region = self.dirty_region.take()?;
+        // overlays are painted above the main contents
+        let roots: Vec<_> = self.layer_roots().collect();
+        let nodes: Vec<_> = roots
+            .into_iter()
+            .flat_map(|root| self.descendants(root))
+            .collect();
+
+        draw_ctx.push_axis_aligned_clip(region);
+        for id in nodes {
+            let window_bounds = self.window_bounds(id);
+            let node = &mut self.nodes[id];
+            node.needs_paint = false;
+            node.child_needs_paint = false;
+            if !window_bounds.intersects(&region) {
+                continue;
+            }
+            if let Some(ref mut widget) = node.widget {
+                // avoid saving the drawing state for nodes that are only translated
+                let transform = node.window_transform.get();
+                let is_translation = transform.m11 == 1.0
+                    && transform.m12 == 0.0
+                    && transform.m21 == 0.0
+                    && transform.m22 == 1.0;
+                let bounds = if is_translation {
+                    Rect::new(node.window_pos.get(), node.measurements.size)
+                } else {
+                    draw_ctx.save();
+                    draw_ctx.transform(&transform);
+                    Rect::new(Point::origin(), node.measurements.size)
+                };
+
+                let mut ctx = PaintCtx {
+                    draw_ctx: &mut *draw_ctx,
+                    node_id: id,
+                    window_bounds,
+                    focus: focus.focus,
+                    pointer_grab: focus.pointer_grab,
+                    hot: focus.hot,
+                };
+                // children are painted by the traversal
+                widget.paint(&mut ctx, &mut [], bounds);
+                if !is_translation {
+                    draw_ctx.restore();
+                }
+            }
+        }
+        draw_ctx.pop_axis_aligned_clip();
+
+        Some(region)
+    }
+}
@@ -12,7 +12,7 @@ impl Widget for Button {
         todo!()
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, children: &[Node], bounds: Rect) {
+    fn paint(&mut self, ctx: &mut PaintCtx, children: &mut [Node], bounds: Rect) {
         todo!()
     }
 }
@@ -20,4 +20,8 @@ impl Widget for Button {
 #[track_caller]
 fn button(cx: &mut CompositionCtx, label: &str) {
     cx.enter();
+    cx.emit(|| Button {
+        label: label.to_string(),
+    });
+    cx.exit();
 }
\ No newline at end of file
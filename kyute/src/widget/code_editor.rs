@@ -0,0 +1,1211 @@
+//! Multi-line code editor with syntax highlighting, line numbers and folding.
+use crate::{
+    event::{Event, KeyboardEvent, PointerButtonEvent, WheelDeltaMode},
+    layout::{BoxConstraints, Measurements},
+    node::{EventCtx, PaintCtx},
+    widget::{LayoutCtx, Node, Widget},
+    Point, Rect, Size,
+};
+use kyute_shell::{
+    drawing::{Brush, Color, DrawTextOptions, PathGeometry},
+    text::{FontWeight, TextFormat, TextLayout},
+    winit::event::VirtualKeyCode,
+};
+use std::{ops::Range, rc::Rc};
+
+const DEFAULT_FONT_FAMILY: &str = "Consolas";
+const DEFAULT_FONT_SIZE: f32 = 13.0;
+/// Horizontal padding on both sides of the line numbers.
+const GUTTER_PADDING: f64 = 6.0;
+/// Width of the column of fold markers, between the line numbers and the text.
+const FOLD_MARKER_WIDTH: f64 = 12.0;
+/// Padding between the gutter and the text.
+const TEXT_PADDING: f64 = 4.0;
+const CARET_WIDTH: f64 = 1.0;
+const TAB_SPACES: &str = "    ";
+/// Number of rows scrolled by one wheel notch, when the delta is in lines.
+const WHEEL_SCROLL_ROWS: f64 = 3.0;
+
+/// State of a tokenizer at the start of a line, e.g. whether the line starts inside a block
+/// comment. The state at the start of the document is `0`.
+pub type TokenizerState = u32;
+
+/// Style of a token.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TokenStyle {
+    pub color: Color,
+    pub weight: FontWeight,
+}
+
+impl TokenStyle {
+    pub fn new(color: Color) -> TokenStyle {
+        TokenStyle {
+            color,
+            weight: FontWeight::Normal,
+        }
+    }
+}
+
+/// A styled range of a line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Token {
+    /// Range of the token in the line, in UTF-8 code units.
+    pub range: Range<usize>,
+    pub style: TokenStyle,
+}
+
+/// Splits lines of code into styled tokens.
+pub trait Tokenizer {
+    /// Appends the tokens of a line to `tokens`, and returns the state at the start of the next line.
+    ///
+    /// `state` is the state returned for the previous line. Text not covered by a token is drawn
+    /// with the default style.
+    fn tokenize_line(
+        &self,
+        line: &str,
+        state: TokenizerState,
+        tokens: &mut Vec<Token>,
+    ) -> TokenizerState;
+}
+
+/// Tokenizer that doesn't produce any token.
+pub struct PlainText;
+
+impl Tokenizer for PlainText {
+    fn tokenize_line(
+        &self,
+        _line: &str,
+        state: TokenizerState,
+        _tokens: &mut Vec<Token>,
+    ) -> TokenizerState {
+        state
+    }
+}
+
+/// Position in a [`CodeEditor`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct CodePosition {
+    pub line: usize,
+    /// Byte offset in the line.
+    pub column: usize,
+}
+
+impl CodePosition {
+    pub fn new(line: usize, column: usize) -> CodePosition {
+        CodePosition { line, column }
+    }
+}
+
+/// A range of lines that can be collapsed.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct FoldRegion {
+    /// Lines of the region. The first line stays visible when the region is folded.
+    pub lines: Range<usize>,
+    pub folded: bool,
+}
+
+/// A modification of the text of a [`CodeEditor`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CodeEdit {
+    /// Replaced range, in positions before the modification.
+    pub range: Range<CodePosition>,
+    /// Inserted text, which can contain line breaks.
+    pub text: String,
+}
+
+/// Actions emitted by a [`CodeEditor`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CodeEditorAction {
+    /// The text has been modified.
+    Changed(CodeEdit),
+}
+
+/// Layout of a row of the editor (a visible line).
+struct Row {
+    line: usize,
+    text_layout: TextLayout,
+    line_number: Rc<TextLayout>,
+    tokens: Vec<Token>,
+}
+
+/// Multi-line code editor.
+///
+/// Lines don't wrap. Only the lines on screen are laid out, so that the cost of layout and
+/// painting doesn't depend on the size of the document.
+pub struct CodeEditor {
+    lines: Vec<String>,
+    /// Position where the selection started.
+    anchor: CodePosition,
+    caret: CodePosition,
+    tokenizer: Box<dyn Tokenizer>,
+    /// Tokenizer state at the start of each line, for the first `line_states.len()` lines.
+    line_states: Vec<TokenizerState>,
+    /// Number of entries of `line_states` that are up to date. The others may be stale after
+    /// a modification.
+    valid_states: usize,
+    /// First line after the lines modified since they were last tokenized. Stale states are
+    /// recomputed at least up to this line.
+    modified_end: usize,
+    folds: Vec<FoldRegion>,
+    /// Ranges of lines hidden in folded regions, sorted and disjoint.
+    hidden_lines: Vec<Range<usize>>,
+    /// First row on screen.
+    first_row: usize,
+    text_format: Option<TextFormat>,
+    line_height: f64,
+    gutter_width: f64,
+    size: Size,
+    rows: Vec<Row>,
+    on_action: Option<Box<dyn FnMut(&CodeEditorAction)>>,
+}
+
+impl CodeEditor {
+    /// Creates a new editor with the specified text.
+    pub fn new(text: &str) -> CodeEditor {
+        let mut editor = CodeEditor {
+            lines: Vec::new(),
+            anchor: CodePosition::default(),
+            caret: CodePosition::default(),
+            tokenizer: Box::new(PlainText),
+            line_states: vec![0],
+            valid_states: 1,
+            modified_end: 0,
+            folds: Vec::new(),
+            hidden_lines: Vec::new(),
+            first_row: 0,
+            text_format: None,
+            line_height: 0.0,
+            gutter_width: 0.0,
+            size: Size::zero(),
+            rows: Vec::new(),
+            on_action: None,
+        };
+        editor.set_text(text);
+        editor
+    }
+
+    /// Sets the tokenizer used for syntax highlighting.
+    pub fn tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> CodeEditor {
+        self.tokenizer = Box::new(tokenizer);
+        self.reset_tokenizer_states();
+        self
+    }
+
+    /// Sets the text format used to display the text. It should use a monospace font.
+    pub fn text_format(mut self, text_format: TextFormat) -> CodeEditor {
+        self.text_format = Some(text_format);
+        self
+    }
+
+    /// Sets the function called when the editor emits a [`CodeEditorAction`].
+    pub fn on_action(mut self, f: impl FnMut(&CodeEditorAction) + 'static) -> CodeEditor {
+        self.on_action = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the current text.
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// Replaces the text, removes all fold regions and moves the caret to the start.
+    pub fn set_text(&mut self, text: &str) {
+        self.lines = text
+            .split('\n')
+            .map(|line| line.trim_end_matches('\r').to_owned())
+            .collect();
+        self.anchor = CodePosition::default();
+        self.caret = CodePosition::default();
+        self.reset_tokenizer_states();
+        self.folds.clear();
+        self.first_row = 0;
+        self.update_hidden_lines();
+    }
+
+    /// Returns the number of lines.
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Returns the position of the caret.
+    pub fn caret(&self) -> CodePosition {
+        self.caret
+    }
+
+    /// Returns the selected range, in document order.
+    pub fn selection(&self) -> Range<CodePosition> {
+        self.anchor.min(self.caret)..self.anchor.max(self.caret)
+    }
+
+    /// Adds a region that can be folded. The first line of the region stays visible.
+    ///
+    /// Regions should be nested or disjoint. Regions of less than two lines are ignored.
+    pub fn add_fold_region(&mut self, lines: Range<usize>) {
+        if lines.end > lines.start + 1 && lines.end <= self.lines.len() {
+            self.folds.push(FoldRegion {
+                lines,
+                folded: false,
+            });
+        }
+    }
+
+    /// Returns the fold regions.
+    pub fn fold_regions(&self) -> &[FoldRegion] {
+        &self.folds
+    }
+
+    /// Folds or unfolds the innermost region starting at the specified line. Returns whether
+    /// there was a region starting at this line.
+    pub fn toggle_fold(&mut self, line: usize) -> bool {
+        let region = self
+            .folds
+            .iter_mut()
+            .filter(|region| region.lines.start == line)
+            .min_by_key(|region| region.lines.end);
+        match region {
+            Some(region) => {
+                region.folded = !region.folded;
+                self.update_hidden_lines();
+                // don't leave the caret in a hidden line
+                if self.is_hidden(self.caret.line) {
+                    let end = self.lines[line].len();
+                    self.set_caret(CodePosition::new(line, end), false);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns whether the line is hidden inside a folded region.
+    fn is_hidden(&self, line: usize) -> bool {
+        self.folds
+            .iter()
+            .any(|region| region.folded && line > region.lines.start && line < region.lines.end)
+    }
+
+    fn update_hidden_lines(&mut self) {
+        let mut hidden: Vec<Range<usize>> = self
+            .folds
+            .iter()
+            .filter(|region| region.folded)
+            .map(|region| region.lines.start + 1..region.lines.end)
+            .collect();
+        hidden.sort_by_key(|range| range.start);
+
+        // merge nested and adjacent ranges
+        self.hidden_lines.clear();
+        for range in hidden {
+            match self.hidden_lines.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => self.hidden_lines.push(range),
+            }
+        }
+        self.first_row = self.first_row.min(self.row_count().saturating_sub(1));
+    }
+
+    /// Returns the number of rows, i.e. the number of lines that are not hidden.
+    fn row_count(&self) -> usize {
+        let hidden: usize = self.hidden_lines.iter().map(|range| range.len()).sum();
+        self.lines.len() - hidden
+    }
+
+    /// Returns the line displayed in the specified row.
+    fn line_of_row(&self, row: usize) -> Option<usize> {
+        let mut line = row;
+        for range in self.hidden_lines.iter() {
+            if range.start > line {
+                break;
+            }
+            line += range.len();
+        }
+        if line < self.lines.len() {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the row displaying the specified line, or the row of the fold region hiding it.
+    fn row_of_line(&self, line: usize) -> usize {
+        let mut hidden = 0;
+        for range in self.hidden_lines.iter() {
+            if line < range.start {
+                break;
+            }
+            if line < range.end {
+                // the line before the range is the first line of the fold region
+                return range.start - 1 - hidden;
+            }
+            hidden += range.len();
+        }
+        line - hidden
+    }
+
+    /// Shifts the fold regions after a change in the number of lines at `line`.
+    fn adjust_folds(&mut self, line: usize, delta: isize) {
+        if delta == 0 {
+            return;
+        }
+        let shift = |l: usize| {
+            if l > line {
+                ((l as isize + delta).max(line as isize + 1)) as usize
+            } else {
+                l
+            }
+        };
+        for region in self.folds.iter_mut() {
+            region.lines = shift(region.lines.start)..shift(region.lines.end);
+        }
+        let line_count = self.lines.len();
+        self.folds.retain(|region| {
+            region.lines.end > region.lines.start + 1 && region.lines.end <= line_count
+        });
+    }
+
+    /// Returns the number of rows that fit in the editor.
+    fn page_rows(&self) -> usize {
+        if self.line_height > 0.0 {
+            ((self.size.height / self.line_height).floor() as usize).max(1)
+        } else {
+            1
+        }
+    }
+
+    /// Scrolls so that the caret is on screen.
+    fn scroll_to_caret(&mut self) {
+        let row = self.row_of_line(self.caret.line);
+        let page = self.page_rows();
+        if row < self.first_row {
+            self.first_row = row;
+        } else if row >= self.first_row + page {
+            self.first_row = row + 1 - page;
+        }
+    }
+
+    /// Scrolls by the specified number of rows.
+    fn scroll_by(&mut self, rows: isize) {
+        let max = self.row_count().saturating_sub(1) as isize;
+        self.first_row = (self.first_row as isize + rows).max(0).min(max) as usize;
+    }
+
+    /// Returns the tokenizer state at the start of the specified line.
+    fn tokenizer_state(&mut self, line: usize) -> TokenizerState {
+        let mut tokens = Vec::new();
+        while self.valid_states <= line {
+            let i = self.valid_states - 1;
+            let state = self.line_states[i];
+            tokens.clear();
+            let next = self
+                .tokenizer
+                .tokenize_line(&self.lines[i], state, &mut tokens);
+            self.push_tokenizer_state(next);
+        }
+        self.line_states[line]
+    }
+
+    /// Stores the state at the end of the last line with an up-to-date state.
+    fn push_tokenizer_state(&mut self, next: TokenizerState) {
+        let i = self.valid_states;
+        if i == self.line_states.len() {
+            self.line_states.push(next);
+            self.valid_states += 1;
+        } else if i > self.modified_end && self.line_states[i] == next {
+            // the next line starts in the same state as before the modifications, and the lines
+            // after it are unmodified: the following states are still valid
+            self.valid_states = self.line_states.len();
+            self.modified_end = 0;
+        } else {
+            self.line_states[i] = next;
+            self.valid_states += 1;
+        }
+    }
+
+    fn reset_tokenizer_states(&mut self) {
+        self.line_states.truncate(1);
+        self.valid_states = 1;
+        self.modified_end = 0;
+    }
+
+    /// Called when `removed` lines after `line` have been replaced by `inserted` lines, and
+    /// `line` has been modified: the tokenizer states of the next lines may change.
+    fn invalidate_tokenizer_states(&mut self, line: usize, removed: usize, inserted: usize) {
+        if self.line_states.len() > line + 1 {
+            let end = (line + 1 + removed).min(self.line_states.len());
+            self.line_states.splice(line + 1..end, vec![0; inserted]);
+        }
+        if self.modified_end > line {
+            self.modified_end = (self.modified_end + inserted)
+                .saturating_sub(removed)
+                .max(line + 1);
+        }
+        self.modified_end = self.modified_end.max(line + inserted + 1);
+        self.valid_states = self.valid_states.min(line + 1);
+    }
+
+    fn clamp_position(&self, pos: CodePosition) -> CodePosition {
+        let line = pos.line.min(self.lines.len() - 1);
+        let text = &self.lines[line];
+        let mut column = pos.column.min(text.len());
+        while !text.is_char_boundary(column) {
+            column -= 1;
+        }
+        CodePosition::new(line, column)
+    }
+
+    /// Moves the caret, optionally extending the selection.
+    fn set_caret(&mut self, pos: CodePosition, extend: bool) {
+        self.caret = self.clamp_position(pos);
+        if !extend {
+            self.anchor = self.caret;
+        }
+    }
+
+    /// Returns the position of the character before `pos`, possibly on the previous line.
+    fn prev_position(&self, pos: CodePosition) -> CodePosition {
+        if pos.column > 0 {
+            let text = &self.lines[pos.line][..pos.column];
+            let len = text.chars().next_back().map(|c| c.len_utf8()).unwrap_or(0);
+            CodePosition::new(pos.line, pos.column - len)
+        } else if pos.line > 0 {
+            // skip hidden lines
+            let row = self.row_of_line(pos.line);
+            let line = match row.checked_sub(1).and_then(|row| self.line_of_row(row)) {
+                Some(line) => line,
+                None => pos.line - 1,
+            };
+            CodePosition::new(line, self.lines[line].len())
+        } else {
+            pos
+        }
+    }
+
+    /// Returns the position of the character after `pos`, possibly on the next line.
+    fn next_position(&self, pos: CodePosition) -> CodePosition {
+        let text = &self.lines[pos.line];
+        if pos.column < text.len() {
+            let len = text[pos.column..]
+                .chars()
+                .next()
+                .map(|c| c.len_utf8())
+                .unwrap_or(0);
+            CodePosition::new(pos.line, pos.column + len)
+        } else {
+            let row = self.row_of_line(pos.line);
+            match self.line_of_row(row + 1) {
+                Some(line) => CodePosition::new(line, 0),
+                None => pos,
+            }
+        }
+    }
+
+    /// Returns the position `rows` rows above or below `pos`, at the same column if possible.
+    fn vertical_position(&self, pos: CodePosition, rows: isize) -> CodePosition {
+        let row = self.row_of_line(pos.line) as isize + rows;
+        if row < 0 {
+            return CodePosition::new(0, 0);
+        }
+        match self.line_of_row(row as usize) {
+            Some(line) => self.clamp_position(CodePosition::new(line, pos.column)),
+            None => {
+                let last = self.lines.len() - 1;
+                CodePosition::new(last, self.lines[last].len())
+            }
+        }
+    }
+
+    /// Removes the selected text. Returns the modification, or `None` if the selection was
+    /// empty.
+    fn delete_selection(&mut self) -> Option<CodeEdit> {
+        let Range { start, end } = self.selection();
+        if start == end {
+            return None;
+        }
+        Some(self.delete_range(start, end))
+    }
+
+    /// Removes the text between two positions, and moves the caret to `start`.
+    fn delete_range(&mut self, start: CodePosition, end: CodePosition) -> CodeEdit {
+        if start.line == end.line {
+            self.lines[start.line].replace_range(start.column..end.column, "");
+            self.invalidate_tokenizer_states(start.line, 0, 0);
+        } else {
+            let tail = self.lines[end.line][end.column..].to_owned();
+            self.lines[start.line].truncate(start.column);
+            self.lines[start.line].push_str(&tail);
+            self.lines.drain(start.line + 1..=end.line);
+            self.invalidate_tokenizer_states(start.line, end.line - start.line, 0);
+            self.adjust_folds(start.line, -((end.line - start.line) as isize));
+            self.update_hidden_lines();
+        }
+        self.set_caret(start, false);
+        CodeEdit {
+            range: start..end,
+            text: String::new(),
+        }
+    }
+
+    /// Replaces the selected text with the specified string, which can contain line breaks.
+    fn insert_str(&mut self, s: &str) -> CodeEdit {
+        let range = self.selection();
+        self.delete_selection();
+        let CodePosition { line, column } = self.caret;
+        let mut new_lines = s.split('\n').map(|l| l.trim_end_matches('\r'));
+        let first = new_lines.next().unwrap_or("");
+        let rest: Vec<String> = new_lines.map(str::to_owned).collect();
+        let mut text = first.to_owned();
+        for l in rest.iter() {
+            text.push('\n');
+            text.push_str(l);
+        }
+
+        if rest.is_empty() {
+            self.lines[line].insert_str(column, first);
+            self.invalidate_tokenizer_states(line, 0, 0);
+            self.set_caret(CodePosition::new(line, column + first.len()), false);
+        } else {
+            let tail = self.lines[line].split_off(column);
+            self.lines[line].push_str(first);
+            let count = rest.len();
+            let caret_column = rest[count - 1].len();
+            self.lines.splice(line + 1..line + 1, rest);
+            self.lines[line + count].push_str(&tail);
+            self.invalidate_tokenizer_states(line, 0, count);
+            self.adjust_folds(line, count as isize);
+            self.update_hidden_lines();
+            self.set_caret(CodePosition::new(line + count, caret_column), false);
+        }
+        CodeEdit { range, text }
+    }
+
+    /// Inserts a line break, keeping the indentation of the current line.
+    fn insert_newline(&mut self) -> CodeEdit {
+        let line = &self.lines[self.selection().start.line];
+        let indent_len = line.len() - line.trim_start_matches(|c| c == ' ' || c == '\t').len();
+        let mut s = String::from("\n");
+        s.push_str(&line[..indent_len]);
+        self.insert_str(&s)
+    }
+
+    fn emit_action(&mut self, action: CodeEditorAction) {
+        if let Some(ref mut on_action) = self.on_action {
+            on_action(&action);
+        }
+    }
+
+    /// Called after the text was modified.
+    fn text_changed(&mut self, ctx: &mut EventCtx, edit: CodeEdit) {
+        self.scroll_to_caret();
+        self.emit_action(CodeEditorAction::Changed(edit));
+        ctx.request_relayout();
+    }
+
+    /// Returns the horizontal position of the text.
+    fn text_x(&self) -> f64 {
+        self.gutter_width + TEXT_PADDING
+    }
+
+    /// Returns the position in the text closest to the specified point in local coordinates.
+    fn position_at_point(&self, point: Point) -> CodePosition {
+        let row = if point.y < 0.0 || self.line_height <= 0.0 {
+            self.first_row
+        } else {
+            self.first_row + (point.y / self.line_height) as usize
+        };
+        let line = match self.line_of_row(row) {
+            Some(line) => line,
+            None => {
+                let last = self.lines.len() - 1;
+                return CodePosition::new(last, self.lines[last].len());
+            }
+        };
+        let column = self
+            .rows
+            .iter()
+            .find(|r| r.line == line)
+            .and_then(|r| {
+                let hit = r
+                    .text_layout
+                    .hit_test_point(Point::new(point.x - self.text_x(), 0.0))
+                    .ok()?;
+                Some(if hit.is_trailing_hit {
+                    hit.metrics.text_position + hit.metrics.length
+                } else {
+                    hit.metrics.text_position
+                })
+            })
+            .unwrap_or(0);
+        self.clamp_position(CodePosition::new(line, column))
+    }
+
+    fn pointer_down(&mut self, ctx: &mut EventCtx, event: &PointerButtonEvent) {
+        let position = event.pointer.position;
+        if position.x < self.gutter_width {
+            // click in the gutter: toggle the fold region starting at this line
+            let pos = self.position_at_point(position);
+            if self.toggle_fold(pos.line) {
+                ctx.request_relayout();
+            }
+        } else {
+            let pos = self.position_at_point(position);
+            if event.repeat_count == 2 {
+                // double-click selects the whole line
+                let len = self.lines[pos.line].len();
+                self.set_caret(CodePosition::new(pos.line, 0), false);
+                self.set_caret(CodePosition::new(pos.line, len), true);
+            } else {
+                self.set_caret(pos, event.pointer.modifiers.shift());
+            }
+            ctx.capture_pointer();
+        }
+        ctx.request_focus();
+        ctx.set_handled();
+        ctx.request_redraw();
+    }
+
+    fn key_down(&mut self, ctx: &mut EventCtx, event: &KeyboardEvent) {
+        let shift = event.modifiers.shift();
+        let ctrl = event.modifiers.ctrl();
+        let caret = self.caret;
+        let page = self.page_rows() as isize;
+
+        match event.key {
+            Some(VirtualKeyCode::Left) => {
+                let pos = self.prev_position(caret);
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Right) => {
+                let pos = self.next_position(caret);
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Up) => {
+                let pos = self.vertical_position(caret, -1);
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Down) => {
+                let pos = self.vertical_position(caret, 1);
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::PageUp) => {
+                let pos = self.vertical_position(caret, -page);
+                self.scroll_by(-page);
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::PageDown) => {
+                let pos = self.vertical_position(caret, page);
+                self.scroll_by(page);
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Home) => {
+                let pos = if ctrl {
+                    CodePosition::new(0, 0)
+                } else {
+                    CodePosition::new(caret.line, 0)
+                };
+                self.set_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::End) => {
+                let line = if ctrl {
+                    self.lines.len() - 1
+                } else {
+                    caret.line
+                };
+                self.set_caret(CodePosition::new(line, self.lines[line].len()), shift);
+            }
+            Some(VirtualKeyCode::A) if ctrl => {
+                let last = self.lines.len() - 1;
+                self.set_caret(CodePosition::new(0, 0), false);
+                self.set_caret(CodePosition::new(last, self.lines[last].len()), true);
+            }
+            Some(VirtualKeyCode::Back) => {
+                let edit = match self.delete_selection() {
+                    Some(edit) => edit,
+                    None => {
+                        let start = self.prev_position(caret);
+                        if start == caret {
+                            // at the start of the document
+                            ctx.set_handled();
+                            return;
+                        }
+                        self.delete_range(start, caret)
+                    }
+                };
+                self.text_changed(ctx, edit);
+            }
+            Some(VirtualKeyCode::Delete) => {
+                let edit = match self.delete_selection() {
+                    Some(edit) => edit,
+                    None => {
+                        let end = self.next_position(caret);
+                        if end == caret {
+                            // at the end of the document
+                            ctx.set_handled();
+                            return;
+                        }
+                        self.delete_range(caret, end)
+                    }
+                };
+                self.text_changed(ctx, edit);
+            }
+            Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::NumpadEnter) => {
+                let edit = self.insert_newline();
+                self.text_changed(ctx, edit);
+            }
+            Some(VirtualKeyCode::Tab) => {
+                let edit = self.insert_str(TAB_SPACES);
+                self.text_changed(ctx, edit);
+            }
+            _ => return,
+        }
+
+        self.scroll_to_caret();
+        // the rows on screen may have changed
+        ctx.request_relayout();
+        ctx.set_handled();
+        ctx.request_redraw();
+    }
+
+    /// Re-creates the layouts of the rows on screen.
+    fn layout_rows(&mut self, ctx: &mut LayoutCtx) {
+        let text_format = match self.text_format {
+            Some(ref text_format) => text_format.clone(),
+            None => match TextFormat::builder()
+                .family(DEFAULT_FONT_FAMILY)
+                .size(DEFAULT_FONT_SIZE)
+                .build()
+            {
+                Ok(text_format) => {
+                    self.text_format = Some(text_format.clone());
+                    text_format
+                }
+                Err(err) => {
+                    tracing::error!("failed to create text format: {}", err);
+                    self.rows.clear();
+                    return;
+                }
+            },
+        };
+        let scale_factor = ctx.scale_factor();
+
+        // all rows have the same height, so that the rows on screen can be found without laying out
+        // the lines before them
+        self.line_height = match text_format.font_metrics(scale_factor) {
+            Ok(metrics) => metrics.line_height(),
+            Err(err) => {
+                tracing::error!("failed to get font metrics: {}", err);
+                (DEFAULT_FONT_SIZE * 1.25) as f64
+            }
+        };
+
+        let unbounded = Size::new(f32::MAX as f64, f32::MAX as f64);
+        let digits = self.lines.len().to_string().len();
+        self.gutter_width = match ctx.text_layout_cache().get_or_create(
+            &"0".repeat(digits),
+            &text_format,
+            unbounded,
+            scale_factor,
+        ) {
+            Ok(layout) => layout.metrics().width_including_trailing_whitespace as f64,
+            Err(_) => 0.0,
+        } + 2.0 * GUTTER_PADDING
+            + FOLD_MARKER_WIDTH;
+
+        self.rows.clear();
+        let page = self.page_rows() + 1;
+        for row in self.first_row..self.first_row + page {
+            let line = match self.line_of_row(row) {
+                Some(line) => line,
+                None => break,
+            };
+            let state = self.tokenizer_state(line);
+            let mut tokens = Vec::new();
+            let next = self
+                .tokenizer
+                .tokenize_line(&self.lines[line], state, &mut tokens);
+            if self.valid_states == line + 1 {
+                self.push_tokenizer_state(next);
+            }
+
+            let text_layout = match TextLayout::new(&self.lines[line], &text_format, unbounded) {
+                Ok(mut text_layout) => {
+                    for token in tokens.iter() {
+                        if token.style.weight != FontWeight::Normal {
+                            text_layout.set_font_weight(token.style.weight, token.range.clone());
+                        }
+                    }
+                    text_layout
+                }
+                Err(err) => {
+                    tracing::error!("failed to create text layout: {}", err);
+                    continue;
+                }
+            };
+            // line numbers are the same from one layout to the next
+            let line_number = match ctx.text_layout_cache().get_or_create(
+                &(line + 1).to_string(),
+                &text_format,
+                unbounded,
+                scale_factor,
+            ) {
+                Ok(layout) => layout,
+                Err(_) => continue,
+            };
+            self.rows.push(Row {
+                line,
+                text_layout,
+                line_number,
+                tokens,
+            });
+        }
+    }
+}
+
+impl Widget for CodeEditor {
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        _children: &mut [Node],
+        constraints: &BoxConstraints,
+    ) -> Measurements {
+        // fill the available space
+        self.size =
+            constraints.constrain(Size::new(constraints.max_width(), constraints.max_height()));
+        self.layout_rows(ctx);
+        Measurements::new(self.size)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _children: &mut [Node], bounds: Rect) {
+        let background = Brush::new_solid_color(ctx, Color::new(0.12, 0.12, 0.12, 1.0));
+        let gutter_background = Brush::new_solid_color(ctx, Color::new(0.15, 0.15, 0.15, 1.0));
+        let current_line = Brush::new_solid_color(ctx, Color::new(0.18, 0.18, 0.2, 1.0));
+        let selection_brush = Brush::new_solid_color(ctx, Color::new(0.2, 0.35, 0.6, 1.0));
+        let line_number_brush = Brush::new_solid_color(ctx, Color::new(0.5, 0.5, 0.5, 1.0));
+        let text_brush = Brush::new_solid_color(ctx, Color::new(0.9, 0.9, 0.9, 1.0));
+
+        ctx.fill_rectangle(bounds, &background);
+        ctx.push_axis_aligned_clip(bounds);
+
+        let gutter = Rect::new(
+            bounds.origin,
+            Size::new(self.gutter_width, bounds.size.height),
+        );
+        ctx.fill_rectangle(gutter, &gutter_background);
+
+        let text_x = bounds.origin.x + self.text_x();
+        let selection = self.selection();
+        let caret_row = self.row_of_line(self.caret.line);
+        let has_focus = ctx.has_focus();
+
+        for (i, row) in self.rows.iter_mut().enumerate() {
+            let y = bounds.origin.y + (i as f64) * self.line_height;
+            let row_bounds = Rect::new(
+                Point::new(bounds.origin.x + self.gutter_width, y),
+                Size::new(bounds.size.width - self.gutter_width, self.line_height),
+            );
+            let line_len = self.lines[row.line].len();
+
+            // current line highlight
+            if self.first_row + i == caret_row && selection.start == selection.end {
+                ctx.fill_rectangle(row_bounds, &current_line);
+            }
+
+            // selection
+            if selection.start.line <= row.line && row.line <= selection.end.line {
+                let start = if selection.start.line == row.line {
+                    selection.start.column
+                } else {
+                    0
+                };
+                let end = if selection.end.line == row.line {
+                    selection.end.column
+                } else {
+                    line_len
+                };
+                if let Ok(hits) = row
+                    .text_layout
+                    .hit_test_text_range(start..end, &Point::new(text_x, y))
+                {
+                    for hit in hits.iter() {
+                        ctx.fill_rectangle(hit.bounds, &selection_brush);
+                    }
+                }
+            }
+
+            // line number, right-aligned before the fold markers
+            let number_width = row
+                .line_number
+                .metrics()
+                .width_including_trailing_whitespace as f64;
+            ctx.draw_text_layout(
+                Point::new(
+                    bounds.origin.x + self.gutter_width
+                        - FOLD_MARKER_WIDTH
+                        - GUTTER_PADDING
+                        - number_width,
+                    y,
+                ),
+                &row.line_number,
+                &line_number_brush,
+                DrawTextOptions::default(),
+            );
+
+            // fold marker: a triangle pointing right if the region is folded, down otherwise
+            if let Some(region) = self
+                .folds
+                .iter()
+                .filter(|region| region.lines.start == row.line)
+                .min_by_key(|region| region.lines.end)
+            {
+                let cx = bounds.origin.x + self.gutter_width - 0.5 * FOLD_MARKER_WIDTH;
+                let cy = y + 0.5 * self.line_height;
+                let r = 0.25 * FOLD_MARKER_WIDTH;
+                let points = if region.folded {
+                    [
+                        Point::new(cx - 0.5 * r, cy - r),
+                        Point::new(cx + 0.5 * r, cy),
+                        Point::new(cx - 0.5 * r, cy + r),
+                    ]
+                } else {
+                    [
+                        Point::new(cx - r, cy - 0.5 * r),
+                        Point::new(cx, cy + 0.5 * r),
+                        Point::new(cx + r, cy - 0.5 * r),
+                    ]
+                };
+                if let Ok(marker) = PathGeometry::polyline(&points) {
+                    ctx.draw_geometry(&marker, &line_number_brush, 1.0);
+                }
+            }
+
+            // syntax highlighting: brushes are only available when painting
+            let mut brushes: Vec<(Color, Brush)> = Vec::new();
+            for token in row.tokens.iter() {
+                if token.range.end > line_len || token.range.start >= token.range.end {
+                    continue;
+                }
+                let index = match brushes
+                    .iter()
+                    .position(|(color, _)| *color == token.style.color)
+                {
+                    Some(index) => index,
+                    None => {
+                        brushes.push((
+                            token.style.color,
+                            Brush::new_solid_color(ctx, token.style.color),
+                        ));
+                        brushes.len() - 1
+                    }
+                };
+                row.text_layout
+                    .set_drawing_effect(&brushes[index].1, token.range.clone());
+            }
+            ctx.draw_text_layout(
+                Point::new(text_x, y),
+                &row.text_layout,
+                &text_brush,
+                DrawTextOptions::default(),
+            );
+
+            // caret
+            if has_focus && row.line == self.caret.line {
+                if let Ok(hit) = row.text_layout.hit_test_text_position(self.caret.column) {
+                    ctx.fill_rectangle(
+                        Rect::new(
+                            Point::new(text_x + hit.point.x, y),
+                            Size::new(CARET_WIDTH, self.line_height),
+                        ),
+                        &text_brush,
+                    );
+                }
+            }
+        }
+
+        ctx.pop_axis_aligned_clip();
+    }
+
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event) {
+        match event {
+            Event::PointerDown(p) => {
+                self.pointer_down(ctx, p);
+            }
+            Event::PointerMove(p) => {
+                if ctx.is_capturing_pointer() {
+                    let pos = self.position_at_point(p.position);
+                    self.set_caret(pos, true);
+                    // scroll when dragging past the top or bottom
+                    let old_first_row = self.first_row;
+                    self.scroll_to_caret();
+                    if self.first_row != old_first_row {
+                        ctx.request_relayout();
+                    }
+                    ctx.set_handled();
+                    ctx.request_redraw();
+                }
+            }
+            Event::PointerUp(_) => {
+                if ctx.is_capturing_pointer() {
+                    ctx.release_pointer();
+                    ctx.set_handled();
+                }
+            }
+            Event::Wheel(wheel) => {
+                let rows = match wheel.delta_mode {
+                    WheelDeltaMode::Pixel => -wheel.delta_y / self.line_height.max(1.0),
+                    WheelDeltaMode::Line => -wheel.delta_y * WHEEL_SCROLL_ROWS,
+                    WheelDeltaMode::Page => -wheel.delta_y * self.page_rows() as f64,
+                };
+                self.scroll_by(rows.round() as isize);
+                ctx.request_relayout();
+                ctx.set_handled();
+                ctx.request_redraw();
+            }
+            Event::KeyDown(k) => {
+                if ctx.has_focus() {
+                    self.key_down(ctx, k);
+                }
+            }
+            Event::Input(input) => {
+                // control characters (backspace, enter, tab...) are handled in KeyDown
+                if ctx.has_focus() && !input.character.is_control() {
+                    let mut buf = [0u8; 4];
+                    let edit = self.insert_str(input.character.encode_utf8(&mut buf));
+                    self.text_changed(ctx, edit);
+                    ctx.set_handled();
+                    ctx.request_redraw();
+                }
+            }
+            Event::FocusIn | Event::FocusOut => {
+                ctx.request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_edit_lines() {
+        let mut editor = CodeEditor::new("fn main() {\n}");
+        editor.set_caret(CodePosition::new(0, 11), false);
+        editor.insert_newline();
+        editor.insert_str("    let x = 0;");
+        assert_eq!(editor.text(), "fn main() {\n    let x = 0;\n}");
+        assert_eq!(editor.caret(), CodePosition::new(1, 14));
+
+        // join lines with backspace at the start of a line
+        editor.set_caret(CodePosition::new(2, 0), false);
+        let start = editor.prev_position(editor.caret());
+        editor.delete_range(start, editor.caret());
+        assert_eq!(editor.text(), "fn main() {\n    let x = 0;}");
+        assert_eq!(editor.caret(), CodePosition::new(1, 14));
+
+        // multi-line selection
+        editor.set_caret(CodePosition::new(0, 3), false);
+        editor.set_caret(CodePosition::new(1, 4), true);
+        assert_eq!(
+            editor.insert_str("a\r\nb"),
+            CodeEdit {
+                range: CodePosition::new(0, 3)..CodePosition::new(1, 4),
+                text: "a\nb".to_owned(),
+            }
+        );
+        assert_eq!(editor.text(), "fn a\nblet x = 0;}");
+        assert_eq!(editor.line_count(), 2);
+    }
+
+    fn visible_lines(editor: &CodeEditor) -> Vec<usize> {
+        (0..editor.row_count())
+            .map(|row| editor.line_of_row(row).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_folding() {
+        let mut editor = CodeEditor::new("0\n1\n2\n3\n4\n5");
+        editor.add_fold_region(1..4);
+        editor.add_fold_region(2..4);
+        assert_eq!(visible_lines(&editor), vec![0, 1, 2, 3, 4, 5]);
+
+        editor.toggle_fold(2);
+        assert_eq!(visible_lines(&editor), vec![0, 1, 2, 4, 5]);
+        editor.toggle_fold(1);
+        assert_eq!(visible_lines(&editor), vec![0, 1, 4, 5]);
+        // navigation skips the hidden lines
+        assert_eq!(
+            editor.next_position(CodePosition::new(1, 1)),
+            CodePosition::new(4, 0)
+        );
+        assert_eq!(
+            editor.prev_position(CodePosition::new(4, 0)),
+            CodePosition::new(1, 1)
+        );
+
+        // inserting lines before a region shifts it
+        editor.set_caret(CodePosition::new(0, 1), false);
+        editor.insert_str("\nx");
+        assert_eq!(editor.fold_regions()[0].lines, 2..5);
+        assert_eq!(visible_lines(&editor), vec![0, 1, 2, 5, 6]);
+
+        editor.toggle_fold(2);
+        editor.toggle_fold(3);
+        assert_eq!(visible_lines(&editor), vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    /// Tokenizer for block comments, which counts the lines it tokenizes.
+    struct BlockComments(Rc<Cell<usize>>);
+
+    impl Tokenizer for BlockComments {
+        fn tokenize_line(
+            &self,
+            line: &str,
+            mut state: TokenizerState,
+            _tokens: &mut Vec<Token>,
+        ) -> TokenizerState {
+            self.0.set(self.0.get() + 1);
+            if line.contains("/*") {
+                state = 1;
+            }
+            if line.contains("*/") {
+                state = 0;
+            }
+            state
+        }
+    }
+
+    #[test]
+    fn test_tokenizer_states() {
+        let count = Rc::new(Cell::new(0));
+        let text = vec!["x"; 100].join("\n");
+        let mut editor = CodeEditor::new(&text).tokenizer(BlockComments(count.clone()));
+        assert_eq!(editor.tokenizer_state(99), 0);
+        assert_eq!(count.get(), 99);
+
+        // the state after the modified line is unchanged: the next lines are not tokenized again
+        count.set(0);
+        editor.set_caret(CodePosition::new(10, 0), false);
+        editor.insert_str("y\ny");
+        assert_eq!(editor.tokenizer_state(100), 0);
+        assert_eq!(count.get(), 3);
+
+        // opening a comment changes the state of all the next lines
+        count.set(0);
+        editor.set_caret(CodePosition::new(20, 0), false);
+        editor.insert_str("/*");
+        assert_eq!(editor.tokenizer_state(100), 1);
+        assert_eq!(count.get(), 80);
+
+        // the modification of a later line is not skipped
+        count.set(0);
+        editor.set_caret(CodePosition::new(50, 0), false);
+        editor.insert_str("*/");
+        editor.set_caret(CodePosition::new(10, 0), false);
+        editor.insert_str("z");
+        assert_eq!(editor.tokenizer_state(100), 0);
+        assert_eq!(editor.tokenizer_state(40), 1);
+        assert_eq!(count.get(), 90);
+    }
+}
@@ -0,0 +1,1428 @@
+//! Positional composition of widget trees.
+//!
+//! Composable functions describe the children of a node by calling [`CompositionCtx::emit`], and
+//! keep state between passes with [`CompositionCtx::state`]. Both are identified by the source
+//! location of the call, within the innermost *scope* entered with [`CompositionCtx::enter`] or
+//! [`CompositionCtx::scope`]. Scopes are themselves keyed by the location where they are entered,
+//! so a composable called conditionally, or whose call is moved relative to its siblings, keeps
+//! its state and its nodes.
+//!
+//! Calls repeated at the same location in a scope (e.g. in a loop) are told apart by their index.
+//! When the items of a loop can be reordered, use [`CompositionCtx::with_key`] to key them by an
+//! identity instead.
+//!
+//! Writing to a [`State`] marks the scope that owns it as dirty. Nothing is recomposed until the
+//! owner of the [`Composer`] calls [`Composer::recompose`]. Scopes entered with
+//! [`CompositionCtx::restartable`] are then recomposed on their own, without running the
+//! composable functions of the rest of the composition; a dirty scope outside of any restartable
+//! scope causes the whole composition to be recomposed.
+//!
+//! [`CompositionCtx::memo`] skips the composable functions of a scope when their inputs haven't
+//! changed since the previous pass: the nodes and state entries of the scope are kept as they are.
+use crate::{
+    key::Key,
+    widget::{
+        scope_table::{Entry, EntryKind, EntryType, ScopeTable},
+        state::{DirtyScopes, State},
+        Node, Widget,
+    },
+};
+use std::{
+    any::Any,
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    iter, mem,
+    rc::Rc,
+};
+
+/// ID of the root scope of a composition.
+const ROOT_SCOPE_ID: u64 = 0;
+
+/// What's needed to recompose a restartable scope on its own.
+pub(crate) struct Restart {
+    /// Keys of the nodes on the path from the root to the node whose children are composed by
+    /// the scope.
+    path: Vec<Key>,
+    /// Index of the first child emitted by the scope.
+    node_pos: usize,
+    /// Number of children emitted by the scope.
+    node_len: usize,
+    content: Rc<dyn Fn(&mut CompositionCtx)>,
+}
+
+/// Inputs and emitted nodes of a memoized scope.
+pub(crate) struct Memo {
+    inputs: Box<dyn Any>,
+    /// Same as `Restart::path`.
+    path: Vec<Key>,
+    /// Index of the first child emitted by the scope.
+    node_pos: usize,
+    /// Keys of the children emitted by the scope.
+    nodes: Vec<Key>,
+    /// Number of scopes in the scope, including itself.
+    scopes: usize,
+}
+
+/// Statistics about the last composition pass.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompositionStats {
+    /// Number of memoized scopes whose content was run.
+    pub memo_misses: usize,
+    /// Number of memoized scopes whose content was skipped because their inputs didn't change.
+    pub memo_hits: usize,
+    /// Number of scopes that were skipped, including the scopes nested in skipped memoized
+    /// scopes.
+    pub skipped_scopes: usize,
+}
+
+/// Path from the root of the composition to the node being composed.
+#[derive(Copy, Clone)]
+enum NodePath<'a> {
+    Keys(&'a [Key]),
+    Child(Key, &'a NodePath<'a>),
+}
+
+impl<'a> NodePath<'a> {
+    fn to_vec(self) -> Vec<Key> {
+        match self {
+            NodePath::Keys(keys) => keys.to_vec(),
+            NodePath::Child(key, parent) => {
+                let mut keys = parent.to_vec();
+                keys.push(key);
+                keys
+            }
+        }
+    }
+
+    /// Returns whether this is the path made of `keys`, without allocating.
+    fn is(self, keys: &[Key]) -> bool {
+        match self {
+            NodePath::Keys(k) => k == keys,
+            NodePath::Child(key, parent) => match keys.split_last() {
+                Some((&last, rest)) => last == key && parent.is(rest),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Position in a scope being composed.
+struct Frame {
+    /// Position of the start of the scope in the table.
+    start: usize,
+    /// Position of the end of the scope, counted from the end of the table.
+    ///
+    /// Entries are only inserted or removed inside the innermost scope, so this doesn't change
+    /// while the scope is being composed.
+    end_from_back: usize,
+    /// ID of the scope.
+    id: u64,
+    /// Number of times each key was used in the scope during this pass.
+    occurrences: HashMap<Key, u32>,
+    /// Groups of entries of the scope that were skipped during this pass, because an entry
+    /// further in the scope was looked up. They are dropped when exiting the scope.
+    pending: HashMap<(Key, EntryType), Vec<Entry>>,
+    /// Keys of the entries of the scope that weren't visited yet, collected on the first lookup
+    /// that doesn't find the entry at the cursor. Avoids scanning the scope for new entries.
+    unvisited: Option<HashSet<(Key, EntryType)>>,
+}
+
+impl Frame {
+    fn new(start: usize, end_from_back: usize, id: u64) -> Frame {
+        Frame {
+            start,
+            end_from_back,
+            id,
+            occurrences: HashMap::new(),
+            pending: HashMap::new(),
+            unvisited: None,
+        }
+    }
+}
+
+/// Number of moves after which the scope index is rebuilt.
+const MAX_INDEX_MOVES: usize = 32;
+
+/// Location of a scope in the table.
+struct ScopeLocation {
+    /// ID of the scope that contains it.
+    parent: u64,
+    /// Position of the start of the scope when it was indexed.
+    pos: usize,
+    /// Number of moves in the index when the scope was indexed.
+    moves: usize,
+}
+
+/// Change of the table made by recomposing a restartable scope on its own.
+struct TableMove {
+    /// End of the scope (exclusive) before it was recomposed.
+    old_end: usize,
+    /// End of the scope (exclusive) after it was recomposed.
+    new_end: usize,
+}
+
+/// Positions of the scopes in the table, and the restartable and memoized scopes that compose the
+/// children of each node, so that recomposing a scope doesn't scan the table.
+///
+/// The index is built when it's first needed after a full pass. Recomposing a restartable scope
+/// on its own moves the entries that follow it: the move is recorded and applied to the
+/// positions indexed before it when they are looked up, and the scopes inside are indexed again.
+#[derive(Default)]
+struct ScopeIndex {
+    /// Whether the index matches the table.
+    valid: bool,
+    scopes: HashMap<u64, ScopeLocation>,
+    /// Moves since the index was built.
+    moves: Vec<TableMove>,
+    /// IDs of the restartable and memoized scopes, by path of the node whose children they
+    /// compose.
+    node_scopes: HashMap<Vec<Key>, HashSet<u64>>,
+}
+
+impl ScopeIndex {
+    /// Marks the index as outdated, after the table was modified by a full pass.
+    fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    /// Builds the index again if it's outdated, or if too many moves accumulated.
+    fn update(&mut self, table: &ScopeTable) {
+        if self.valid && self.moves.len() < MAX_INDEX_MOVES {
+            return;
+        }
+        self.scopes.clear();
+        self.moves.clear();
+        self.node_scopes.clear();
+        self.insert_range(table, 0, table.len(), ROOT_SCOPE_ID);
+        self.valid = true;
+    }
+
+    /// Indexes the scopes in `start..end`, which is a part of the content of the scope `parent`.
+    fn insert_range(&mut self, table: &ScopeTable, start: usize, end: usize, parent: u64) {
+        let mut parents = vec![parent];
+        for (pos, entry) in (start..end).zip(table.iter(start, end)) {
+            let parent = *parents.last().unwrap();
+            match entry.kind {
+                EntryKind::ScopeStart {
+                    id, ref restart, ..
+                } => {
+                    let moves = self.moves.len();
+                    self.scopes.insert(id, ScopeLocation { parent, pos, moves });
+                    if let Some(restart) = restart {
+                        self.node_scopes
+                            .entry(restart.path.clone())
+                            .or_default()
+                            .insert(id);
+                    }
+                    parents.push(id);
+                }
+                // first entry of the memoized scope
+                EntryKind::Memo(ref memo) => {
+                    self.node_scopes
+                        .entry(memo.path.clone())
+                        .or_default()
+                        .insert(parent);
+                }
+                EntryKind::ScopeEnd => {
+                    parents.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Removes the scopes in `start..end` from the index.
+    fn remove_range(&mut self, table: &ScopeTable, start: usize, end: usize) {
+        let mut scope = None;
+        for entry in table.iter(start, end) {
+            let path = match entry.kind {
+                EntryKind::ScopeStart {
+                    id, ref restart, ..
+                } => {
+                    self.scopes.remove(&id);
+                    scope = Some(id);
+                    restart.as_ref().map(|restart| &restart.path)
+                }
+                EntryKind::Memo(ref memo) => Some(&memo.path),
+                _ => None,
+            };
+            if let (Some(path), Some(id)) = (path, scope) {
+                if let Some(ids) = self.node_scopes.get_mut(path) {
+                    ids.remove(&id);
+                }
+            }
+        }
+    }
+
+    /// Returns the position of the start of a scope, or `None` if it was removed.
+    fn position(&self, id: u64) -> Option<usize> {
+        let location = self.scopes.get(&id)?;
+        let mut pos = location.pos;
+        for table_move in &self.moves[location.moves..] {
+            if pos >= table_move.old_end {
+                pos = pos - table_move.old_end + table_move.new_end;
+            }
+        }
+        Some(pos)
+    }
+
+    /// Returns the IDs of a scope and of the scopes that contain it, innermost first.
+    fn ancestors(&self, id: u64) -> impl Iterator<Item = u64> + '_ {
+        iter::successors(Some(id), move |id| {
+            self.scopes.get(id).map(|location| location.parent)
+        })
+        .take_while(|&id| id != ROOT_SCOPE_ID)
+    }
+
+    /// Returns the IDs of the restartable and memoized scopes that compose the children of the
+    /// node at `path`.
+    fn node_scopes(&self, path: &[Key]) -> Vec<u64> {
+        self.node_scopes
+            .get(path)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Holds the state of a composition between passes.
+pub struct Composer {
+    table: ScopeTable,
+    index: ScopeIndex,
+    /// Position of the next entry in the table.
+    pos: usize,
+    /// Scopes entered during the current pass, outermost first.
+    frames: Vec<Frame>,
+    /// Scopes whose state was modified since the last pass.
+    dirty: DirtyScopes,
+    /// Scopes whose state was modified before the current pass, and the scopes that contain
+    /// them. They can't be skipped.
+    invalid: HashSet<u64>,
+    stats: CompositionStats,
+}
+
+impl Composer {
+    pub fn new() -> Composer {
+        Composer {
+            table: ScopeTable::new(),
+            index: ScopeIndex::default(),
+            pos: 0,
+            frames: vec![],
+            dirty: DirtyScopes::default(),
+            invalid: HashSet::new(),
+            stats: CompositionStats::default(),
+        }
+    }
+
+    /// Returns statistics about the last pass.
+    pub fn stats(&self) -> CompositionStats {
+        self.stats
+    }
+
+    /// Returns whether a state entry of the composition was modified since the last pass.
+    pub fn needs_recomposition(&self) -> bool {
+        !self.dirty.borrow().is_empty()
+    }
+
+    /// Recomposes the children of `node` with the composable function `f`.
+    ///
+    /// State entries, scopes and child nodes that were created in the previous pass but not
+    /// during this one are removed.
+    pub fn compose(&mut self, node: &mut Node, f: impl FnOnce(&mut CompositionCtx)) {
+        assert!(self.frames.is_empty(), "recursive call to `compose`");
+        self.take_dirty();
+        self.stats = CompositionStats::default();
+        self.index.invalidate();
+        // the root scope has no start and end entries
+        self.pos = 0;
+        self.frames.push(Frame::new(0, 0, ROOT_SCOPE_ID));
+        f(&mut CompositionCtx::new(self, node, NodePath::Keys(&[]), 0));
+        assert_eq!(
+            self.frames.len(),
+            1,
+            "unbalanced calls to `enter` and `exit`"
+        );
+        self.frames.pop();
+        self.table.remove_range(self.pos, self.table.len());
+        self.invalid.clear();
+    }
+
+    /// Recomposes the scopes whose state was modified since the last pass.
+    ///
+    /// Each modified scope is recomposed by running the content of the closest restartable scope
+    /// that contains it. If one of them isn't in a restartable scope, the whole composition is
+    /// recomposed with `f`, like [`compose`](Composer::compose).
+    pub fn recompose(&mut self, node: &mut Node, f: impl FnOnce(&mut CompositionCtx)) {
+        assert!(self.frames.is_empty(), "recursive call to `recompose`");
+        let dirty = self.dirty.borrow().clone();
+        self.index.update(&self.table);
+
+        let mut restarted = Vec::new();
+        for id in dirty {
+            if id == ROOT_SCOPE_ID {
+                self.compose(node, f);
+                return;
+            }
+            // the scope may have been removed since it was modified
+            if self.index.position(id).is_none() {
+                continue;
+            }
+            let (table, index) = (&self.table, &self.index);
+            let restartable = index
+                .ancestors(id)
+                .map(|id| (index.position(id).unwrap(), id))
+                .find(|&(pos, _)| table.restart(pos).is_some());
+            match restartable {
+                Some(scope) => restarted.push(scope),
+                None => {
+                    self.compose(node, f);
+                    return;
+                }
+            }
+        }
+
+        // in table order, so that enclosing scopes are recomposed first
+        restarted.sort_unstable();
+        restarted.dedup();
+        self.take_dirty();
+        self.stats = CompositionStats::default();
+        let mut done = HashSet::new();
+        for (_, id) in restarted {
+            let pos = match self.index.position(id) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            // skip scopes that were recomposed with an enclosing scope
+            if self.index.ancestors(id).any(|id| done.contains(&id)) {
+                continue;
+            }
+            let index = &self.index;
+            let ancestors: Vec<usize> = index
+                .ancestors(id)
+                .skip(1)
+                .map(|id| index.position(id).unwrap())
+                .collect();
+            self.restart(node, pos, &ancestors);
+            done.insert(id);
+        }
+        self.invalid.clear();
+    }
+
+    /// Recomposes the restartable scope starting at `start`.
+    fn restart(&mut self, root: &mut Node, start: usize, ancestors: &[usize]) {
+        let id = self.table.scope_id(start);
+        let old_len = self.table.group_len(start);
+        let restart = self.table.restart(start).unwrap();
+        let path = restart.path.clone();
+        let node_pos = restart.node_pos;
+        let old_node_len = restart.node_len;
+        let content = restart.content.clone();
+
+        let mut node = root;
+        for key in path.iter() {
+            node = node
+                .children
+                .iter_mut()
+                .find(|child| child.key == Some(*key))
+                .expect("node of restartable scope not found");
+        }
+
+        self.pos = start + 1;
+        let end = start + old_len - 1;
+        self.frames
+            .push(Frame::new(start, self.table.len() - end, id));
+        let old_scopes = self.table.scope_count(start, end);
+        // the content of the scope is indexed again once it's composed
+        self.index.remove_range(&self.table, start + 1, end);
+        // set aside the children that follow those of the scope, so that they aren't removed
+        // with the children that weren't emitted
+        let tail = node.children.split_off(node_pos + old_node_len);
+        content(&mut CompositionCtx::new(
+            self,
+            node,
+            NodePath::Keys(&path),
+            node_pos,
+        ));
+        let new_node_len = node.children.len() - node_pos;
+        node.children.extend(tail);
+        assert_eq!(
+            self.frames.len(),
+            1,
+            "unbalanced calls to `enter` and `exit`"
+        );
+
+        let end = self.scope_end();
+        self.table.remove_range(self.pos, end);
+        self.frames.pop();
+        let new_len = self.pos + 1 - start;
+        self.index.moves.push(TableMove {
+            old_end: start + old_len,
+            new_end: start + new_len,
+        });
+        self.index
+            .insert_range(&self.table, start + 1, start + new_len - 1, id);
+        self.table.set_scope_len(start, new_len);
+        for &pos in ancestors {
+            let len = self.table.group_len(pos) - old_len + new_len;
+            self.table.set_scope_len(pos, len);
+        }
+        self.table.restart_mut(start).unwrap().node_len = new_node_len;
+
+        // the enclosing memoized scopes are skipped if their inputs don't change: they must keep
+        // the nodes emitted by the scope
+        let new_scopes = self.table.scope_count(start, start + new_len);
+        for &pos in ancestors {
+            if let EntryKind::Memo(ref mut memo) = self.table.get_mut(pos + 1).kind {
+                memo.scopes = memo.scopes + new_scopes - old_scopes;
+                if memo.path == path {
+                    let len = memo.nodes.len() + new_node_len - old_node_len;
+                    memo.nodes = node.children[memo.node_pos..memo.node_pos + len]
+                        .iter()
+                        .map(|node| node.key.unwrap())
+                        .collect();
+                }
+            }
+        }
+
+        // the other restartable and memoized scopes that compose children of the same node must
+        // follow the change in the number of children
+        if new_node_len != old_node_len {
+            for id in self.index.node_scopes(&path) {
+                let pos = self.index.position(id).unwrap();
+                if pos >= start + new_len {
+                    // the start of the scope, followed by the inputs if it's memoized
+                    self.move_node_positions(
+                        pos,
+                        pos + 2,
+                        NodePath::Keys(&path),
+                        old_node_len,
+                        new_node_len,
+                    );
+                }
+            }
+            for &pos in ancestors {
+                match self.table.restart_mut(pos) {
+                    Some(restart) if restart.path == path => {
+                        restart.node_len = restart.node_len + new_node_len - old_node_len;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Marks the scopes whose state was modified, and the scopes that contain them, as invalid.
+    fn take_dirty(&mut self) {
+        let dirty = mem::take(&mut *self.dirty.borrow_mut());
+        self.invalid.clear();
+        if dirty.is_empty() {
+            return;
+        }
+        self.index.update(&self.table);
+        for id in dirty {
+            for id in self.index.ancestors(id) {
+                if !self.invalid.insert(id) {
+                    // the enclosing scopes were already added
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Moves the node positions of the restartable and memoized scopes in `start..end` that
+    /// compose children of the node at `path`, from `old_pos` to `new_pos`.
+    fn move_node_positions(
+        &mut self,
+        start: usize,
+        end: usize,
+        path: NodePath,
+        old_pos: usize,
+        new_pos: usize,
+    ) {
+        for pos in start..end {
+            match self.table.get_mut(pos).kind {
+                EntryKind::ScopeStart {
+                    restart: Some(ref mut restart),
+                    ..
+                } if path.is(&restart.path) => {
+                    restart.node_pos = restart.node_pos + new_pos - old_pos;
+                }
+                EntryKind::Memo(ref mut memo) if path.is(&memo.path) => {
+                    memo.node_pos = memo.node_pos + new_pos - old_pos;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Returns the position of the end of the innermost scope being composed.
+    fn scope_end(&self) -> usize {
+        self.table.len() - self.frames.last().unwrap().end_from_back
+    }
+
+    /// Makes a key unique in the current scope, by combining it with the number of times it was
+    /// already used during this pass.
+    fn disambiguate(&mut self, key: Key) -> Key {
+        let count = self.frame().occurrences.entry(key).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            key
+        } else {
+            key.combine(*count - 1)
+        }
+    }
+
+    /// Looks for an entry in the part of the current scope that wasn't visited yet, and moves it
+    /// (with its group) at the current position.
+    ///
+    /// Returns whether an entry was found.
+    fn find(&mut self, key: Key, ty: EntryType) -> bool {
+        let end = self.scope_end();
+        if self.pos < end && self.table.get(self.pos).matches(key, ty) {
+            // same structure as the previous pass
+            return true;
+        }
+
+        let pos = self.pos;
+        if let Some(group) = self.frame().pending.remove(&(key, ty)) {
+            self.table.insert_group(pos, group);
+            return true;
+        }
+
+        let table = &self.table;
+        let unvisited = self
+            .frames
+            .last_mut()
+            .unwrap()
+            .unvisited
+            .get_or_insert_with(|| table.keys(pos, end));
+        if !unvisited.contains(&(key, ty)) {
+            return false;
+        }
+
+        if let Some(found) = self.table.find(pos, end, key, ty) {
+            // set aside the groups in between, they may be looked up later in this pass
+            let mut skipped = found - pos;
+            while skipped > 0 {
+                let entry = self.table.get(pos);
+                let group_key = (entry.key, entry.ty().unwrap());
+                let group = self.table.take_group(pos);
+                skipped -= group.len();
+                self.frame().pending.insert(group_key, group);
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for Composer {
+    fn default() -> Self {
+        Composer::new()
+    }
+}
+
+/// Context passed to composable functions, to emit the children of a node.
+///
+/// When the context is dropped, the children of the node that weren't emitted are removed.
+pub struct CompositionCtx<'a> {
+    composer: &'a mut Composer,
+    /// The parent node for which we are recomposing the children of.
+    parent: &'a mut Node,
+    /// Where we should emit the next node in the list of children.
+    pos: usize,
+    /// Children that were skipped during this pass, because a node further in the list was
+    /// emitted. They are removed when the context is dropped.
+    pending: HashMap<Key, Node>,
+    /// Keys of the children that weren't emitted yet, collected on the first node that isn't
+    /// found at `pos`.
+    unvisited: Option<HashSet<Key>>,
+    /// Path to the parent node.
+    path: NodePath<'a>,
+}
+
+impl<'a> CompositionCtx<'a> {
+    fn new(
+        composer: &'a mut Composer,
+        parent: &'a mut Node,
+        path: NodePath<'a>,
+        pos: usize,
+    ) -> CompositionCtx<'a> {
+        CompositionCtx {
+            composer,
+            parent,
+            pos,
+            pending: HashMap::new(),
+            unvisited: None,
+            path,
+        }
+    }
+
+    /// Enters a composition scope, keyed by the location of the caller.
+    ///
+    /// Must be balanced with a call to [`exit`](CompositionCtx::exit). Composable functions
+    /// are usually marked `#[track_caller]` and enter a scope on entry, so that each call site of
+    /// the function gets its own state.
+    #[track_caller]
+    pub fn enter(&mut self) {
+        self.enter_scope(Key::from_caller())
+    }
+
+    fn enter_scope(&mut self, key: Key) {
+        let composer = &mut *self.composer;
+        let key = composer.disambiguate(key);
+        let start = composer.pos;
+        if !composer.find(key, EntryType::Scope) {
+            let mut hasher = DefaultHasher::new();
+            composer.frame().id.hash(&mut hasher);
+            key.hash(&mut hasher);
+            composer.table.insert_scope(start, key, hasher.finish());
+        }
+        let id = match composer.table.get(start).kind {
+            EntryKind::ScopeStart { id, .. } => id,
+            _ => unreachable!(),
+        };
+        let end = start + composer.table.group_len(start) - 1;
+        composer
+            .frames
+            .push(Frame::new(start, composer.table.len() - end, id));
+        composer.pos = start + 1;
+    }
+
+    /// Exits the current composition scope.
+    ///
+    /// The slots of the scope that weren't visited since the matching
+    /// [`enter`](CompositionCtx::enter) are removed.
+    pub fn exit(&mut self) {
+        assert!(
+            self.composer.frames.len() > 1,
+            "`exit` called without a matching `enter`"
+        );
+        let composer = &mut *self.composer;
+        let end = composer.scope_end();
+        composer.table.remove_range(composer.pos, end);
+        let frame = composer.frames.pop().unwrap();
+        composer
+            .table
+            .set_scope_len(frame.start, composer.pos + 1 - frame.start);
+        composer.pos += 1;
+    }
+
+    /// Runs `f` in a composition scope keyed by the location of the caller.
+    #[track_caller]
+    pub fn scope<R>(&mut self, f: impl FnOnce(&mut CompositionCtx) -> R) -> R {
+        self.enter_scope(Key::from_caller());
+        let result = f(self);
+        self.exit();
+        result
+    }
+
+    /// Runs `f` in a composition scope keyed by the location of the caller and `id`.
+    ///
+    /// Use this for composables called in a loop, with an `id` that identifies the item, so that
+    /// their state and nodes follow the items when they are reordered, inserted or removed.
+    #[track_caller]
+    pub fn with_key<R>(&mut self, id: impl Hash, f: impl FnOnce(&mut CompositionCtx) -> R) -> R {
+        self.enter_scope(Key::with_id(id));
+        let result = f(self);
+        self.exit();
+        result
+    }
+
+    /// Runs `content` in a composition scope keyed by the location of the caller, that
+    /// [`Composer::recompose`] can recompose on its own when its state changes.
+    ///
+    /// `content` composes the children of the parent node of this context, after those already
+    /// emitted. It is kept until the next pass, so it can't borrow from the caller: values that it
+    /// needs should be moved into it.
+    #[track_caller]
+    pub fn restartable(&mut self, content: impl Fn(&mut CompositionCtx) + 'static) {
+        self.enter_scope(Key::from_caller());
+        let node_pos = self.pos;
+        content(self);
+        let start = self.composer.frame().start;
+        self.composer.table.set_restart(
+            start,
+            Restart {
+                path: self.path.to_vec(),
+                node_pos,
+                node_len: self.pos - node_pos,
+                content: Rc::new(content),
+            },
+        );
+        self.exit();
+    }
+
+    /// Runs `content` in a composition scope keyed by the location of the caller, unless `inputs`
+    /// is equal to the inputs of the previous pass.
+    ///
+    /// When the inputs are equal, the nodes and the state entries of the previous pass are kept
+    /// as they are. The scope is composed anyway if one of its state entries was modified. Inputs
+    /// that don't implement `PartialEq` can be compared by passing a hash of them.
+    #[track_caller]
+    pub fn memo<T>(&mut self, inputs: T, content: impl FnOnce(&mut CompositionCtx))
+    where
+        T: PartialEq + 'static,
+    {
+        self.enter_scope(Key::from_caller());
+        let composer = &mut *self.composer;
+        let start = composer.frame().start;
+        let id = composer.frame().id;
+        let end = composer.scope_end();
+        let node_pos = self.pos;
+
+        if start + 1 < end {
+            if let EntryKind::Memo(ref memo) = composer.table.get(start + 1).kind {
+                if memo.inputs.downcast_ref::<T>() == Some(&inputs)
+                    && !composer.invalid.contains(&id)
+                {
+                    let old_pos = memo.node_pos;
+                    let scopes = memo.scopes;
+                    let nodes = memo.nodes.clone();
+                    // if a node can't be found, the content is composed again, and finds the nodes
+                    // that were already moved in place
+                    if nodes.into_iter().all(|key| self.take_child(key, |_| true)) {
+                        let composer = &mut *self.composer;
+                        if old_pos != node_pos {
+                            composer.move_node_positions(start, end, self.path, old_pos, node_pos);
+                        }
+                        composer.pos = end;
+                        composer.stats.memo_hits += 1;
+                        composer.stats.skipped_scopes += scopes;
+                        self.exit();
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.pos = node_pos;
+        let composer = &mut *self.composer;
+        let memo_key = Key::from_caller();
+        if !matches!(composer.table.get(start + 1).kind, EntryKind::Memo(_)) {
+            // placeholder, filled after the content is composed
+            let memo = Memo {
+                inputs: Box::new(()),
+                path: vec![],
+                node_pos: 0,
+                nodes: vec![],
+                scopes: 0,
+            };
+            composer
+                .table
+                .insert(start + 1, memo_key, EntryKind::Memo(Box::new(memo)));
+        }
+        composer.pos = start + 2;
+        content(self);
+
+        let nodes = self.parent.children[node_pos..self.pos]
+            .iter()
+            .map(|node| node.key.unwrap())
+            .collect();
+        let composer = &mut *self.composer;
+        let scopes = composer.table.scope_count(start, composer.pos);
+        if let EntryKind::Memo(ref mut memo) = composer.table.get_mut(start + 1).kind {
+            memo.inputs = Box::new(inputs);
+            if !self.path.is(&memo.path) {
+                memo.path = self.path.to_vec();
+            }
+            memo.node_pos = node_pos;
+            memo.nodes = nodes;
+            memo.scopes = scopes;
+        }
+        composer.stats.memo_misses += 1;
+        self.exit();
+    }
+
+    /// Returns a handle to a state entry of the current scope, creating it with `init` if it
+    /// doesn't exist.
+    ///
+    /// Writing to the state through the handle marks the current scope as dirty, so that the next
+    /// call to [`Composer::recompose`] recomposes it.
+    #[track_caller]
+    pub fn state<T: 'static>(&mut self, init: impl FnOnce() -> T) -> State<T> {
+        let composer = &mut *self.composer;
+        let key = composer.disambiguate(Key::from_caller());
+        let pos = composer.pos;
+        if !composer.find(key, EntryType::State) {
+            let state = State::new(init(), composer.frame().id, composer.dirty.clone());
+            composer
+                .table
+                .insert(pos, key, EntryKind::State(Box::new(state)));
+        }
+        composer.pos += 1;
+        match composer.table.get(pos).kind {
+            // the key identifies the call site, so the type should match
+            EntryKind::State(ref data) => data
+                .downcast_ref::<State<T>>()
+                .expect("unexpected state type")
+                .clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Emits (get or create) a child widget, keyed by the location of the caller in the current
+    /// scope.
+    ///
+    /// Returns the context to compose the children of the emitted node.
+    #[track_caller]
+    pub fn emit<T>(&mut self, init: impl FnOnce() -> T) -> CompositionCtx<'_>
+    where
+        T: Widget,
+    {
+        let composer = &mut *self.composer;
+        let key = composer
+            .disambiguate(Key::from_caller())
+            .combine(composer.frame().id);
+        if !composer.find(key, EntryType::Node) {
+            composer.table.insert(composer.pos, key, EntryKind::Node);
+        }
+        composer.pos += 1;
+
+        let pos = self.pos;
+        if !self.take_child(key, |node| node.widget.is::<T>()) {
+            // create and insert a new node with the provided constructor
+            let mut node = Node::new(Box::new(init()));
+            node.key = Some(key);
+            self.parent.children.insert(pos, node);
+            self.pos += 1;
+        }
+
+        // create child composition context
+        CompositionCtx::new(
+            &mut *self.composer,
+            &mut self.parent.children[pos],
+            NodePath::Child(key, &self.path),
+            0,
+        )
+    }
+}
+
+impl<'a> CompositionCtx<'a> {
+    /// Moves the child node with the specified key at the current position, if it exists and
+    /// matches `is_match`, and advances the position.
+    fn take_child(&mut self, key: Key, is_match: impl Fn(&Node) -> bool) -> bool {
+        let pos = self.pos;
+        let children = &mut self.parent.children;
+        let is_match = |node: &Node| node.key == Some(key) && is_match(node);
+        if !children.get(pos).map_or(false, is_match) {
+            if self.pending.get(&key).map_or(false, is_match) {
+                children.insert(pos, self.pending.remove(&key).unwrap());
+            } else {
+                let unvisited = self.unvisited.get_or_insert_with(|| {
+                    children[pos..].iter().filter_map(|node| node.key).collect()
+                });
+                let index = if unvisited.contains(&key) {
+                    children[pos..].iter().position(is_match)
+                } else {
+                    None
+                };
+                match index {
+                    Some(index) => {
+                        // found an existing node: set aside the nodes in between, they may be
+                        // emitted later in this pass
+                        for mut node in children.drain(pos..pos + index) {
+                            match node.key {
+                                Some(key) => {
+                                    self.pending.insert(key, node);
+                                }
+                                None => node.unmount(),
+                            }
+                        }
+                    }
+                    None => return false,
+                }
+            }
+        }
+        self.pos += 1;
+        true
+    }
+}
+
+impl<'a> Drop for CompositionCtx<'a> {
+    fn drop(&mut self) {
+        for mut node in self.parent.children.drain(self.pos..) {
+            node.unmount();
+        }
+        for (_, mut node) in self.pending.drain() {
+            node.unmount();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        layout::{BoxConstraints, Measurements},
+        node::PaintCtx,
+        widget::{Composer, CompositionCtx, Dummy, LayoutCtx, Node, State, Widget},
+        Rect,
+    };
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        rc::Rc,
+    };
+
+    struct Tag(u32);
+
+    impl Widget for Tag {
+        fn layout(
+            &mut self,
+            _ctx: &mut LayoutCtx,
+            _children: &mut [Node],
+            _constraints: &BoxConstraints,
+        ) -> Measurements {
+            Measurements::default()
+        }
+
+        fn paint(&mut self, _ctx: &mut PaintCtx, _children: &mut [Node], _bounds: Rect) {}
+    }
+
+    fn next(counter: &Cell<u32>) -> u32 {
+        counter.set(counter.get() + 1);
+        counter.get()
+    }
+
+    /// Returns the values of the `Tag` children of a node.
+    fn tags(node: &Node) -> Vec<u32> {
+        node.children
+            .iter()
+            .map(|child| child.widget.downcast_ref::<Tag>().unwrap().0)
+            .collect()
+    }
+
+    /// Emits a `Tag` node and returns the value of a state entry, both created from `counter`.
+    #[track_caller]
+    fn tagged(cx: &mut CompositionCtx, counter: &Cell<u32>) -> u32 {
+        cx.enter();
+        let value = cx.state(|| next(counter)).get();
+        cx.emit(|| Tag(next(counter)));
+        cx.exit();
+        value
+    }
+
+    fn part_a(cx: &mut CompositionCtx, counter: &Cell<u32>) -> u32 {
+        cx.scope(|cx| tagged(cx, counter))
+    }
+
+    fn part_b(cx: &mut CompositionCtx, counter: &Cell<u32>) -> u32 {
+        cx.scope(|cx| tagged(cx, counter))
+    }
+
+    fn conditional(cx: &mut CompositionCtx, counter: &Cell<u32>, show: bool) -> Vec<u32> {
+        let mut values = vec![];
+        if show {
+            values.push(tagged(cx, counter));
+        }
+        values.push(tagged(cx, counter));
+        values
+    }
+
+    #[test]
+    fn test_scope_insertion_and_removal() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let counter = Cell::new(0);
+        let mut values = vec![];
+
+        composer.compose(&mut root, |cx| values = conditional(cx, &counter, false));
+        assert_eq!(values, vec![1]);
+        assert_eq!(tags(&root), vec![2]);
+
+        // insertion before an existing scope
+        composer.compose(&mut root, |cx| values = conditional(cx, &counter, true));
+        assert_eq!(values, vec![3, 1]);
+        assert_eq!(tags(&root), vec![4, 2]);
+
+        // recomposing with the same structure doesn't create anything
+        composer.compose(&mut root, |cx| values = conditional(cx, &counter, true));
+        assert_eq!(values, vec![3, 1]);
+        assert_eq!(tags(&root), vec![4, 2]);
+
+        // removal: the state and the node of the removed scope are dropped
+        composer.compose(&mut root, |cx| values = conditional(cx, &counter, false));
+        assert_eq!(values, vec![1]);
+        assert_eq!(tags(&root), vec![2]);
+        composer.compose(&mut root, |cx| values = conditional(cx, &counter, true));
+        assert_eq!(values, vec![5, 1]);
+        assert_eq!(tags(&root), vec![6, 2]);
+    }
+
+    #[test]
+    fn test_scope_reordering() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let counter = Cell::new(0);
+        let mut values = vec![];
+
+        composer.compose(&mut root, |cx| {
+            values = vec![part_a(cx, &counter), part_b(cx, &counter)]
+        });
+        assert_eq!(values, vec![1, 3]);
+        assert_eq!(tags(&root), vec![2, 4]);
+
+        composer.compose(&mut root, |cx| {
+            values = vec![part_b(cx, &counter), part_a(cx, &counter)]
+        });
+        assert_eq!(values, vec![3, 1]);
+        assert_eq!(tags(&root), vec![4, 2]);
+        assert_eq!(counter.get(), 4);
+    }
+
+    #[test]
+    fn test_nested_nodes() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let counter = Cell::new(0);
+
+        let mut compose = |root: &mut Node, show: bool| {
+            composer.compose(root, |cx| {
+                let mut inner = cx.emit(|| Tag(0));
+                conditional(&mut inner, &counter, show);
+            })
+        };
+        compose(&mut root, false);
+        assert_eq!(tags(&root.children[0]), vec![2]);
+        compose(&mut root, true);
+        assert_eq!(tags(&root.children[0]), vec![4, 2]);
+        compose(&mut root, false);
+        assert_eq!(tags(&root.children[0]), vec![2]);
+        assert_eq!(tags(&root), vec![0]);
+    }
+
+    fn list(cx: &mut CompositionCtx, counter: &Cell<u32>, items: &[u32]) -> Vec<u32> {
+        items
+            .iter()
+            .map(|&item| cx.with_key(item, |cx| tagged(cx, counter)))
+            .collect()
+    }
+
+    #[test]
+    fn test_keyed_scopes() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let counter = Cell::new(0);
+        let mut values = vec![];
+
+        composer.compose(&mut root, |cx| values = list(cx, &counter, &[1, 2, 3]));
+        assert_eq!(values, vec![1, 3, 5]);
+        assert_eq!(tags(&root), vec![2, 4, 6]);
+
+        // reordering
+        composer.compose(&mut root, |cx| values = list(cx, &counter, &[3, 1, 2]));
+        assert_eq!(values, vec![5, 1, 3]);
+        assert_eq!(tags(&root), vec![6, 2, 4]);
+
+        // removal in the middle, and insertion
+        composer.compose(&mut root, |cx| values = list(cx, &counter, &[3, 4, 2]));
+        assert_eq!(values, vec![5, 7, 3]);
+        assert_eq!(tags(&root), vec![6, 8, 4]);
+
+        // duplicate keys are told apart by their index
+        composer.compose(&mut root, |cx| values = list(cx, &counter, &[3, 3, 2]));
+        assert_eq!(values, vec![5, 9, 3]);
+        assert_eq!(tags(&root), vec![6, 10, 4]);
+    }
+
+    #[test]
+    fn test_repeated_calls() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let counter = Cell::new(0);
+        let mut values = vec![];
+
+        let mut compose = |root: &mut Node, values: &mut Vec<u32>, count: usize| {
+            composer.compose(root, |cx| {
+                *values = (0..count).map(|_| tagged(cx, &counter)).collect();
+                // repeated state entries and nodes in the same scope
+                for _ in 0..count {
+                    cx.state(|| 0);
+                    cx.emit(|| Tag(0));
+                }
+            })
+        };
+
+        compose(&mut root, &mut values, 2);
+        assert_eq!(values, vec![1, 3]);
+        assert_eq!(tags(&root), vec![2, 4, 0, 0]);
+        compose(&mut root, &mut values, 3);
+        assert_eq!(values, vec![1, 3, 5]);
+        assert_eq!(tags(&root), vec![2, 4, 6, 0, 0, 0]);
+        compose(&mut root, &mut values, 1);
+        assert_eq!(values, vec![1]);
+        assert_eq!(tags(&root), vec![2, 0]);
+    }
+
+    #[test]
+    fn test_state_handles() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let mut handle = None;
+
+        let mut compose = |root: &mut Node, handle: &mut Option<State<u32>>| {
+            composer.compose(root, |cx| *handle = Some(cx.state(|| 1)));
+        };
+        compose(&mut root, &mut handle);
+        let first = handle.clone().unwrap();
+        assert_eq!(first.get(), 1);
+
+        first.set(2);
+        first.update(|value| *value += 1);
+        compose(&mut root, &mut handle);
+        // the handle refers to the same entry across passes
+        assert!(first.ptr_eq(handle.as_ref().unwrap()));
+        assert_eq!(handle.unwrap().get(), 3);
+    }
+
+    #[test]
+    fn test_restartable_scopes() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let outer_runs = Cell::new(0);
+        let content_runs = Rc::new(Cell::new(0));
+        let count: Rc<RefCell<Option<State<u32>>>> = Rc::new(RefCell::new(None));
+        let outer: RefCell<Option<State<u32>>> = RefCell::new(None);
+
+        let compose = |cx: &mut CompositionCtx| {
+            outer_runs.set(outer_runs.get() + 1);
+            *outer.borrow_mut() = Some(cx.state(|| 0));
+            let mut inner = cx.emit(|| Tag(100));
+            inner.emit(|| Tag(50));
+            let content_runs = content_runs.clone();
+            let count = count.clone();
+            inner.restartable(move |cx| {
+                content_runs.set(content_runs.get() + 1);
+                let state = cx.state(|| 1);
+                for i in 0..state.get() {
+                    cx.emit(|| Tag(i));
+                }
+                *count.borrow_mut() = Some(state);
+            });
+            inner.emit(|| Tag(60));
+        };
+
+        composer.compose(&mut root, compose);
+        assert_eq!(tags(&root.children[0]), vec![50, 0, 60]);
+        assert!(!composer.needs_recomposition());
+
+        // only the content of the restartable scope runs again
+        count.borrow().as_ref().unwrap().set(3);
+        assert!(composer.needs_recomposition());
+        composer.recompose(&mut root, compose);
+        assert!(!composer.needs_recomposition());
+        assert_eq!(tags(&root.children[0]), vec![50, 0, 1, 2, 60]);
+        assert_eq!((outer_runs.get(), content_runs.get()), (1, 2));
+
+        count.borrow().as_ref().unwrap().set(0);
+        composer.recompose(&mut root, compose);
+        assert_eq!(tags(&root.children[0]), vec![50, 60]);
+        assert_eq!((outer_runs.get(), content_runs.get()), (1, 3));
+
+        // state outside of a restartable scope: everything is recomposed
+        outer.borrow().as_ref().unwrap().set(1);
+        composer.recompose(&mut root, compose);
+        assert_eq!(tags(&root.children[0]), vec![50, 60]);
+        assert_eq!((outer_runs.get(), content_runs.get()), (2, 4));
+        assert_eq!(count.borrow().as_ref().unwrap().get(), 0);
+    }
+
+    /// Emits `count` `Tag` nodes from `base`, in a restartable scope. The `count` state is
+    /// stored in `state`.
+    #[track_caller]
+    fn counted(cx: &mut CompositionCtx, base: u32, state: &Rc<RefCell<Option<State<u32>>>>) {
+        let state = state.clone();
+        cx.restartable(move |cx| {
+            let count = cx.state(|| 1);
+            for i in 0..count.get() {
+                cx.emit(|| Tag(base + i));
+            }
+            *state.borrow_mut() = Some(count);
+        });
+    }
+
+    #[test]
+    fn test_restartable_siblings() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let runs = Cell::new(0);
+        let a = Rc::new(RefCell::new(None));
+        let b = Rc::new(RefCell::new(None));
+        let set = |state: &Rc<RefCell<Option<State<u32>>>>, count| {
+            state.borrow().as_ref().unwrap().set(count)
+        };
+
+        let compose = |cx: &mut CompositionCtx| {
+            cx.emit(|| Tag(100));
+            counted(cx, 0, &a);
+            memo_list(cx, &runs, &[50]);
+            counted(cx, 10, &b);
+            cx.emit(|| Tag(200));
+        };
+
+        composer.compose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 0, 50, 10, 200]);
+
+        // the nodes of the following scopes move
+        set(&a, 3);
+        composer.recompose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 0, 1, 2, 50, 10, 200]);
+
+        // both scopes are recomposed, the second one after the first one moved it
+        set(&a, 0);
+        set(&b, 2);
+        composer.recompose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 50, 10, 11, 200]);
+        set(&b, 1);
+        composer.recompose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 50, 10, 200]);
+
+        composer.compose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 50, 10, 200]);
+        assert_eq!((runs.get(), composer.stats().memo_hits), (1, 1));
+    }
+
+    #[test]
+    fn test_restartable_in_memo() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let count = Rc::new(RefCell::new(None));
+
+        let compose = |cx: &mut CompositionCtx| {
+            cx.emit(|| Tag(100));
+            cx.memo((), |cx| counted(cx, 0, &count));
+            cx.emit(|| Tag(200));
+        };
+
+        composer.compose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 0, 200]);
+
+        count.borrow().as_ref().unwrap().set(3);
+        composer.recompose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 0, 1, 2, 200]);
+
+        // the memoized scope is skipped, and keeps the nodes of the recomposed scope
+        composer.compose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 0, 1, 2, 200]);
+        let stats = composer.stats();
+        assert_eq!((stats.memo_hits, stats.skipped_scopes), (1, 2));
+
+        count.borrow().as_ref().unwrap().set(1);
+        composer.recompose(&mut root, compose);
+        composer.compose(&mut root, compose);
+        assert_eq!(tags(&root), vec![100, 0, 200]);
+        assert_eq!(composer.stats().memo_hits, 1);
+    }
+
+    /// Emits a `Tag` node for each item, in a memoized scope.
+    #[track_caller]
+    fn memo_list(cx: &mut CompositionCtx, runs: &Cell<u32>, items: &[u32]) -> Vec<State<u32>> {
+        let mut states = vec![];
+        cx.memo(items.to_vec(), |cx| {
+            runs.set(runs.get() + 1);
+            for &item in items {
+                cx.with_key(item, |cx| {
+                    states.push(cx.state(|| item));
+                    cx.emit(|| Tag(item));
+                });
+            }
+        });
+        states
+    }
+
+    #[test]
+    fn test_memo() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let runs = Cell::new(0);
+        let mut states = vec![];
+
+        let mut compose = |root: &mut Node, states: &mut Vec<State<u32>>, items: &[u32]| {
+            composer.compose(root, |cx| {
+                cx.emit(|| Tag(0));
+                let new_states = memo_list(cx, &runs, items);
+                if !new_states.is_empty() {
+                    *states = new_states;
+                }
+                cx.emit(|| Tag(100));
+            });
+            composer.stats()
+        };
+
+        let stats = compose(&mut root, &mut states, &[1, 2]);
+        assert_eq!(tags(&root), vec![0, 1, 2, 100]);
+        assert_eq!((stats.memo_misses, stats.memo_hits), (1, 0));
+
+        // same inputs: the nodes are kept, and the content isn't run
+        let stats = compose(&mut root, &mut states, &[1, 2]);
+        assert_eq!(tags(&root), vec![0, 1, 2, 100]);
+        assert_eq!(runs.get(), 1);
+        assert_eq!(stats.memo_hits, 1);
+        assert_eq!(stats.skipped_scopes, 3);
+
+        // modified state in the scope
+        states[1].set(5);
+        let stats = compose(&mut root, &mut states, &[1, 2]);
+        assert_eq!(runs.get(), 2);
+        assert_eq!((stats.memo_misses, stats.memo_hits), (1, 0));
+        assert_eq!(states[1].get(), 5);
+
+        // different inputs
+        compose(&mut root, &mut states, &[2, 3]);
+        assert_eq!(tags(&root), vec![0, 2, 3, 100]);
+        assert_eq!(runs.get(), 3);
+        let stats = compose(&mut root, &mut states, &[2, 3]);
+        assert_eq!(tags(&root), vec![0, 2, 3, 100]);
+        assert_eq!(runs.get(), 3);
+        assert_eq!(stats.memo_hits, 1);
+    }
+
+    #[test]
+    fn test_memo_reordering() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        let runs = Cell::new(0);
+        let mut states = HashMap::new();
+
+        let mut compose = |root: &mut Node, states: &mut HashMap<u32, _>, items: &[u32]| {
+            composer.compose(root, |cx| {
+                for &item in items {
+                    let new_states =
+                        cx.with_key(item, |cx| memo_list(cx, &runs, &[item, item + 10]));
+                    if !new_states.is_empty() {
+                        states.insert(item, new_states);
+                    }
+                }
+            });
+            composer.stats()
+        };
+
+        compose(&mut root, &mut states, &[1, 2, 3]);
+        assert_eq!(tags(&root), vec![1, 11, 2, 12, 3, 13]);
+        assert_eq!(runs.get(), 3);
+
+        // the skipped scopes move their nodes along
+        let stats = compose(&mut root, &mut states, &[3, 1]);
+        assert_eq!(tags(&root), vec![3, 13, 1, 11]);
+        assert_eq!(runs.get(), 3);
+        assert_eq!(stats.memo_hits, 2);
+
+        // a modified state only invalidates the memoized scope that contains it
+        states[&1][1].set(0);
+        let stats = compose(&mut root, &mut states, &[3, 1]);
+        assert_eq!(runs.get(), 4);
+        assert_eq!((stats.memo_misses, stats.memo_hits), (1, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "unbalanced")]
+    fn test_unbalanced_scopes() {
+        let mut composer = Composer::new();
+        let mut root = Node::new(Box::new(Dummy));
+        composer.compose(&mut root, |cx| cx.enter());
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     collections::{Bound, VecDeque},
     marker::PhantomData,
     mem,
-    ops::{Deref, RangeBounds},
+    ops::{Range, RangeBounds},
     ptr,
     ptr::NonNull,
 };
@@ -28,9 +28,10 @@ impl<T> RawVec<T> {
         unsafe {
             let elem_size = mem::size_of::<T>();
 
-            let (new_cap, ptr) = if self.cap == 0 {
-                let ptr = alloc::alloc(Layout::array::<T>(1).unwrap());
-                (1, ptr)
+            let (new_cap, new_layout, ptr) = if self.cap == 0 {
+                let new_layout = Layout::array::<T>(1).unwrap();
+                let ptr = alloc::alloc(new_layout);
+                (1, new_layout, ptr)
             } else {
                 let new_cap = 2 * self.cap;
                 let old_layout = Layout::array::<T>(self.cap).unwrap();
@@ -39,7 +40,7 @@ impl<T> RawVec<T> {
 
                 assert!(new_byte_size < isize::MAX as usize);
                 let ptr = alloc::realloc(self.ptr.as_ptr().cast(), old_layout, new_byte_size);
-                (new_cap, ptr)
+                (new_cap, new_layout, ptr)
             };
 
             // If allocate or reallocate fail, oom
@@ -66,7 +67,8 @@ impl<T> Drop for RawVec<T> {
     }
 }
 
-struct GapBuffer<T> {
+/// A vector with a movable gap, for efficient insertions and removals around a single location.
+pub struct GapBuffer<T> {
     buf: RawVec<T>,
     gap_pos: usize,
     gap_size: usize,
@@ -87,6 +89,11 @@ impl<T> GapBuffer<T> {
         self.buf.cap - self.gap_size
     }
 
+    /// Returns whether the buffer contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     fn base_ptr(&self) -> *mut T {
         self.buf.ptr.as_ptr()
     }
@@ -109,12 +116,13 @@ impl<T> GapBuffer<T> {
 
         unsafe {
             match pos.cmp(&self.gap_pos) {
-                Ordering::Greater => ptr::copy_nonoverlapping(
+                // source and destination may overlap if the gap is smaller than the distance
+                Ordering::Greater => ptr::copy(
                     base_ptr.add(self.gap_pos + self.gap_size),
                     base_ptr.add(self.gap_pos),
                     pos - self.gap_pos,
                 ),
-                Ordering::Less => ptr::copy_nonoverlapping(
+                Ordering::Less => ptr::copy(
                     base_ptr.add(pos),
                     base_ptr.add(pos + self.gap_size),
                     self.gap_pos - pos,
@@ -131,7 +139,6 @@ impl<T> GapBuffer<T> {
 
         unsafe {
             ptr::write(self.base_ptr().add(pos), elem);
-            self.len += 1;
         }
 
         self.gap_pos += 1;
@@ -141,13 +148,56 @@ impl<T> GapBuffer<T> {
     /// Moves the gap to the given position and removes the element
     pub fn remove(&mut self, pos: usize) -> T {
         assert!(pos < self.len());
-        let ptr = self.base_ptr();
         self.move_gap(pos, false);
         let val = unsafe { ptr::read(self.base_ptr().add(self.gap_pos + self.gap_size)) };
         self.gap_size += 1;
         val
     }
 
+    /// Inserts all elements of an iterator at the given position.
+    pub fn insert_iter(&mut self, pos: usize, elems: impl IntoIterator<Item = T>) {
+        for (pos, elem) in (pos..).zip(elems) {
+            self.insert(pos, elem);
+        }
+    }
+
+    /// Removes a range of elements. Dropped elements are not returned.
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        assert!(range.start <= range.end && range.end <= self.len());
+        self.move_gap(range.start, false);
+        for _ in range {
+            // grow the gap over the removed element
+            unsafe {
+                ptr::drop_in_place(self.base_ptr().add(self.gap_pos + self.gap_size));
+            }
+            self.gap_size += 1;
+        }
+    }
+
+    /// Removes all elements.
+    pub fn clear(&mut self) {
+        let len = self.len();
+        self.remove_range(0..len);
+    }
+
+    /// Returns a reference to the element at the given position.
+    pub fn get(&self, pos: usize) -> Option<&T> {
+        if pos < self.len() {
+            unsafe { Some(&*self.get_elem_ptr(pos)) }
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the element at the given position.
+    pub fn get_mut(&mut self, pos: usize) -> Option<&mut T> {
+        if pos < self.len() {
+            unsafe { Some(&mut *self.get_elem_ptr(pos)) }
+        } else {
+            None
+        }
+    }
+
     fn get_elem_ptr(&self, pos: usize) -> *mut T {
         assert!(pos <= self.len());
         unsafe {
@@ -201,6 +251,21 @@ impl<T> GapBuffer<T> {
     }
 }
 
+impl GapBuffer<u8> {
+    /// Returns the contents of the buffer as an UTF-8 string.
+    ///
+    /// Panics if the buffer doesn't contain valid UTF-8.
+    pub fn to_utf8_string(&self) -> String {
+        String::from_utf8(self.iter(..).copied().collect()).expect("invalid UTF-8")
+    }
+}
+
+impl<T> Default for GapBuffer<T> {
+    fn default() -> Self {
+        GapBuffer::new()
+    }
+}
+
 impl<T> Drop for GapBuffer<T> {
     fn drop(&mut self) {
         unsafe {
@@ -214,7 +279,7 @@ impl<T> Drop for GapBuffer<T> {
     }
 }
 
-struct Iter<'a, T> {
+pub struct Iter<'a, T> {
     start: *const T,
     end: *const T,
     gap_start: *const T,
@@ -238,9 +303,32 @@ impl<'a, T> Iterator for Iter<'a, T> {
 
         Some(p)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = remaining(self.start, self.end, self.gap_start, self.gap_end);
+        (len, Some(len))
+    }
 }
 
-struct IterMut<'a, T> {
+impl<'a, T> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.start == self.end {
+            return None;
+        }
+
+        // `end` is never at the start of the gap, see `get_elem_ptr`
+        if self.end == self.gap_end {
+            self.end = self.gap_start;
+        }
+        self.end = unsafe { self.end.offset(-1) };
+
+        Some(unsafe { &*self.end })
+    }
+}
+
+impl<'a, T> ExactSizeIterator for Iter<'a, T> {}
+
+pub struct IterMut<'a, T> {
     start: *mut T,
     end: *mut T,
     gap_start: *mut T,
@@ -264,4 +352,77 @@ impl<'a, T> Iterator for IterMut<'a, T> {
 
         Some(p)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = remaining(self.start, self.end, self.gap_start, self.gap_end);
+        (len, Some(len))
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for IterMut<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.start == self.end {
+            return None;
+        }
+
+        if self.end == self.gap_end {
+            self.end = self.gap_start;
+        }
+        self.end = unsafe { self.end.offset(-1) };
+
+        Some(unsafe { &mut *self.end })
+    }
+}
+
+impl<'a, T> ExactSizeIterator for IterMut<'a, T> {}
+
+/// Returns the number of elements between `start` and `end`, skipping the gap if it's in between.
+fn remaining<T>(start: *const T, end: *const T, gap_start: *const T, gap_end: *const T) -> usize {
+    let distance = |from: *const T, to: *const T| (to as usize - from as usize) / mem::size_of::<T>();
+    if start < gap_start && end >= gap_end {
+        distance(start, end) - distance(gap_start, gap_end)
+    } else {
+        distance(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GapBuffer;
+
+    #[test]
+    fn test_iter_around_gap() {
+        let mut buffer = GapBuffer::new();
+        buffer.insert_iter(0, 0..6);
+        // move the gap in the middle
+        buffer.remove(3);
+        buffer.insert(3, 3);
+
+        assert_eq!(buffer.iter(..).len(), 6);
+        assert_eq!(buffer.iter(1..5).len(), 4);
+        assert_eq!(buffer.iter(4..).len(), 2);
+        assert_eq!(
+            buffer.iter(..).rev().copied().collect::<Vec<_>>(),
+            vec![5, 4, 3, 2, 1, 0]
+        );
+        assert_eq!(buffer.iter(..5).rposition(|&x| x == 2), Some(2));
+
+        // both ends meet in the middle
+        let mut iter = buffer.iter(1..5);
+        assert_eq!(iter.next(), Some(&1));
+        assert_eq!(iter.next_back(), Some(&4));
+        assert_eq!(iter.next_back(), Some(&3));
+        assert_eq!(iter.len(), 1);
+        assert_eq!(iter.next(), Some(&2));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+
+        for x in buffer.iter_mut(2..).rev() {
+            *x *= 10;
+        }
+        assert_eq!(
+            buffer.iter(..).copied().collect::<Vec<_>>(),
+            vec![0, 1, 20, 30, 40, 50]
+        );
+    }
 }
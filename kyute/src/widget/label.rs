@@ -0,0 +1,482 @@
+//! Read-only text, optionally selectable, with hyperlinks.
+use crate::{
+    event::{Event, KeyboardEvent, PointerButtonEvent, PointerEvent},
+    layout::{BoxConstraints, Measurements},
+    node::{EventCtx, PaintCtx},
+    widget::{LayoutCtx, Node, Selection, Widget},
+    Point, Rect, Size,
+};
+use kyute_shell::{
+    drawing::{Brush, Color, DrawTextOptions},
+    text::{TextFormat, TextLayout},
+    winit::{event::VirtualKeyCode, window::CursorIcon},
+};
+use std::{ops::Range, rc::Rc};
+
+const DEFAULT_FONT_SIZE: f32 = 12.0;
+
+/// A hyperlink on a range of text of a [`Label`].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Link {
+    /// Range of the link text, in UTF-8 code units.
+    pub range: Range<usize>,
+    /// Target of the link (e.g. an URL), passed to [`LabelAction::LinkClicked`].
+    pub target: String,
+}
+
+/// Actions emitted by a [`Label`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LabelAction {
+    /// A link was clicked. Contains the link target.
+    LinkClicked(String),
+}
+
+/// Read-only text.
+///
+/// If the label is selectable, the text can be selected with the mouse or the keyboard, and
+/// copied to the clipboard with `Ctrl+C`.
+pub struct Label {
+    text: String,
+    links: Vec<Link>,
+    selectable: bool,
+    selection: Selection,
+    text_format: Option<TextFormat>,
+    /// Text layout, shared with the text layout cache unless the label has links (which modify the
+    /// layout).
+    text_layout: Option<Rc<TextLayout>>,
+    /// Brushes of the links and of the hovered link, created on the first paint.
+    link_brushes: Option<(Brush, Brush)>,
+    /// Hovered link when the drawing effects of the links were set, `None` if they need to be set.
+    link_effects: Option<Option<usize>>,
+    /// Index of the link under the pointer.
+    hovered_link: Option<usize>,
+    /// Index of the link that was under the pointer when the button was pressed.
+    pressed_link: Option<usize>,
+    on_action: Option<Box<dyn FnMut(&LabelAction)>>,
+}
+
+impl Label {
+    /// Creates a new label with the specified text.
+    pub fn new(text: impl Into<String>) -> Label {
+        Label {
+            text: text.into(),
+            links: Vec::new(),
+            selectable: false,
+            selection: Selection::default(),
+            text_format: None,
+            text_layout: None,
+            link_brushes: None,
+            link_effects: None,
+            hovered_link: None,
+            pressed_link: None,
+            on_action: None,
+        }
+    }
+
+    /// Adds a link on a range of the text.
+    ///
+    /// Panics if the range is out of bounds or not on character boundaries.
+    pub fn link(mut self, range: Range<usize>, target: impl Into<String>) -> Label {
+        assert!(self.text.get(range.clone()).is_some(), "invalid link range");
+        self.links.push(Link {
+            range,
+            target: target.into(),
+        });
+        self
+    }
+
+    /// Sets whether the text can be selected.
+    pub fn selectable(mut self, selectable: bool) -> Label {
+        self.selectable = selectable;
+        self
+    }
+
+    /// Sets the text format used to display the text.
+    pub fn text_format(mut self, text_format: TextFormat) -> Label {
+        self.text_format = Some(text_format);
+        self
+    }
+
+    /// Sets the function called when the label emits a [`LabelAction`].
+    pub fn on_action(mut self, f: impl FnMut(&LabelAction) + 'static) -> Label {
+        self.on_action = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the text of the label.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the current selection.
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    /// Returns the selected text.
+    pub fn selected_text(&self) -> &str {
+        &self.text[self.selection.range()]
+    }
+
+    /// Selects the whole text.
+    fn select_all(&mut self) {
+        self.selection = Selection {
+            anchor: 0,
+            active: self.text.len(),
+        };
+    }
+
+    /// Returns the text that `Ctrl+C` copies to the clipboard, if any.
+    fn copied_text(&self) -> Option<&str> {
+        if self.selection.is_empty() {
+            None
+        } else {
+            Some(self.selected_text())
+        }
+    }
+
+    fn emit_action(&mut self, action: LabelAction) {
+        if let Some(ref mut on_action) = self.on_action {
+            on_action(&action);
+        }
+    }
+
+    /// Returns the text position closest to the point, and whether the point is over the text.
+    fn hit_test(&self, point: Point) -> Option<(usize, bool)> {
+        let text_layout = self.text_layout.as_ref()?;
+        let hit = text_layout.hit_test_point(point).ok()?;
+        let pos = if hit.is_trailing_hit {
+            hit.metrics.text_position + hit.metrics.length
+        } else {
+            hit.metrics.text_position
+        };
+        Some((pos, hit.metrics.bounds.contains(point)))
+    }
+
+    /// Returns the index of the link under the specified point.
+    fn link_at_point(&self, point: Point) -> Option<usize> {
+        let text_layout = self.text_layout.as_ref()?;
+        let hit = text_layout.hit_test_point(point).ok()?;
+        if !hit.metrics.bounds.contains(point) {
+            return None;
+        }
+        let pos = hit.metrics.text_position;
+        self.links
+            .iter()
+            .position(|link| link.range.start <= pos && pos < link.range.end)
+    }
+
+    fn prev_char_boundary(&self, pos: usize) -> usize {
+        self.text[..pos]
+            .chars()
+            .next_back()
+            .map(|c| pos - c.len_utf8())
+            .unwrap_or(0)
+    }
+
+    fn next_char_boundary(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .chars()
+            .next()
+            .map(|c| pos + c.len_utf8())
+            .unwrap_or(pos)
+    }
+
+    fn pointer_move(&mut self, ctx: &mut EventCtx, event: &PointerEvent) {
+        if ctx.is_capturing_pointer() && self.pressed_link.is_none() {
+            // extend the selection while dragging
+            if let Some((pos, _)) = self.hit_test(event.position) {
+                self.selection.active = pos;
+                ctx.request_redraw();
+            }
+            ctx.set_handled();
+            return;
+        }
+
+        let hovered_link = self.link_at_point(event.position);
+        if hovered_link != self.hovered_link {
+            self.hovered_link = hovered_link;
+            ctx.request_redraw();
+        }
+        let cursor = if hovered_link.is_some() {
+            CursorIcon::Hand
+        } else if self.selectable
+            && self
+                .hit_test(event.position)
+                .map_or(false, |(_, inside)| inside)
+        {
+            CursorIcon::Text
+        } else {
+            CursorIcon::Default
+        };
+        ctx.set_cursor_icon(cursor);
+    }
+
+    fn pointer_down(&mut self, ctx: &mut EventCtx, event: &PointerButtonEvent) {
+        let position = event.pointer.position;
+        if let Some(link) = self.link_at_point(position) {
+            // the link is activated when the button is released over it
+            self.pressed_link = Some(link);
+            ctx.capture_pointer();
+            return;
+        }
+        if !self.selectable {
+            return;
+        }
+        if let Some((pos, _)) = self.hit_test(position) {
+            if event.pointer.modifiers.shift() {
+                self.selection.active = pos;
+            } else {
+                self.selection = Selection::empty(pos);
+            }
+            ctx.request_focus();
+            ctx.capture_pointer();
+            ctx.request_redraw();
+        }
+    }
+
+    fn pointer_up(&mut self, ctx: &mut EventCtx, event: &PointerButtonEvent) {
+        if !ctx.is_capturing_pointer() {
+            return;
+        }
+        ctx.release_pointer();
+        ctx.set_handled();
+        if let Some(link) = self.pressed_link.take() {
+            if self.link_at_point(event.pointer.position) == Some(link) {
+                let target = self.links[link].target.clone();
+                self.emit_action(LabelAction::LinkClicked(target));
+            }
+        }
+    }
+
+    fn key_down(&mut self, ctx: &mut EventCtx, event: &KeyboardEvent) {
+        let shift = event.modifiers.shift();
+        let ctrl = event.modifiers.ctrl();
+        let active = self.selection.active;
+
+        let pos = match event.key {
+            Some(VirtualKeyCode::C) if ctrl => {
+                if let Some(text) = self.copied_text() {
+                    if let Err(err) = ctx.platform().set_clipboard_text(text) {
+                        tracing::error!("failed to copy text to the clipboard: {}", err);
+                    }
+                }
+                ctx.set_handled();
+                return;
+            }
+            Some(VirtualKeyCode::A) if ctrl => {
+                self.select_all();
+                ctx.set_handled();
+                ctx.request_redraw();
+                return;
+            }
+            Some(VirtualKeyCode::Left) => self.prev_char_boundary(active),
+            Some(VirtualKeyCode::Right) => self.next_char_boundary(active),
+            Some(VirtualKeyCode::Home) => 0,
+            Some(VirtualKeyCode::End) => self.text.len(),
+            _ => return,
+        };
+
+        if shift {
+            self.selection.active = pos;
+        } else {
+            self.selection = Selection::empty(pos);
+        }
+        ctx.set_handled();
+        ctx.request_redraw();
+    }
+
+    /// Sets the drawing effects of the links, after the layout was re-created or the hovered link
+    /// changed.
+    fn update_link_effects(&mut self, ctx: &mut PaintCtx) {
+        let text_layout = match self.text_layout.as_mut().and_then(Rc::get_mut) {
+            Some(text_layout) => text_layout,
+            None => return,
+        };
+        // brushes are only available when painting
+        let (link_brush, hovered_link_brush) = self.link_brushes.get_or_insert_with(|| {
+            (
+                Brush::new_solid_color(ctx, Color::new(0.4, 0.6, 1.0, 1.0)),
+                Brush::new_solid_color(ctx, Color::new(0.6, 0.8, 1.0, 1.0)),
+            )
+        });
+        for (i, link) in self.links.iter().enumerate() {
+            let brush = if self.hovered_link == Some(i) {
+                &*hovered_link_brush
+            } else {
+                &*link_brush
+            };
+            text_layout.set_drawing_effect(brush, link.range.clone());
+        }
+        self.link_effects = Some(self.hovered_link);
+    }
+}
+
+impl Widget for Label {
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        _children: &mut [Node],
+        constraints: &BoxConstraints,
+    ) -> Measurements {
+        let text_format = self.text_format.get_or_insert_with(|| {
+            TextFormat::builder()
+                .size(DEFAULT_FONT_SIZE)
+                .build()
+                .unwrap()
+        });
+
+        // the constraints may be unbounded, but DirectWrite needs a finite layout box
+        let max_size = Size::new(
+            constraints.max_width().min(f32::MAX as f64),
+            f32::MAX as f64,
+        );
+        let scale_factor = ctx.scale_factor();
+        let text_layout = if self.links.is_empty() {
+            ctx.text_layout_cache()
+                .get_or_create(&self.text, text_format, max_size, scale_factor)
+        } else {
+            // links are styled by modifying the layout, so it can't come from the cache: reuse the
+            // previous one, since the text never changes
+            match self.text_layout.take().map(Rc::try_unwrap) {
+                Some(Ok(mut text_layout)) => {
+                    text_layout.set_max_width(max_size.width);
+                    Ok(Rc::new(text_layout))
+                }
+                _ => match TextLayout::new(&self.text, text_format, max_size) {
+                    Ok(mut text_layout) => {
+                        for link in self.links.iter() {
+                            text_layout.set_underline(true, link.range.clone());
+                        }
+                        self.link_effects = None;
+                        Ok(Rc::new(text_layout))
+                    }
+                    Err(err) => Err(err),
+                },
+            }
+        };
+        let text_layout = match text_layout {
+            Ok(text_layout) => text_layout,
+            Err(err) => {
+                tracing::error!("failed to create text layout: {}", err);
+                self.text_layout = None;
+                return Measurements::new(constraints.constrain(Size::zero()));
+            }
+        };
+
+        let metrics = text_layout.metrics();
+        let baseline = text_layout
+            .line_metrics()
+            .first()
+            .map(|line| line.baseline)
+            .unwrap_or(0.0);
+        let size = constraints.constrain(Size::new(
+            metrics.width_including_trailing_whitespace as f64,
+            metrics.bounds.size.height,
+        ));
+        self.text_layout = Some(text_layout);
+
+        Measurements {
+            size,
+            baseline: Some(baseline),
+        }
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _children: &mut [Node], bounds: Rect) {
+        if !self.links.is_empty() && self.link_effects != Some(self.hovered_link) {
+            self.update_link_effects(ctx);
+        }
+        let text_layout = match self.text_layout {
+            Some(ref text_layout) => text_layout,
+            None => return,
+        };
+
+        if !self.selection.is_empty() {
+            let selection_brush = Brush::new_solid_color(ctx, Color::new(0.2, 0.35, 0.6, 1.0));
+            if let Ok(hits) =
+                text_layout.hit_test_text_range(self.selection.range(), &bounds.origin)
+            {
+                for hit in hits.iter() {
+                    ctx.fill_rectangle(hit.bounds, &selection_brush);
+                }
+            }
+        }
+
+        let text_brush = Brush::new_solid_color(ctx, Color::new(0.9, 0.9, 0.9, 1.0));
+        ctx.draw_text_layout(
+            bounds.origin,
+            text_layout,
+            &text_brush,
+            DrawTextOptions::default(),
+        );
+    }
+
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event) {
+        match event {
+            Event::PointerMove(p) => self.pointer_move(ctx, p),
+            Event::PointerDown(p) => self.pointer_down(ctx, p),
+            Event::PointerUp(p) => self.pointer_up(ctx, p),
+            Event::PointerOut(_) => {
+                if self.hovered_link.take().is_some() {
+                    ctx.request_redraw();
+                }
+                ctx.set_cursor_icon(CursorIcon::Default);
+            }
+            Event::KeyDown(k) => {
+                if ctx.has_focus() && self.selectable {
+                    self.key_down(ctx, k);
+                }
+            }
+            Event::FocusOut => {
+                // only the focused label shows a selection
+                self.selection = Selection::empty(self.selection.active);
+                ctx.request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_boundaries() {
+        // 1, 2 and 4-byte characters
+        let label = Label::new("aé😀b");
+        assert_eq!(label.next_char_boundary(0), 1);
+        assert_eq!(label.next_char_boundary(1), 3);
+        assert_eq!(label.next_char_boundary(3), 7);
+        assert_eq!(label.next_char_boundary(7), 8);
+        assert_eq!(label.next_char_boundary(8), 8);
+        assert_eq!(label.prev_char_boundary(8), 7);
+        assert_eq!(label.prev_char_boundary(7), 3);
+        assert_eq!(label.prev_char_boundary(3), 1);
+        assert_eq!(label.prev_char_boundary(1), 0);
+        assert_eq!(label.prev_char_boundary(0), 0);
+    }
+
+    #[test]
+    fn test_select_all_and_copy() {
+        let mut label = Label::new("héllo").selectable(true);
+        // nothing to copy without a selection
+        assert_eq!(label.copied_text(), None);
+
+        label.select_all();
+        assert_eq!(
+            label.selection(),
+            Selection {
+                anchor: 0,
+                active: 6
+            }
+        );
+        assert_eq!(label.copied_text(), Some("héllo"));
+
+        label.selection = Selection {
+            anchor: 6,
+            active: 1,
+        };
+        assert_eq!(label.copied_text(), Some("éllo"));
+    }
+}
@@ -2,19 +2,66 @@
 mod flex;
 mod grid;
 mod button;
+mod code_editor;
+mod composition;
 mod scope_table;
+mod state;
 mod gap_buffer;
+mod label;
+mod paragraph;
+mod text_edit;
+#[cfg(feature = "bench-scope-tree")]
+#[doc(hidden)]
+pub mod scope_tree;
 
 use crate::{
+    application::AppCtx,
+    event::Event,
     layout::{BoxConstraints, Measurements},
-    node::{NodeCursor, NodeId, NodeRef, NodeTree, PaintCtx},
+    node::{EventCtx, NodeCursor, NodeId, NodeRef, NodeTree, PaintCtx},
     Offset, Point, Rect, Size,
 };
 
 use crate::key::Key;
-use std::{any::Any, cell::Cell, marker::PhantomData, panic::Location};
+use kyute_shell::text::TextLayoutCache;
+use std::{
+    any::{Any, TypeId},
+    cell::Cell,
+    marker::PhantomData,
+    panic::Location,
+};
+
+pub use code_editor::{
+    CodeEdit, CodeEditor, CodeEditorAction, CodePosition, FoldRegion, PlainText, Token,
+    TokenStyle, Tokenizer, TokenizerState,
+};
+pub use composition::{Composer, CompositionCtx, CompositionStats};
+pub use state::State;
+pub use label::{Label, LabelAction, Link};
+pub use paragraph::Paragraph;
+pub use text_edit::{Selection, TextEdit, TextEditAction};
+
+/// Context passed to [`Widget::layout`].
+pub struct LayoutCtx<'a> {
+    pub(crate) app_ctx: &'a mut AppCtx,
+    /// Scale factor of the window being laid out.
+    pub(crate) scale_factor: f64,
+}
+
+impl<'a> LayoutCtx<'a> {
+    /// Returns the scale factor of the window (physical pixels per DIP).
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
 
-struct LayoutCtx {}
+    /// Returns the application-wide text layout cache.
+    ///
+    /// Widgets that display static text should get their layouts from this cache instead of
+    /// creating them on every layout pass.
+    pub fn text_layout_cache(&mut self) -> &mut TextLayoutCache {
+        self.app_ctx.text_layout_cache()
+    }
+}
 
 pub trait Widget: Any {
     /// Called to measure this widget and layout the children of this widget (`ctx.children_mut()`).
@@ -26,15 +73,56 @@ pub trait Widget: Any {
     ) -> Measurements;
 
     /// Called to paint the widget
-    fn paint(&mut self, ctx: &mut PaintCtx, children: &[Node], bounds: Rect);
+    fn paint(&mut self, ctx: &mut PaintCtx, children: &mut [Node], bounds: Rect);
+
+    /// Called when an event is delivered to the widget.
+    ///
+    /// The default implementation ignores the event.
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event) {}
+
+    /// Called when the node of the widget is removed from the node tree.
+    ///
+    /// Children are unmounted before their parent. The default implementation does nothing.
+    fn unmount(&mut self) {}
+
+    /// Returns the name of the widget type, for debugging purposes.
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
 
-pub struct State {
-    key: Key,
-    data: Box<dyn Any>,
+impl dyn Widget {
+    /// Returns whether the widget is of type `T`.
+    pub fn is<T: Widget>(&self) -> bool {
+        // calls `type_id` on the concrete type, through the vtable
+        Any::type_id(self) == TypeId::of::<T>()
+    }
+
+    /// Returns a reference to the widget as a `T`, or `None` if it isn't of this type.
+    pub fn downcast_ref<T: Widget>(&self) -> Option<&T> {
+        if self.is::<T>() {
+            // safety: the type was checked above
+            unsafe { Some(&*(self as *const dyn Widget as *const T)) }
+        } else {
+            None
+        }
+    }
+
+    /// Returns a mutable reference to the widget as a `T`, or `None` if it isn't of this type.
+    pub fn downcast_mut<T: Widget>(&mut self) -> Option<&mut T> {
+        if self.is::<T>() {
+            // safety: the type was checked above
+            unsafe { Some(&mut *(self as *mut dyn Widget as *mut T)) }
+        } else {
+            None
+        }
+    }
 }
 
 pub struct Node<W = Box<dyn Widget>> {
+    /// Identifies the node among its siblings during composition.
+    pub(crate) key: Option<Key>,
+
     /// Offset of the node relative to the parent
     pub(crate) offset: Offset,
 
@@ -46,25 +134,30 @@ pub struct Node<W = Box<dyn Widget>> {
 
     /// Widget
     pub(crate) widget: W,
+
+    /// Child nodes.
+    pub(crate) children: Vec<Node>,
 }
 
-impl<W: Widget> Node<W> {
-    pub fn new(widget: W) -> Node<W> {
+impl Node {
+    pub fn new(widget: Box<dyn Widget>) -> Node {
         Node {
+            key: None,
             offset: Default::default(),
             measurements: Default::default(),
             window_pos: Cell::new(Default::default()),
             widget,
             children: vec![],
-            state_entries: vec![],
         }
     }
 
     /// Layouts the node.
     pub fn layout(&mut self, ctx: &mut LayoutCtx, constraints: &BoxConstraints) -> Measurements {
-        let mut ctx = LayoutCtx {};
-        self.widget
-            .layout(&mut ctx, &mut self.children, constraints)
+        let measurements = self
+            .widget
+            .layout(ctx, &mut self.children, constraints);
+        self.measurements = measurements;
+        measurements
     }
 
     /// Sets the offset of this node relative to its parent. Call during layout.
@@ -73,111 +166,15 @@ impl<W: Widget> Node<W> {
     }
 
     fn paint(&mut self, ctx: &mut PaintCtx, bounds: Rect) {
-        let mut ctx = PaintCtx {};
-        self.widget.paint(&mut ctx, &mut self.children, bounds);
+        self.widget.paint(ctx, &mut self.children, bounds);
     }
-}
-
-enum ScopeTreeNode {
-    Scope(ScopeTree),
-    State(State),
-}
-
-struct ScopeTree {
-    key: Key,
-    slots: Vec<ScopeTreeNode>,
-}
-
-/// T: the type of child widgets that are expected. Might be `Box<dyn Widget>`.
-pub struct CompositionCtx<'a> {
-    /// The parent node for which we are recomposing the children of.
-    parent: &'a mut Node,
-    /// Call tree node
-    scope_tree: &'a mut ScopeTree,
-    /// Where we should emit the next node in the list of children.
-    pos: usize,
-    /// Same, but for the call tree
-    call_tree_pos: usize,
-}
-
-impl<'a> CompositionCtx<'a> {
-    pub fn new(node: &'a mut Node, scope_tree: &'a mut CallTree) -> CompositionCtx<'a> {
-        CompositionCtx {
-            parent: node,
-            scope_tree,
-            pos: 0,
-            call_tree_pos: 0
-        }
-    }
-
-    /// Enter a composition scope.
-    pub fn enter(&mut self) {
-        let key = Key::from_caller();
-        let pos = self.scope_tree.slots[self.call_tree_pos..].iter().position(|s| match s {
-           ScopeTreeNode::Scope(ScopeTree {
-                                    key: key, slots
-                                }) => false,
-            _ => false
-        } );
-
-        if let Some(pos) = pos {
 
+    /// Unmounts the widgets of the node and its descendants, children first.
+    pub(crate) fn unmount(&mut self) {
+        for child in self.children.iter_mut() {
+            child.unmount();
         }
-    }
-
-    #[track_caller]
-    pub fn state<T: Any + Clone>(&mut self, init: impl FnOnce() -> T) -> T {
-        let key = Key::from_caller();
-
-        let index = self.parent.slots[self.pos..]
-            .iter()
-            .position(|&w| w.key == key);
-
-        if let Some(index) = index {
-            // found an existing slot, swap it in place
-            self.parent.state_entries.swap(index, self.pos);
-        } else {
-            // create and insert a new node with the provided constructor
-            self.parent
-                .state_entries
-                .insert(self.pos, State { key, data: init() });
-        };
-
-        self.state_pos += 1;
-
-        // safety: if the key matches, then this call originates from the same location that created
-        // the state entry, and thus the type must be the same.
-        unsafe { &*(state as *mut dyn Any as *mut T).clone() }
-    }
-
-    /// Emits (get or create) a child widget with the specified key.
-    #[track_caller]
-    pub fn emit<T>(&mut self, init: impl FnOnce() -> T) -> CompositionCtx<'a>
-    where
-        T: Widget,
-    {
-        let key = Key::from_caller();
-
-        let index = self.parent.children[self.pos..]
-            .iter()
-            .position(|&w| w.key == key);
-
-        if let Some(index) = index {
-            // found an existing node, swap it in place
-            self.parent.children.swap(index, self.pos);
-        } else {
-            // create and insert a new node with the provided constructor
-            self.parent
-                .children
-                .insert(self.pos, Node::new(Box::new(init())));
-        };
-
-        // create child composition context
-        CompositionCtx {
-            parent: node,
-            pos: 0,
-            state_pos: 0,
-        }
+        self.widget.unmount();
     }
 }
 
@@ -196,7 +193,7 @@ impl Widget for Dummy {
         todo!()
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, children: &[Node], bounds: Rect) {
+    fn paint(&mut self, ctx: &mut PaintCtx, children: &mut [Node], bounds: Rect) {
         todo!()
     }
 }
@@ -0,0 +1,142 @@
+//! Paragraph of text with embedded widgets.
+use crate::{
+    layout::{BoxConstraints, Measurements},
+    node::PaintCtx,
+    widget::{LayoutCtx, Node, Widget},
+    Rect, Size,
+};
+use kyute_shell::{
+    drawing::{Brush, Color, DrawTextOptions},
+    text::{InlineObjectMetrics, TextFormat, TextLayout, OBJECT_REPLACEMENT_CHARACTER},
+};
+use tracing::warn;
+
+const DEFAULT_FONT_SIZE: f32 = 12.0;
+
+/// A paragraph of wrapped text, with child widgets embedded in the text.
+///
+/// Each [`OBJECT_REPLACEMENT_CHARACTER`] in the text is replaced by the child node with the same
+/// index (the first replacement character by the first child, and so on). The children wrap with
+/// the text, and are aligned on the baseline of the line they are in.
+pub struct Paragraph {
+    text: String,
+    text_format: Option<TextFormat>,
+    text_layout: Option<TextLayout>,
+}
+
+impl Paragraph {
+    /// Creates a new paragraph with the specified text.
+    pub fn new(text: impl Into<String>) -> Paragraph {
+        Paragraph {
+            text: text.into(),
+            text_format: None,
+            text_layout: None,
+        }
+    }
+
+    /// Sets the text format used to display the text.
+    pub fn text_format(mut self, text_format: TextFormat) -> Paragraph {
+        self.text_format = Some(text_format);
+        self
+    }
+}
+
+impl Widget for Paragraph {
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        children: &mut [Node],
+        constraints: &BoxConstraints,
+    ) -> Measurements {
+        // measure the embedded children first: they determine the space reserved in the text
+        let child_constraints = constraints.loosen();
+        let child_measurements: Vec<Measurements> = children
+            .iter_mut()
+            .map(|child| child.layout(ctx, &child_constraints))
+            .collect();
+
+        let text_format = self.text_format.get_or_insert_with(|| {
+            TextFormat::builder()
+                .size(DEFAULT_FONT_SIZE)
+                .build()
+                .unwrap()
+        });
+        // the constraints may be unbounded, but DirectWrite needs a finite layout box
+        let layout_width = constraints.max_width().min(f32::MAX as f64);
+        let mut text_layout = match TextLayout::new(
+            &self.text,
+            text_format,
+            Size::new(layout_width, f32::MAX as f64),
+        ) {
+            Ok(text_layout) => text_layout,
+            Err(err) => {
+                tracing::error!("failed to create text layout: {}", err);
+                self.text_layout = None;
+                return Measurements::new(constraints.constrain(Size::zero()));
+            }
+        };
+
+        let object_positions: Vec<usize> = self
+            .text
+            .match_indices(OBJECT_REPLACEMENT_CHARACTER)
+            .map(|(pos, _)| pos)
+            .collect();
+        if object_positions.len() != children.len() {
+            warn!(
+                "paragraph has {} replacement characters, but {} children",
+                object_positions.len(),
+                children.len()
+            );
+        }
+
+        for (&text_position, m) in object_positions.iter().zip(child_measurements.iter()) {
+            text_layout.set_inline_object(
+                text_position,
+                InlineObjectMetrics {
+                    size: m.size,
+                    // children without a baseline sit on the baseline
+                    baseline: m.baseline.unwrap_or(m.size.height),
+                },
+            );
+        }
+
+        // place the children where the text layout put the objects
+        for (object, child) in text_layout.inline_objects().iter().zip(children.iter_mut()) {
+            child.set_offset(object.bounds.origin.to_vector());
+        }
+
+        let metrics = text_layout.metrics();
+        let baseline = text_layout
+            .line_metrics()
+            .first()
+            .map(|line| line.baseline)
+            .unwrap_or(0.0);
+        let size = constraints.constrain(Size::new(
+            metrics.width_including_trailing_whitespace as f64,
+            metrics.bounds.size.height,
+        ));
+        self.text_layout = Some(text_layout);
+
+        Measurements {
+            size,
+            baseline: Some(baseline),
+        }
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, children: &mut [Node], bounds: Rect) {
+        if let Some(ref text_layout) = self.text_layout {
+            let text_brush = Brush::new_solid_color(ctx, Color::new(0.9, 0.9, 0.9, 1.0));
+            ctx.draw_text_layout(
+                bounds.origin,
+                text_layout,
+                &text_brush,
+                DrawTextOptions::default(),
+            );
+        }
+
+        for child in children.iter_mut() {
+            let child_bounds = Rect::new(bounds.origin + child.offset, child.measurements.size);
+            child.paint(ctx, child_bounds);
+        }
+    }
+}
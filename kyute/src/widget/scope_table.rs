@@ -1,19 +1,270 @@
-use crate::key::Key;
-use std::any::Any;
-use crate::widget::Node;
+//! Flat storage of composition scopes.
+use crate::{
+    key::Key,
+    widget::{
+        composition::{Memo, Restart},
+        gap_buffer::GapBuffer,
+    },
+};
+use std::{any::Any, collections::HashSet};
 
-enum EntryKind {
-    ScopeStart,
+pub(crate) enum EntryKind {
+    /// Start of a scope. `len` is the number of entries of the scope, including the start and end
+    /// entries.
+    ScopeStart {
+        id: u64,
+        len: usize,
+        restart: Option<Box<Restart>>,
+    },
     ScopeEnd,
     State(Box<dyn Any>),
-    Node(Box<Node>) // Node could be unsized
+    /// Inputs of a memoized scope, first entry of the scope.
+    Memo(Box<Memo>),
+    /// Marks a node emitted in the scope.
+    Node,
 }
 
-struct Entry {
-    key: Key,
-    kind: EntryKind
+pub(crate) struct Entry {
+    pub(crate) key: Key,
+    pub(crate) kind: EntryKind,
 }
 
-struct ScopeTable {
-    entries: Vec
-}
\ No newline at end of file
+impl Entry {
+    /// Returns the type of the entry, or `None` if it can't be looked up.
+    pub(crate) fn ty(&self) -> Option<EntryType> {
+        match self.kind {
+            EntryKind::ScopeStart { .. } => Some(EntryType::Scope),
+            EntryKind::State(_) => Some(EntryType::State),
+            EntryKind::Memo(_) => Some(EntryType::Memo),
+            EntryKind::Node => Some(EntryType::Node),
+            EntryKind::ScopeEnd => None,
+        }
+    }
+
+    /// Returns whether this entry has the specified key and type.
+    pub(crate) fn matches(&self, key: Key, ty: EntryType) -> bool {
+        self.key == key && self.ty() == Some(ty)
+    }
+}
+
+/// Kinds of entries that can be looked up in a scope.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub(crate) enum EntryType {
+    Scope,
+    State,
+    Memo,
+    Node,
+}
+
+/// The scopes, state entries and nodes of a composition, stored in call order in a single gap
+/// buffer.
+///
+/// A scope is a group of entries delimited by `ScopeStart` and `ScopeEnd`. The start entry holds
+/// the length of the group, so that a whole scope can be skipped when looking for an entry.
+/// Recomposition walks the table with a cursor; insertions and removals happen at the cursor,
+/// where the gap of the buffer usually is.
+pub(crate) struct ScopeTable {
+    entries: GapBuffer<Entry>,
+}
+
+impl ScopeTable {
+    pub(crate) fn new() -> ScopeTable {
+        ScopeTable {
+            entries: GapBuffer::new(),
+        }
+    }
+
+    /// Returns the number of entries.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn get(&self, pos: usize) -> &Entry {
+        self.entries.get(pos).expect("invalid position")
+    }
+
+    pub(crate) fn get_mut(&mut self, pos: usize) -> &mut Entry {
+        self.entries.get_mut(pos).expect("invalid position")
+    }
+
+    /// Returns the number of entries of the group starting at `pos`: the length of the scope if
+    /// the entry is a `ScopeStart`, 1 otherwise.
+    pub(crate) fn group_len(&self, pos: usize) -> usize {
+        match self.get(pos).kind {
+            EntryKind::ScopeStart { len, .. } => len,
+            _ => 1,
+        }
+    }
+
+    /// Looks for an entry of the specified type with the specified key in `start..end`, skipping
+    /// over nested scopes.
+    ///
+    /// `start` must be the position of an entry in the scope that contains the range, and `end`
+    /// the position of the `ScopeEnd` of this scope (or the length of the table).
+    pub(crate) fn find(&self, start: usize, end: usize, key: Key, ty: EntryType) -> Option<usize> {
+        let mut pos = start;
+        while pos < end {
+            if self.get(pos).matches(key, ty) {
+                return Some(pos);
+            }
+            pos += self.group_len(pos);
+        }
+        None
+    }
+
+    /// Returns the keys and types of the entries in `start..end`, skipping over nested scopes.
+    ///
+    /// See [`find`](ScopeTable::find) for the requirements on `start` and `end`.
+    pub(crate) fn keys(&self, start: usize, end: usize) -> HashSet<(Key, EntryType)> {
+        let mut keys = HashSet::new();
+        let mut pos = start;
+        while pos < end {
+            let entry = self.get(pos);
+            keys.insert((entry.key, entry.ty().unwrap()));
+            pos += self.group_len(pos);
+        }
+        keys
+    }
+
+    /// Removes the group of entries starting at `pos` and returns it.
+    pub(crate) fn take_group(&mut self, pos: usize) -> Vec<Entry> {
+        let len = self.group_len(pos);
+        (0..len).map(|_| self.entries.remove(pos)).collect()
+    }
+
+    /// Inserts a group of entries at `pos`.
+    pub(crate) fn insert_group(&mut self, pos: usize, group: Vec<Entry>) {
+        self.entries.insert_iter(pos, group);
+    }
+
+    /// Inserts an entry at `pos`.
+    pub(crate) fn insert(&mut self, pos: usize, key: Key, kind: EntryKind) {
+        self.entries.insert(pos, Entry { key, kind });
+    }
+
+    /// Inserts an empty scope at `pos`.
+    pub(crate) fn insert_scope(&mut self, pos: usize, key: Key, id: u64) {
+        self.insert(
+            pos,
+            key,
+            EntryKind::ScopeStart {
+                id,
+                len: 2,
+                restart: None,
+            },
+        );
+        self.insert(pos + 1, key, EntryKind::ScopeEnd);
+    }
+
+    /// Sets the length of the scope starting at `pos`.
+    pub(crate) fn set_scope_len(&mut self, pos: usize, new_len: usize) {
+        match self.get_mut(pos).kind {
+            EntryKind::ScopeStart { ref mut len, .. } => *len = new_len,
+            _ => panic!("not the start of a scope"),
+        }
+    }
+
+    /// Returns the information needed to recompose the scope starting at `pos` on its own, if it
+    /// is restartable.
+    pub(crate) fn restart(&self, pos: usize) -> Option<&Restart> {
+        match self.get(pos).kind {
+            EntryKind::ScopeStart { ref restart, .. } => restart.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn restart_mut(&mut self, pos: usize) -> Option<&mut Restart> {
+        match self.get_mut(pos).kind {
+            EntryKind::ScopeStart {
+                ref mut restart, ..
+            } => restart.as_deref_mut(),
+            _ => None,
+        }
+    }
+
+    /// Makes the scope starting at `pos` restartable.
+    pub(crate) fn set_restart(&mut self, pos: usize, new_restart: Restart) {
+        match self.get_mut(pos).kind {
+            EntryKind::ScopeStart {
+                ref mut restart, ..
+            } => *restart = Some(Box::new(new_restart)),
+            _ => panic!("not the start of a scope"),
+        }
+    }
+
+    /// Returns the ID of the scope starting at `pos`.
+    pub(crate) fn scope_id(&self, pos: usize) -> u64 {
+        match self.get(pos).kind {
+            EntryKind::ScopeStart { id, .. } => id,
+            _ => panic!("not the start of a scope"),
+        }
+    }
+
+    /// Returns the entries in `start..end`.
+    pub(crate) fn iter(&self, start: usize, end: usize) -> impl Iterator<Item = &Entry> {
+        self.entries.iter(start..end)
+    }
+
+    /// Returns the number of scopes in `start..end`.
+    pub(crate) fn scope_count(&self, start: usize, end: usize) -> usize {
+        self.entries
+            .iter(start..end)
+            .filter(|entry| matches!(entry.kind, EntryKind::ScopeStart { .. }))
+            .count()
+    }
+
+    /// Removes the entries in `start..end`.
+    pub(crate) fn remove_range(&mut self, start: usize, end: usize) {
+        // don't move the gap if there's nothing to remove
+        if start < end {
+            self.entries.remove_range(start..end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EntryKind, EntryType, ScopeTable};
+    use crate::key::Key;
+
+    #[test]
+    fn test_group_skipping() {
+        let a = Key::with_id(0);
+        let b = Key::with_id(1);
+        let s = Key::with_id(2);
+
+        // A[S] B[S] S
+        let mut table = ScopeTable::new();
+        table.insert_scope(0, a, 0);
+        table.insert(1, s, EntryKind::State(Box::new(1)));
+        table.set_scope_len(0, 3);
+        table.insert_scope(3, b, 1);
+        table.insert(4, s, EntryKind::State(Box::new(2)));
+        table.set_scope_len(3, 3);
+        table.insert(6, s, EntryKind::State(Box::new(3)));
+        assert_eq!(table.len(), 7);
+
+        // the state entries of the nested scopes are skipped
+        assert_eq!(table.find(0, 7, s, EntryType::State), Some(6));
+        assert_eq!(table.find(0, 7, b, EntryType::Scope), Some(3));
+        assert_eq!(table.find(0, 7, s, EntryType::Scope), None);
+        assert_eq!(table.find(1, 2, s, EntryType::State), Some(1));
+        assert_eq!(table.keys(0, 7).len(), 3);
+
+        // B[S] A[S] S
+        let group = table.take_group(3);
+        assert_eq!(group.len(), 3);
+        table.insert_group(0, group);
+        assert!(table.get(0).matches(b, EntryType::Scope));
+        assert!(table.get(3).matches(a, EntryType::Scope));
+        match table.get(1).kind {
+            EntryKind::State(ref data) => assert_eq!(data.downcast_ref::<i32>(), Some(&2)),
+            _ => panic!(),
+        }
+
+        // B[S] S
+        table.remove_range(3, 6);
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.find(0, 4, s, EntryType::State), Some(3));
+    }
+}
@@ -0,0 +1,316 @@
+//! Previous implementation of composition, which stores scopes in a tree of vectors.
+//!
+//! Only compiled with the `bench-scope-tree` feature, so that the `composition` benchmark can
+//! compare it with the slot table of [`Composer`](super::Composer). It has the same API, minus
+//! memoized and restartable scopes, and [`state`](CompositionCtx::state) returns a copy of the
+//! value instead of a handle.
+use crate::{
+    key::Key,
+    widget::{Node, Widget},
+};
+use std::{
+    any::Any,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+/// A state entry in a scope.
+struct State {
+    key: Key,
+    data: Box<dyn Any>,
+}
+
+enum ScopeTreeNode {
+    Scope(ScopeTree),
+    State(State),
+}
+
+impl ScopeTreeNode {
+    fn matches(&self, key: Key, is_scope: bool) -> bool {
+        match self {
+            ScopeTreeNode::Scope(scope) => is_scope && scope.key == key,
+            ScopeTreeNode::State(state) => !is_scope && state.key == key,
+        }
+    }
+}
+
+/// The slots of a scope (nested scopes and state entries), in call order.
+struct ScopeTree {
+    key: Key,
+    /// Identifies the scope in the composition: hash of the keys of the scope and its ancestors.
+    id: u64,
+    slots: Vec<ScopeTreeNode>,
+}
+
+impl ScopeTree {
+    fn new(key: Key, id: u64) -> ScopeTree {
+        ScopeTree {
+            key,
+            id,
+            slots: vec![],
+        }
+    }
+
+    /// Looks for a slot with the specified key at or after `pos`, and moves it to `pos`.
+    ///
+    /// Returns whether a slot was found.
+    fn find_slot(&mut self, pos: usize, key: Key, is_scope: bool) -> bool {
+        if let Some(index) = self.slots[pos..]
+            .iter()
+            .position(|slot| slot.matches(key, is_scope))
+        {
+            self.slots.swap(pos, pos + index);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Position in a scope being composed.
+#[derive(Clone, Debug)]
+struct Frame {
+    /// Index of the next slot of the scope.
+    pos: usize,
+    /// ID of the scope.
+    id: u64,
+    /// Number of times each key was used in the scope during this pass.
+    occurrences: HashMap<Key, u32>,
+}
+
+impl Frame {
+    fn new(id: u64) -> Frame {
+        Frame {
+            pos: 0,
+            id,
+            occurrences: HashMap::new(),
+        }
+    }
+}
+
+/// Holds the state of a composition between passes.
+pub struct Composer {
+    scope_tree: ScopeTree,
+    /// Scopes entered during the current pass, outermost first.
+    frames: Vec<Frame>,
+}
+
+impl Composer {
+    pub fn new() -> Composer {
+        Composer {
+            scope_tree: ScopeTree::new(Key::from_caller(), 0),
+            frames: vec![],
+        }
+    }
+
+    /// Recomposes the children of `node` with the composable function `f`.
+    ///
+    /// State entries, scopes and child nodes that were created in the previous pass but not
+    /// during this one are removed.
+    pub fn compose(&mut self, node: &mut Node, f: impl FnOnce(&mut CompositionCtx)) {
+        assert!(self.frames.is_empty(), "recursive call to `compose`");
+        self.frames.push(Frame::new(self.scope_tree.id));
+        f(&mut CompositionCtx {
+            composer: self,
+            parent: node,
+            pos: 0,
+        });
+        assert_eq!(
+            self.frames.len(),
+            1,
+            "unbalanced calls to `enter` and `exit`"
+        );
+        let frame = self.frames.pop().unwrap();
+        self.scope_tree.slots.truncate(frame.pos);
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Makes a key unique in the current scope, by combining it with the number of times it was
+    /// already used during this pass.
+    fn disambiguate(&mut self, key: Key) -> Key {
+        let count = self.frame().occurrences.entry(key).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            key
+        } else {
+            key.combine(*count - 1)
+        }
+    }
+
+    /// Returns the innermost scope being composed.
+    fn current_scope(&mut self) -> &mut ScopeTree {
+        let depth = self.frames.len() - 1;
+        let mut scope = &mut self.scope_tree;
+        for frame in &self.frames[..depth] {
+            scope = match scope.slots[frame.pos] {
+                ScopeTreeNode::Scope(ref mut scope) => scope,
+                _ => unreachable!(),
+            };
+        }
+        scope
+    }
+}
+
+impl Default for Composer {
+    fn default() -> Self {
+        Composer::new()
+    }
+}
+
+/// Context passed to composable functions, to emit the children of a node.
+///
+/// When the context is dropped, the children of the node that weren't emitted are removed.
+pub struct CompositionCtx<'a> {
+    composer: &'a mut Composer,
+    /// The parent node for which we are recomposing the children of.
+    parent: &'a mut Node,
+    /// Where we should emit the next node in the list of children.
+    pos: usize,
+}
+
+impl<'a> CompositionCtx<'a> {
+    /// Enters a composition scope, keyed by the location of the caller.
+    ///
+    /// Must be balanced with a call to [`exit`](CompositionCtx::exit). Composable functions
+    /// are usually marked `#[track_caller]` and enter a scope on entry, so that each call site of
+    /// the function gets its own state.
+    #[track_caller]
+    pub fn enter(&mut self) {
+        self.enter_scope(Key::from_caller())
+    }
+
+    fn enter_scope(&mut self, key: Key) {
+        let key = self.composer.disambiguate(key);
+        let Frame {
+            pos, id: parent_id, ..
+        } = *self.composer.frame();
+        let scope = self.composer.current_scope();
+        if !scope.find_slot(pos, key, true) {
+            let mut hasher = DefaultHasher::new();
+            parent_id.hash(&mut hasher);
+            key.hash(&mut hasher);
+            scope.slots.insert(
+                pos,
+                ScopeTreeNode::Scope(ScopeTree::new(key, hasher.finish())),
+            );
+        }
+        let id = match scope.slots[pos] {
+            ScopeTreeNode::Scope(ref scope) => scope.id,
+            _ => unreachable!(),
+        };
+        self.composer.frames.push(Frame::new(id));
+    }
+
+    /// Exits the current composition scope.
+    ///
+    /// The slots of the scope that weren't visited since the matching
+    /// [`enter`](CompositionCtx::enter) are removed.
+    pub fn exit(&mut self) {
+        assert!(
+            self.composer.frames.len() > 1,
+            "`exit` called without a matching `enter`"
+        );
+        let pos = self.composer.frame().pos;
+        self.composer.current_scope().slots.truncate(pos);
+        self.composer.frames.pop();
+        self.composer.frame().pos += 1;
+    }
+
+    /// Runs `f` in a composition scope keyed by the location of the caller.
+    #[track_caller]
+    pub fn scope<R>(&mut self, f: impl FnOnce(&mut CompositionCtx) -> R) -> R {
+        self.enter_scope(Key::from_caller());
+        let result = f(self);
+        self.exit();
+        result
+    }
+
+    /// Runs `f` in a composition scope keyed by the location of the caller and `id`.
+    ///
+    /// Use this for composables called in a loop, with an `id` that identifies the item, so that
+    /// their state and nodes follow the items when they are reordered, inserted or removed.
+    #[track_caller]
+    pub fn with_key<R>(&mut self, id: impl Hash, f: impl FnOnce(&mut CompositionCtx) -> R) -> R {
+        self.enter_scope(Key::with_id(id));
+        let result = f(self);
+        self.exit();
+        result
+    }
+
+    /// Returns the value of a state entry of the current scope, creating it with `init` if it
+    /// doesn't exist.
+    #[track_caller]
+    pub fn state<T: Any + Clone>(&mut self, init: impl FnOnce() -> T) -> T {
+        let key = self.composer.disambiguate(Key::from_caller());
+        let pos = self.composer.frame().pos;
+        let scope = self.composer.current_scope();
+        if !scope.find_slot(pos, key, false) {
+            scope.slots.insert(
+                pos,
+                ScopeTreeNode::State(State {
+                    key,
+                    data: Box::new(init()),
+                }),
+            );
+        }
+        let value = match scope.slots[pos] {
+            // the key identifies the call site, so the type should match
+            ScopeTreeNode::State(ref state) => state
+                .data
+                .downcast_ref::<T>()
+                .expect("unexpected state type")
+                .clone(),
+            _ => unreachable!(),
+        };
+        self.composer.frame().pos += 1;
+        value
+    }
+
+    /// Emits (get or create) a child widget, keyed by the location of the caller in the current
+    /// scope.
+    ///
+    /// Returns the context to compose the children of the emitted node.
+    #[track_caller]
+    pub fn emit<T>(&mut self, init: impl FnOnce() -> T) -> CompositionCtx
+    where
+        T: Widget,
+    {
+        let key = self
+            .composer
+            .disambiguate(Key::from_caller())
+            .combine(self.composer.frame().id);
+        let pos = self.pos;
+        let children = &mut self.parent.children;
+        if let Some(index) = children[pos..]
+            .iter()
+            .position(|node| node.key == Some(key) && node.widget.is::<T>())
+        {
+            // found an existing node, swap it in place
+            children.swap(pos, pos + index);
+        } else {
+            // create and insert a new node with the provided constructor
+            let mut node = Node::new(Box::new(init()));
+            node.key = Some(key);
+            children.insert(pos, node);
+        }
+        self.pos += 1;
+
+        // create child composition context
+        CompositionCtx {
+            composer: &mut *self.composer,
+            parent: &mut self.parent.children[pos],
+            pos: 0,
+        }
+    }
+}
+
+impl<'a> Drop for CompositionCtx<'a> {
+    fn drop(&mut self) {
+        for mut node in self.parent.children.drain(self.pos..) {
+            node.unmount();
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! State handles returned by [`CompositionCtx::state`](crate::widget::CompositionCtx::state).
+use std::{
+    cell::{Ref, RefCell},
+    collections::HashSet,
+    fmt,
+    rc::Rc,
+};
+
+/// Set of IDs of the scopes whose state changed since they were last composed, shared between a
+/// [`Composer`](crate::widget::Composer) and the state handles it created.
+pub(crate) type DirtyScopes = Rc<RefCell<HashSet<u64>>>;
+
+struct StateInner<T> {
+    value: RefCell<T>,
+    /// ID of the scope that owns the state entry.
+    scope: u64,
+    dirty: DirtyScopes,
+}
+
+/// A handle to a state entry of a composition scope.
+///
+/// Handles are cheap to clone, and can be moved into event handlers or widgets to modify the
+/// state outside of composition. Writing to the state marks the scope that owns it as dirty, but
+/// doesn't recompose anything by itself: the owner of the
+/// [`Composer`](crate::widget::Composer) should call
+/// [`Composer::recompose`](crate::widget::Composer::recompose) when
+/// [`Composer::needs_recomposition`](crate::widget::Composer::needs_recomposition) returns `true`.
+/// If the scope is inside a scope entered with
+/// [`CompositionCtx::restartable`](crate::widget::CompositionCtx::restartable), only the closest
+/// one is recomposed. Otherwise, the whole composition is recomposed.
+pub struct State<T> {
+    inner: Rc<StateInner<T>>,
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for State<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("State")
+            .field(&*self.inner.value.borrow())
+            .finish()
+    }
+}
+
+impl<T> State<T> {
+    pub(crate) fn new(value: T, scope: u64, dirty: DirtyScopes) -> State<T> {
+        State {
+            inner: Rc::new(StateInner {
+                value: RefCell::new(value),
+                scope,
+                dirty,
+            }),
+        }
+    }
+
+    /// Returns a copy of the current value.
+    pub fn get(&self) -> T
+    where
+        T: Clone,
+    {
+        self.inner.value.borrow().clone()
+    }
+
+    /// Borrows the current value.
+    pub fn borrow(&self) -> Ref<T> {
+        self.inner.value.borrow()
+    }
+
+    /// Replaces the value, and marks the scope that owns the state as dirty.
+    pub fn set(&self, value: T) {
+        self.update(|v| *v = value)
+    }
+
+    /// Modifies the value in place, and marks the scope that owns the state as dirty.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let result = f(&mut self.inner.value.borrow_mut());
+        self.inner.dirty.borrow_mut().insert(self.inner.scope);
+        result
+    }
+
+    /// Returns whether both handles refer to the same state entry.
+    pub fn ptr_eq(&self, other: &State<T>) -> bool {
+        Rc::ptr_eq(&self.inner, &other.inner)
+    }
+}
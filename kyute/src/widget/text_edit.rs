@@ -0,0 +1,994 @@
+//! Editable text field.
+use crate::{
+    event::{CompositionEvent, Event, KeyboardEvent, PointerButtonEvent},
+    layout::{BoxConstraints, Measurements},
+    node::{EventCtx, PaintCtx},
+    widget::{gap_buffer::GapBuffer, LayoutCtx, Node, Widget},
+    Offset, Point, Rect, Size,
+};
+use kyute_shell::{
+    drawing::{Brush, Color, DrawTextOptions},
+    text::{
+        FontMetrics, ReadingDirection, TextDecoration, TextFormat, TextLayout, VisualDirection,
+        WordWrapping,
+    },
+    winit::event::VirtualKeyCode,
+};
+use std::ops::Range;
+use tracing::trace;
+
+/// Padding between the border of the text field and the text.
+const TEXT_EDIT_PADDING: f64 = 4.0;
+const CARET_WIDTH: f64 = 1.0;
+const PREEDIT_UNDERLINE_WIDTH: f64 = 1.0;
+const PREEDIT_CLAUSE_UNDERLINE_WIDTH: f64 = 2.0;
+const DEFAULT_FONT_SIZE: f32 = 12.0;
+
+/// A text selection.
+///
+/// Positions are byte offsets in the UTF-8 text.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Selection {
+    /// Position where the selection started.
+    pub anchor: usize,
+    /// Position of the caret: where the selection ends.
+    pub active: usize,
+}
+
+impl Selection {
+    /// Creates an empty selection (a caret) at the specified position.
+    pub fn empty(at: usize) -> Selection {
+        Selection {
+            anchor: at,
+            active: at,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.active
+    }
+
+    pub fn min(&self) -> usize {
+        self.anchor.min(self.active)
+    }
+
+    pub fn max(&self) -> usize {
+        self.anchor.max(self.active)
+    }
+
+    /// Returns the selected range of text.
+    pub fn range(&self) -> Range<usize> {
+        self.min()..self.max()
+    }
+}
+
+/// Actions emitted by a [`TextEdit`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TextEditAction {
+    /// The text has been modified.
+    Changed(String),
+    /// The text was committed: enter was pressed in a single-line editor, or the editor lost focus.
+    Committed(String),
+}
+
+/// Text being composed by an input method, shown inline in the editor.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Preedit {
+    /// Range of the preedit text in the buffer.
+    range: Range<usize>,
+    /// Cursor or selected clause, relative to the start of the preedit text.
+    cursor: Option<Range<usize>>,
+}
+
+/// Single- or multi-line editable text field.
+///
+/// The contents are stored in a gap buffer, so that edits around the caret are cheap.
+pub struct TextEdit {
+    /// UTF-8 contents, including the preedit text if there's a composition in progress.
+    text: GapBuffer<u8>,
+    selection: Selection,
+    preedit: Option<Preedit>,
+    multiline: bool,
+    reading_direction: ReadingDirection,
+    text_format: Option<TextFormat>,
+    text_layout: Option<TextLayout>,
+    /// Metrics of the font, to place underlines.
+    font_metrics: Option<FontMetrics>,
+    /// Size of the box that the text was laid out in.
+    layout_box_size: Size,
+    /// Decorations on ranges of text.
+    decorations: Vec<(Range<usize>, TextDecoration)>,
+    on_action: Option<Box<dyn FnMut(&TextEditAction)>>,
+}
+
+impl TextEdit {
+    /// Creates a new single-line text editor with the specified initial text.
+    pub fn new(text: &str) -> TextEdit {
+        let mut buffer = GapBuffer::new();
+        buffer.insert_iter(0, text.bytes());
+        TextEdit {
+            text: buffer,
+            selection: Selection::empty(text.len()),
+            preedit: None,
+            multiline: false,
+            reading_direction: ReadingDirection::LeftToRight,
+            text_format: None,
+            text_layout: None,
+            font_metrics: None,
+            layout_box_size: Size::zero(),
+            decorations: Vec::new(),
+            on_action: None,
+        }
+    }
+
+    /// Sets whether the editor accepts multiple lines of text.
+    pub fn multiline(mut self, multiline: bool) -> TextEdit {
+        self.multiline = multiline;
+        self
+    }
+
+    /// Sets the base direction of the text (left-to-right or right-to-left).
+    pub fn reading_direction(mut self, reading_direction: ReadingDirection) -> TextEdit {
+        self.reading_direction = reading_direction;
+        self
+    }
+
+    /// Sets the text format used to display the text.
+    pub fn text_format(mut self, text_format: TextFormat) -> TextEdit {
+        self.text_format = Some(text_format);
+        self
+    }
+
+    /// Sets the function called when the editor emits a [`TextEditAction`].
+    pub fn on_action(mut self, f: impl FnMut(&TextEditAction) + 'static) -> TextEdit {
+        self.on_action = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the current text.
+    ///
+    /// The text being composed by an input method is not included.
+    pub fn text(&self) -> String {
+        match self.preedit {
+            Some(ref preedit) => {
+                let bytes = self
+                    .text
+                    .iter(..preedit.range.start)
+                    .chain(self.text.iter(preedit.range.end..))
+                    .copied()
+                    .collect();
+                String::from_utf8(bytes).expect("invalid UTF-8")
+            }
+            None => self.text.to_utf8_string(),
+        }
+    }
+
+    /// Replaces the text and moves the caret at the end.
+    ///
+    /// This cancels the composition in progress, if any.
+    pub fn set_text(&mut self, text: &str) {
+        self.preedit = None;
+        self.text.clear();
+        self.text.insert_iter(0, text.bytes());
+        self.selection = Selection::empty(text.len());
+        self.relayout_text();
+    }
+
+    /// Returns the current selection.
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    /// Sets the current selection.
+    ///
+    /// Panics if the selection bounds are out of range.
+    pub fn set_selection(&mut self, selection: Selection) {
+        assert!(selection.max() <= self.text.len());
+        self.selection = selection;
+    }
+
+    /// Sets the decorations (underlines, highlights...) drawn on ranges of the text.
+    ///
+    /// The ranges are not updated when the text is edited: they should be set again in response
+    /// to [`TextEditAction::Changed`]. Ranges outside the text are ignored.
+    pub fn set_decorations(
+        &mut self,
+        decorations: impl IntoIterator<Item = (Range<usize>, TextDecoration)>,
+    ) {
+        self.decorations = decorations.into_iter().collect();
+    }
+
+    /// Draws the decorations in front of or behind the text.
+    fn paint_decorations(&self, ctx: &mut PaintCtx, text_origin: Point, background: bool) {
+        let (text_layout, font_metrics) = match (&self.text_layout, &self.font_metrics) {
+            (Some(text_layout), Some(font_metrics)) => (text_layout, font_metrics),
+            _ => return,
+        };
+        let len = self.text.len();
+        for (range, decoration) in self.decorations.iter() {
+            // accept reversed ranges
+            let range = range.start.min(range.end)..range.start.max(range.end);
+            if decoration.is_background() != background
+                || range.end > len
+                || !self.is_char_boundary(range.start)
+                || !self.is_char_boundary(range.end)
+            {
+                continue;
+            }
+            if let Err(err) =
+                ctx.draw_text_decoration(text_origin, text_layout, font_metrics, range, decoration)
+            {
+                tracing::error!("failed to draw text decoration: {}", err);
+            }
+        }
+    }
+
+    /// Returns the range of the text being composed by an input method, if there's a composition
+    /// in progress.
+    pub fn preedit_range(&self) -> Option<Range<usize>> {
+        self.preedit.as_ref().map(|preedit| preedit.range.clone())
+    }
+
+    /// Removes the preedit text, and returns the position where it was.
+    fn remove_preedit(&mut self) -> Option<usize> {
+        let preedit = self.preedit.take()?;
+        self.text.remove_range(preedit.range.clone());
+        Some(preedit.range.start)
+    }
+
+    /// Abandons the composition in progress, and moves the caret where the preedit text was.
+    /// Returns whether there was a composition in progress.
+    fn cancel_preedit(&mut self) -> bool {
+        match self.remove_preedit() {
+            Some(start) => {
+                self.selection = Selection::empty(start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Updates the contents in response to a composition event.
+    fn apply_composition(&mut self, event: &CompositionEvent) {
+        match event {
+            CompositionEvent::Start => {
+                self.remove_preedit();
+                self.delete_selection();
+                let pos = self.selection.active;
+                self.preedit = Some(Preedit {
+                    range: pos..pos,
+                    cursor: None,
+                });
+            }
+            CompositionEvent::Preedit { text, cursor } => {
+                // the preedit text replaces the selection if the composition was not started explicitly
+                let start = match self.remove_preedit() {
+                    Some(start) => start,
+                    None => {
+                        self.delete_selection();
+                        self.selection.active
+                    }
+                };
+                self.text.insert_iter(start, text.bytes());
+                let caret = start + cursor.as_ref().map(|c| c.end).unwrap_or(text.len());
+                self.selection = Selection::empty(caret);
+                self.preedit = Some(Preedit {
+                    range: start..start + text.len(),
+                    cursor: cursor.clone(),
+                });
+            }
+            CompositionEvent::Commit { text } => {
+                self.cancel_preedit();
+                self.insert_str(text);
+            }
+            CompositionEvent::Cancel => {
+                self.cancel_preedit();
+            }
+        }
+    }
+
+    fn is_char_boundary(&self, pos: usize) -> bool {
+        match self.text.get(pos) {
+            // not an UTF-8 continuation byte
+            Some(&b) => (b & 0xC0) != 0x80,
+            None => pos == self.text.len(),
+        }
+    }
+
+    /// Returns the position of the character before `pos`.
+    fn prev_char_boundary(&self, pos: usize) -> usize {
+        let mut pos = pos;
+        while pos > 0 {
+            pos -= 1;
+            if self.is_char_boundary(pos) {
+                break;
+            }
+        }
+        pos
+    }
+
+    /// Returns the position of the character after `pos`.
+    fn next_char_boundary(&self, pos: usize) -> usize {
+        let mut pos = pos;
+        while pos < self.text.len() {
+            pos += 1;
+            if self.is_char_boundary(pos) {
+                break;
+            }
+        }
+        pos
+    }
+
+    /// Returns the position of the caret after moving it one character to the left or to the right
+    /// on screen.
+    ///
+    /// In bidirectional text, this can move the caret backwards in the text. At the visual end of a
+    /// line, the caret moves to the previous or next line.
+    fn visual_caret_move(&self, pos: usize, direction: VisualDirection) -> usize {
+        let visual_pos = self
+            .text_layout
+            .as_ref()
+            .and_then(|text_layout| text_layout.visual_caret_move(pos, direction).ok())
+            .unwrap_or(pos);
+        if visual_pos != pos {
+            return visual_pos;
+        }
+        // end of line, or no layout: move in logical order
+        let forward = match (direction, self.reading_direction) {
+            (VisualDirection::Right, ReadingDirection::LeftToRight)
+            | (VisualDirection::Left, ReadingDirection::RightToLeft) => true,
+            _ => false,
+        };
+        if forward {
+            self.next_char_boundary(pos)
+        } else {
+            self.prev_char_boundary(pos)
+        }
+    }
+
+    /// Returns the position of the start of the line containing `pos`.
+    fn line_start(&self, pos: usize) -> usize {
+        self.text
+            .iter(..pos)
+            .rposition(|&b| b == b'\n')
+            .map(|i| i + 1)
+            .unwrap_or(0)
+    }
+
+    /// Returns the position of the end of the line containing `pos` (before the line terminator).
+    fn line_end(&self, pos: usize) -> usize {
+        self.text
+            .iter(pos..)
+            .position(|&b| b == b'\n')
+            .map(|i| pos + i)
+            .unwrap_or(self.text.len())
+    }
+
+    /// Moves the caret, optionally extending the selection.
+    fn move_caret(&mut self, pos: usize, extend: bool) {
+        if extend {
+            self.selection.active = pos;
+        } else {
+            self.selection = Selection::empty(pos);
+        }
+    }
+
+    /// Removes the selected text. Returns whether the text was modified.
+    fn delete_selection(&mut self) -> bool {
+        if self.selection.is_empty() {
+            return false;
+        }
+        let range = self.selection.range();
+        self.text.remove_range(range.clone());
+        self.selection = Selection::empty(range.start);
+        true
+    }
+
+    /// Removes the selected text, or the character before the caret. Returns whether the text was
+    /// modified.
+    fn delete_backward(&mut self) -> bool {
+        if self.selection.is_empty() {
+            self.selection.anchor = self.prev_char_boundary(self.selection.active);
+        }
+        self.delete_selection()
+    }
+
+    /// Removes the selected text, or the character after the caret. Returns whether the text was
+    /// modified.
+    fn delete_forward(&mut self) -> bool {
+        if self.selection.is_empty() {
+            self.selection.active = self.next_char_boundary(self.selection.active);
+        }
+        self.delete_selection()
+    }
+
+    /// Replaces the selected text with the specified string.
+    fn insert_str(&mut self, s: &str) {
+        self.delete_selection();
+        let pos = self.selection.active;
+        self.text.insert_iter(pos, s.bytes());
+        self.selection = Selection::empty(pos + s.len());
+    }
+
+    /// Inserts a typed character. A composition in progress is abandoned, since the preedit text
+    /// would otherwise end up at the wrong position.
+    fn insert_char(&mut self, c: char) {
+        self.cancel_preedit();
+        let mut buf = [0u8; 4];
+        self.insert_str(c.encode_utf8(&mut buf));
+    }
+
+    fn emit_action(&mut self, action: TextEditAction) {
+        if let Some(ref mut on_action) = self.on_action {
+            on_action(&action);
+        }
+    }
+
+    /// Called after the text was modified.
+    fn text_changed(&mut self, ctx: &mut EventCtx) {
+        self.relayout_text();
+        let text = self.text();
+        self.emit_action(TextEditAction::Changed(text));
+        ctx.request_relayout();
+    }
+
+    /// Re-creates the text layout after a change to the contents or to the layout box.
+    fn relayout_text(&mut self) {
+        let text_format = self.text_format.get_or_insert_with(|| {
+            TextFormat::builder()
+                .size(DEFAULT_FONT_SIZE)
+                .build()
+                .unwrap()
+        });
+        self.text_layout = match TextLayout::new(
+            &self.text.to_utf8_string(),
+            text_format,
+            self.layout_box_size,
+        ) {
+            Ok(mut text_layout) => {
+                text_layout.set_reading_direction(self.reading_direction);
+                if !self.multiline {
+                    // shrink the layout box to the text, otherwise right-to-left text
+                    // would be aligned to the far end of the (unbounded) box. Wrapping is
+                    // disabled so that rounding errors in the width can't break the line.
+                    text_layout.set_word_wrapping(WordWrapping::NoWrap);
+                    let width = text_layout.metrics().width_including_trailing_whitespace;
+                    text_layout.set_max_width(width as f64);
+                }
+                Some(text_layout)
+            }
+            Err(err) => {
+                tracing::error!("failed to create text layout: {}", err);
+                None
+            }
+        };
+    }
+
+    /// Returns the rectangle of the caret, in local coordinates.
+    fn caret_rect(&self) -> Option<Rect> {
+        let text_layout = self.text_layout.as_ref()?;
+        let hit = text_layout
+            .hit_test_text_position(self.selection.active)
+            .ok()?;
+        Some(Rect::new(
+            Point::new(
+                TEXT_EDIT_PADDING + hit.point.x,
+                TEXT_EDIT_PADDING + hit.metrics.bounds.origin.y,
+            ),
+            Size::new(CARET_WIDTH, hit.metrics.bounds.size.height),
+        ))
+    }
+
+    /// Returns the text position closest to the specified point in local coordinates.
+    fn text_position_at_point(&self, point: Point) -> usize {
+        if let Some(ref text_layout) = self.text_layout {
+            let point = point - Offset::new(TEXT_EDIT_PADDING, TEXT_EDIT_PADDING);
+            match text_layout.hit_test_point(point) {
+                Ok(hit) => {
+                    if hit.is_trailing_hit {
+                        hit.metrics.text_position + hit.metrics.length
+                    } else {
+                        hit.metrics.text_position
+                    }
+                }
+                Err(_) => self.selection.active,
+            }
+        } else {
+            self.selection.active
+        }
+    }
+
+    /// Returns the position on the line above (`dir < 0`) or below (`dir > 0`) the caret.
+    fn vertical_caret_position(&self, dir: f64) -> usize {
+        let text_layout = match self.text_layout {
+            Some(ref text_layout) => text_layout,
+            None => return self.selection.active,
+        };
+        match text_layout.hit_test_text_position(self.selection.active) {
+            Ok(hit) => {
+                let line_height = hit.metrics.bounds.size.height;
+                let target = Point::new(
+                    hit.point.x,
+                    hit.metrics.bounds.origin.y + 0.5 * line_height + dir * line_height,
+                );
+                if target.y < 0.0 {
+                    return 0;
+                }
+                if target.y > text_layout.metrics().bounds.max_y() {
+                    return self.text.len();
+                }
+                self.text_position_at_point(
+                    target + Offset::new(TEXT_EDIT_PADDING, TEXT_EDIT_PADDING),
+                )
+            }
+            Err(_) => self.selection.active,
+        }
+    }
+
+    fn pointer_down(&mut self, ctx: &mut EventCtx, event: &PointerButtonEvent) {
+        let pos = self.text_position_at_point(event.pointer.position);
+        if event.repeat_count == 2 {
+            // double-click selects the whole line
+            self.selection = Selection {
+                anchor: self.line_start(pos),
+                active: self.line_end(pos),
+            };
+        } else {
+            self.move_caret(pos, event.pointer.modifiers.shift());
+        }
+        ctx.request_focus();
+        ctx.capture_pointer();
+        ctx.request_redraw();
+    }
+
+    fn key_down(&mut self, ctx: &mut EventCtx, event: &KeyboardEvent) {
+        if self.preedit.is_some() {
+            // keys are handled by the input method during a composition
+            return;
+        }
+
+        let shift = event.modifiers.shift();
+        let ctrl = event.modifiers.ctrl();
+        let selection = self.selection;
+
+        match event.key {
+            Some(VirtualKeyCode::Left) => {
+                let pos = if !shift && !selection.is_empty() {
+                    selection.min()
+                } else {
+                    self.visual_caret_move(selection.active, VisualDirection::Left)
+                };
+                self.move_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Right) => {
+                let pos = if !shift && !selection.is_empty() {
+                    selection.max()
+                } else {
+                    self.visual_caret_move(selection.active, VisualDirection::Right)
+                };
+                self.move_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Up) if self.multiline => {
+                let pos = self.vertical_caret_position(-1.0);
+                self.move_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Down) if self.multiline => {
+                let pos = self.vertical_caret_position(1.0);
+                self.move_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::Home) => {
+                let pos = if ctrl {
+                    0
+                } else {
+                    self.line_start(selection.active)
+                };
+                self.move_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::End) => {
+                let pos = if ctrl {
+                    self.text.len()
+                } else {
+                    self.line_end(selection.active)
+                };
+                self.move_caret(pos, shift);
+            }
+            Some(VirtualKeyCode::A) if ctrl => {
+                self.selection = Selection {
+                    anchor: 0,
+                    active: self.text.len(),
+                };
+            }
+            Some(VirtualKeyCode::Back) => {
+                if self.delete_backward() {
+                    self.text_changed(ctx);
+                }
+            }
+            Some(VirtualKeyCode::Delete) => {
+                if self.delete_forward() {
+                    self.text_changed(ctx);
+                }
+            }
+            Some(VirtualKeyCode::Return) | Some(VirtualKeyCode::NumpadEnter) => {
+                if self.multiline && !ctrl {
+                    self.insert_str("\n");
+                    self.text_changed(ctx);
+                } else {
+                    let text = self.text();
+                    self.emit_action(TextEditAction::Committed(text));
+                }
+            }
+            _ => return,
+        }
+
+        ctx.set_handled();
+        ctx.request_redraw();
+    }
+}
+
+impl Widget for TextEdit {
+    fn layout(
+        &mut self,
+        ctx: &mut LayoutCtx,
+        _children: &mut [Node],
+        constraints: &BoxConstraints,
+    ) -> Measurements {
+        // single-line editors don't wrap the text
+        let layout_width = if self.multiline {
+            (constraints.max_width() - 2.0 * TEXT_EDIT_PADDING).max(0.0)
+        } else {
+            f32::MAX as f64
+        };
+        self.layout_box_size = Size::new(layout_width, f32::MAX as f64);
+        self.relayout_text();
+        if let Some(ref text_format) = self.text_format {
+            self.font_metrics = match text_format.font_metrics(ctx.scale_factor()) {
+                Ok(font_metrics) => Some(font_metrics),
+                Err(err) => {
+                    tracing::error!("failed to get font metrics: {}", err);
+                    None
+                }
+            };
+        }
+
+        let (text_size, baseline) = if let Some(ref text_layout) = self.text_layout {
+            let metrics = text_layout.metrics();
+            let line_metrics = text_layout.line_metrics();
+            let first_line = line_metrics.first();
+            let height = metrics
+                .bounds
+                .size
+                .height
+                .max(first_line.map(|l| l.height).unwrap_or(0.0));
+            (
+                Size::new(metrics.width_including_trailing_whitespace as f64, height),
+                first_line.map(|l| l.baseline).unwrap_or(0.0),
+            )
+        } else {
+            (Size::zero(), 0.0)
+        };
+
+        let size = constraints.constrain(Size::new(
+            text_size.width + 2.0 * TEXT_EDIT_PADDING,
+            text_size.height + 2.0 * TEXT_EDIT_PADDING,
+        ));
+        trace!("text_edit layout size={:?}", size);
+
+        Measurements {
+            size,
+            baseline: Some(baseline + TEXT_EDIT_PADDING),
+        }
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, _children: &mut [Node], bounds: Rect) {
+        let background = Brush::new_solid_color(ctx, Color::new(0.1, 0.1, 0.1, 1.0));
+        let border_color = if ctx.has_focus() {
+            Color::new(0.3, 0.5, 0.9, 1.0)
+        } else {
+            Color::new(0.4, 0.4, 0.4, 1.0)
+        };
+        let border = Brush::new_solid_color(ctx, border_color);
+        ctx.fill_rectangle(bounds, &background);
+        ctx.draw_rectangle(bounds.inflate(-0.5, -0.5), &border, 1.0);
+
+        let text_layout = match self.text_layout {
+            Some(ref text_layout) => text_layout,
+            None => return,
+        };
+        let text_origin = bounds.origin + Offset::new(TEXT_EDIT_PADDING, TEXT_EDIT_PADDING);
+        self.paint_decorations(ctx, text_origin, true);
+
+        // selection highlight, one rectangle per line fragment
+        if !self.selection.is_empty() {
+            let selection_brush = Brush::new_solid_color(ctx, Color::new(0.2, 0.35, 0.6, 1.0));
+            match text_layout.hit_test_text_range(self.selection.range(), &text_origin) {
+                Ok(rects) => {
+                    for hit in rects.iter() {
+                        ctx.fill_rectangle(hit.bounds, &selection_brush);
+                    }
+                }
+                Err(err) => tracing::error!("hit_test_text_range failed: {}", err),
+            }
+        }
+
+        let text_brush = Brush::new_solid_color(ctx, Color::new(0.9, 0.9, 0.9, 1.0));
+        ctx.draw_text_layout(
+            text_origin,
+            text_layout,
+            &text_brush,
+            DrawTextOptions::default(),
+        );
+        self.paint_decorations(ctx, text_origin, false);
+
+        // underline the preedit text, and the selected clause with a thicker line
+        if let Some(ref preedit) = self.preedit {
+            let mut underlines = vec![(preedit.range.clone(), PREEDIT_UNDERLINE_WIDTH)];
+            if let Some(ref cursor) = preedit.cursor {
+                if !cursor.is_empty() {
+                    underlines.push((
+                        preedit.range.start + cursor.start..preedit.range.start + cursor.end,
+                        PREEDIT_CLAUSE_UNDERLINE_WIDTH,
+                    ));
+                }
+            }
+            for (range, width) in underlines {
+                if let Ok(rects) = text_layout.hit_test_text_range(range, &text_origin) {
+                    for hit in rects.iter() {
+                        let underline = Rect::new(
+                            Point::new(hit.bounds.min_x(), hit.bounds.max_y() - width),
+                            Size::new(hit.bounds.size.width, width),
+                        );
+                        ctx.fill_rectangle(underline, &text_brush);
+                    }
+                }
+            }
+        }
+
+        // caret
+        let hide_caret = matches!(self.preedit, Some(Preedit { cursor: None, .. }));
+        if ctx.has_focus() && !hide_caret {
+            if let Some(caret) = self.caret_rect() {
+                ctx.fill_rectangle(caret.translate(bounds.origin.to_vector()), &text_brush);
+            }
+        }
+    }
+
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event) {
+        match event {
+            Event::PointerDown(p) => {
+                self.pointer_down(ctx, p);
+            }
+            Event::PointerMove(p) => {
+                if ctx.is_capturing_pointer() {
+                    // extend the selection while dragging
+                    let pos = self.text_position_at_point(p.position);
+                    self.move_caret(pos, true);
+                    ctx.set_handled();
+                    ctx.request_redraw();
+                }
+            }
+            Event::PointerUp(_) => {
+                if ctx.is_capturing_pointer() {
+                    ctx.release_pointer();
+                    ctx.set_handled();
+                }
+            }
+            Event::KeyDown(k) => {
+                if ctx.has_focus() {
+                    self.key_down(ctx, k);
+                }
+            }
+            Event::Input(input) => {
+                // control characters (backspace, enter, ...) are handled in KeyDown
+                if ctx.has_focus() && !input.character.is_control() {
+                    self.insert_char(input.character);
+                    self.text_changed(ctx);
+                    ctx.set_handled();
+                    ctx.request_redraw();
+                }
+            }
+            Event::Composition(composition) => {
+                if ctx.has_focus() {
+                    self.apply_composition(composition);
+                    if let CompositionEvent::Commit { .. } = composition {
+                        self.text_changed(ctx);
+                    } else {
+                        // the preedit text is not part of the contents: don't emit a change
+                        self.relayout_text();
+                        ctx.request_relayout();
+                    }
+                    ctx.set_handled();
+                    ctx.request_redraw();
+                }
+            }
+            Event::FocusIn => {
+                ctx.request_redraw();
+            }
+            Event::FocusOut => {
+                // abandon the composition in progress
+                if self.cancel_preedit() {
+                    self.relayout_text();
+                    ctx.request_relayout();
+                }
+                let text = self.text();
+                self.emit_action(TextEditAction::Committed(text));
+                ctx.request_redraw();
+                return;
+            }
+            _ => {}
+        }
+
+        // keep the candidate window of the input method next to the caret
+        if let Some(caret) = self.caret_rect() {
+            ctx.set_ime_cursor_area(caret);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kyute_shell::platform::Platform;
+    use std::sync::Once;
+
+    /// Creates a single-line editor with its text laid out.
+    fn laid_out(text: &str, reading_direction: ReadingDirection) -> TextEdit {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            Platform::init();
+        });
+        let mut edit = TextEdit::new(text).reading_direction(reading_direction);
+        edit.layout_box_size = Size::new(f32::MAX as f64, f32::MAX as f64);
+        edit.relayout_text();
+        assert!(edit.text_layout.is_some());
+        edit
+    }
+
+    #[test]
+    fn test_composition() {
+        let mut edit = TextEdit::new("ab");
+        edit.set_selection(Selection::empty(1));
+
+        edit.apply_composition(&CompositionEvent::Start);
+        assert_eq!(edit.preedit_range(), Some(1..1));
+
+        edit.apply_composition(&CompositionEvent::Preedit {
+            text: "に".to_string(),
+            cursor: Some(3..3),
+        });
+        assert_eq!(edit.text(), "ab");
+        assert_eq!(edit.preedit_range(), Some(1..4));
+        assert_eq!(edit.selection(), Selection::empty(4));
+
+        edit.apply_composition(&CompositionEvent::Preedit {
+            text: "にほん".to_string(),
+            cursor: None,
+        });
+        assert_eq!(edit.text(), "ab");
+        assert_eq!(edit.preedit_range(), Some(1..10));
+        assert_eq!(edit.selection(), Selection::empty(10));
+
+        edit.apply_composition(&CompositionEvent::Commit {
+            text: "日本".to_string(),
+        });
+        assert_eq!(edit.text(), "a日本b");
+        assert_eq!(edit.preedit_range(), None);
+        assert_eq!(edit.selection(), Selection::empty(7));
+
+        // a character typed during a composition replaces the preedit text
+        edit.apply_composition(&CompositionEvent::Preedit {
+            text: "に".to_string(),
+            cursor: None,
+        });
+        assert_eq!(edit.preedit_range(), Some(7..10));
+        edit.insert_char('x');
+        assert_eq!(edit.text(), "a日本xb");
+        assert_eq!(edit.preedit_range(), None);
+        assert_eq!(edit.selection(), Selection::empty(8));
+    }
+
+    #[test]
+    fn test_insert_and_delete() {
+        let mut edit = TextEdit::new("héllo");
+        edit.set_selection(Selection::empty(3));
+        edit.insert_str("y");
+        assert_eq!(edit.text(), "héyllo");
+        assert_eq!(edit.selection(), Selection::empty(4));
+
+        assert!(edit.delete_backward());
+        assert_eq!(edit.text(), "héllo");
+        // multi-byte character
+        assert!(edit.delete_backward());
+        assert_eq!(edit.text(), "hllo");
+        assert_eq!(edit.selection(), Selection::empty(1));
+        assert!(edit.delete_forward());
+        assert_eq!(edit.text(), "hlo");
+        assert_eq!(edit.selection(), Selection::empty(1));
+
+        // the inserted text replaces the selection
+        edit.set_selection(Selection {
+            anchor: 3,
+            active: 1,
+        });
+        edit.insert_str("ab");
+        assert_eq!(edit.text(), "hab");
+        assert_eq!(edit.selection(), Selection::empty(3));
+
+        // nothing to delete at the ends of the text
+        assert!(!edit.delete_forward());
+        edit.set_selection(Selection::empty(0));
+        assert!(!edit.delete_backward());
+        assert_eq!(edit.text(), "hab");
+    }
+
+    #[test]
+    fn test_line_navigation() {
+        let mut edit = TextEdit::new("ab\ncd\n\nef").multiline(true);
+        assert_eq!(edit.line_start(1), 0);
+        assert_eq!(edit.line_end(1), 2);
+        assert_eq!(edit.line_start(4), 3);
+        assert_eq!(edit.line_end(3), 5);
+        // empty line
+        assert_eq!(edit.line_start(6), 6);
+        assert_eq!(edit.line_end(6), 6);
+        assert_eq!(edit.line_start(9), 7);
+        assert_eq!(edit.line_end(7), 9);
+
+        // with the gap of the buffer in the middle of a line
+        edit.set_selection(Selection::empty(4));
+        edit.insert_str("x");
+        assert_eq!(edit.text(), "ab\ncxd\n\nef");
+        assert_eq!(edit.line_start(6), 3);
+        assert_eq!(edit.line_end(3), 6);
+        assert_eq!(edit.line_start(10), 8);
+    }
+
+    #[test]
+    fn test_composition_cancel() {
+        let mut edit = TextEdit::new("hello");
+        // the composition replaces the selection
+        edit.set_selection(Selection {
+            anchor: 1,
+            active: 4,
+        });
+        edit.apply_composition(&CompositionEvent::Preedit {
+            text: "xy".to_string(),
+            cursor: Some(0..1),
+        });
+        assert_eq!(edit.text(), "ho");
+        assert_eq!(edit.preedit_range(), Some(1..3));
+        assert_eq!(edit.selection(), Selection::empty(2));
+
+        edit.apply_composition(&CompositionEvent::Cancel);
+        assert_eq!(edit.text(), "ho");
+        assert_eq!(edit.preedit_range(), None);
+        assert_eq!(edit.selection(), Selection::empty(1));
+    }
+
+    #[test]
+    fn test_visual_caret_move() {
+        use VisualDirection::{Left, Right};
+
+        let edit = laid_out("abc", ReadingDirection::LeftToRight);
+        assert_eq!(edit.visual_caret_move(0, Right), 1);
+        assert_eq!(edit.visual_caret_move(2, Left), 1);
+        // no position further on the line: logical fallback, clamped to the text
+        assert_eq!(edit.visual_caret_move(3, Right), 3);
+        assert_eq!(edit.visual_caret_move(0, Left), 0);
+
+        // right-to-left run in a left-to-right paragraph: "ab " then "גבא" on screen
+        let edit = laid_out("ab אבג", ReadingDirection::LeftToRight);
+        assert_eq!(edit.visual_caret_move(1, Left), 0);
+        assert_eq!(edit.visual_caret_move(0, Right), 1);
+        // inside the run, moving left goes forward in the text
+        assert_eq!(edit.visual_caret_move(5, Left), 7);
+        assert_eq!(edit.visual_caret_move(7, Right), 5);
+
+        // right-to-left paragraph: position 0 is on the right edge
+        let edit = laid_out("אבג", ReadingDirection::RightToLeft);
+        assert_eq!(edit.visual_caret_move(0, Left), 2);
+        assert_eq!(edit.visual_caret_move(4, Right), 2);
+        // at the ends of the line, the fallback follows the reading direction
+        assert_eq!(edit.visual_caret_move(0, Right), 0);
+        assert_eq!(edit.visual_caret_move(6, Left), 6);
+    }
+}